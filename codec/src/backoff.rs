@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+/// Exponential retry delays, for reconnects and for depth snapshot
+/// refetches: 500ms doubling up to 30s, then every 30s for as long as it
+/// takes. Past `MAX_ATTEMPTS` consecutive failures it counts as failed,
+/// though callers keep trying.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    const BASE_MS: u64 = 500;
+    const MAX_MS: u64 = 30_000;
+    const MAX_ATTEMPTS: u32 = 12;
+
+    pub fn next_delay(&mut self) -> Duration {
+        let ms = (Self::BASE_MS << self.attempt.min(16)).min(Self::MAX_MS);
+        self.attempt = self.attempt.saturating_add(1);
+        Duration::from_millis(ms)
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.attempt > Self::MAX_ATTEMPTS
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn is_retrying(&self) -> bool {
+        self.attempt > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_caps_and_resets() {
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1000));
+        for _ in 0..20 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), Duration::from_millis(30_000));
+        assert!(backoff.has_failed());
+        backoff.reset();
+        assert!(!backoff.is_retrying());
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,465 @@
+//! The `.lbp` record format shared by the web app's recordings and the
+//! history server's segment files and range queries, plus the retry
+//! `Backoff` both sides dial with.
+
+mod backoff;
+
+pub use backoff::Backoff;
+
+use anyhow::{anyhow, bail};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+pub type Time = u64;
+
+const MAGIC: &[u8; 4] = b"LBPR";
+/// The only layout read or written.
+const VERSION: u8 = 2;
+
+//MARK: - Records ---------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderBookUpdate {
+    pub ts: Time,    // timestamp
+    pub sq: u64,     // sequence
+    pub side: Side,  // bid or offer
+    pub px: Decimal, // price, exactly as quoted
+    pub sz: Decimal, // resting size, zero removes the level
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Kline {
+    pub ts: Time, // open time
+    pub op: f64,
+    pub hi: f64,
+    pub lo: f64,
+    pub cl: f64,
+    pub vo: f64,  // volume total
+    pub vb: f64,  // volume buy, NaN where the venue has no taker split
+    pub vs: f64,  // volume sell, NaN where the venue has no taker split
+    pub tc: Time, // close time
+}
+
+impl Kline {
+    /// Extend this candle with the one that follows it.
+    pub fn merge(&mut self, next: &Kline) {
+        self.hi = self.hi.max(next.hi);
+        self.lo = self.lo.min(next.lo);
+        self.cl = next.cl;
+        self.vo += next.vo;
+        self.vb += next.vb;
+        self.vs += next.vs;
+        self.tc = self.tc.max(next.tc);
+    }
+}
+
+/// One execution, or several at one price aggregated by the venue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub ts: Time,
+    pub px: Decimal,
+    pub sz: Decimal,
+    /// The taker: `Bid` when a buyer lifted the offer, `Ask` when a seller
+    /// hit the bid.
+    pub side: Side,
+}
+
+/// One entry, stored after its tag byte and receive time.
+///
+/// `Reset` only appears in the server's segment files: it starts every book
+/// image and is resolved into zero updates before anything goes over the
+/// wire. `Gap` marks where the book fell out of sequence; the levels it
+/// showed are stale until the next image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Record {
+    Update(OrderBookUpdate),
+    Kline(Kline),
+    Reset,
+    Gap,
+    Trade(Trade),
+}
+
+impl Record {
+    fn tag(&self) -> u8 {
+        match self {
+            Record::Update(_) => 0,
+            Record::Kline(_) => 1,
+            Record::Reset => 2,
+            Record::Gap => 3,
+            Record::Trade(_) => 4,
+        }
+    }
+}
+
+//MARK: - Encoding ---------------------------------------------
+
+/// Binary layout: magic, version, venue id and symbol, then one record per
+/// entry. Times are varint deltas (receive time against the previous
+/// entry, event time against receive time), book and trade prices and
+/// sizes exact decimals (scale byte and zigzag varint mantissa), kline
+/// numbers little-endian f64.
+pub fn put_header(buf: &mut Vec<u8>, venue: &str, symbol: &str) {
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    put_str(buf, venue);
+    put_str(buf, symbol);
+}
+
+/// Append one record; receive times are deltas against `last_rx`, which
+/// must not go backwards.
+pub fn put_record(buf: &mut Vec<u8>, last_rx: &mut Time, rx: Time, record: &Record) {
+    let rx = rx.max(*last_rx);
+    buf.push(record.tag());
+    put_varint(buf, rx - *last_rx);
+    *last_rx = rx;
+    match record {
+        Record::Update(u) => {
+            put_zigzag(buf, u.ts as i64 - rx as i64);
+            put_varint(buf, u.sq);
+            put_side(buf, u.side);
+            put_decimal(buf, u.px);
+            put_decimal(buf, u.sz);
+        }
+        Record::Kline(k) => {
+            put_zigzag(buf, k.ts as i64 - rx as i64);
+            put_varint(buf, k.tc - k.ts);
+            for v in [k.op, k.hi, k.lo, k.cl, k.vo, k.vb, k.vs] {
+                put_f64(buf, v);
+            }
+        }
+        Record::Trade(t) => {
+            put_zigzag(buf, t.ts as i64 - rx as i64);
+            put_side(buf, t.side);
+            put_decimal(buf, t.px);
+            put_decimal(buf, t.sz);
+        }
+        Record::Reset | Record::Gap => {}
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_zigzag(buf: &mut Vec<u8>, v: i64) {
+    put_varint(buf, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+fn put_side(buf: &mut Vec<u8>, side: Side) {
+    buf.push(match side {
+        Side::Bid => 0,
+        Side::Ask => 1,
+    });
+}
+
+fn put_f64(buf: &mut Vec<u8>, v: f64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_decimal(buf: &mut Vec<u8>, v: Decimal) {
+    let v = v.normalize();
+    buf.push(v.scale() as u8);
+    let m = v.mantissa();
+    let mut z = ((m << 1) ^ (m >> 127)) as u128;
+    while z >= 0x80 {
+        buf.push(z as u8 | 0x80);
+        z >>= 7;
+    }
+    buf.push(z as u8);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+//MARK: - Decoding ---------------------------------------------
+
+/// Streams records out of a recording or segment file.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    rx: Time,
+    venue: String,
+    symbol: String,
+    truncated: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        let mut r = Self {
+            bytes,
+            pos: 0,
+            rx: 0,
+            venue: String::new(),
+            symbol: String::new(),
+            truncated: false,
+        };
+        if r.take(4)? != MAGIC {
+            bail!("not an lbplot recording");
+        }
+        let version = r.take(1)?[0];
+        if version != VERSION {
+            bail!("unsupported recording version {}", version);
+        }
+        r.venue = r.str()?;
+        r.symbol = r.str()?;
+        Ok(r)
+    }
+
+    pub fn venue(&self) -> &str {
+        &self.venue
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Next record and its receive time. A record cut short at the end of
+    /// the file (still being written) ends the stream like EOF does.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<(Time, Record)>> {
+        if self.pos >= self.bytes.len() {
+            return Ok(None);
+        }
+        match self.record() {
+            Ok(record) => Ok(Some(record)),
+            Err(_) if self.truncated => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn record(&mut self) -> anyhow::Result<(Time, Record)> {
+        let tag = self.take(1)?[0];
+        self.rx += self.varint()?;
+        let rx = self.rx;
+        let record = match tag {
+            0 => {
+                let ts = (rx as i64 + self.zigzag()?) as Time;
+                Record::Update(OrderBookUpdate {
+                    ts,
+                    sq: self.varint()?,
+                    side: self.side()?,
+                    px: self.decimal()?,
+                    sz: self.decimal()?,
+                })
+            }
+            1 => {
+                let ts = (rx as i64 + self.zigzag()?) as Time;
+                let tc = ts + self.varint()?;
+                Record::Kline(Kline {
+                    ts,
+                    op: self.f64()?,
+                    hi: self.f64()?,
+                    lo: self.f64()?,
+                    cl: self.f64()?,
+                    vo: self.f64()?,
+                    vb: self.f64()?,
+                    vs: self.f64()?,
+                    tc,
+                })
+            }
+            2 => Record::Reset,
+            3 => Record::Gap,
+            4 => {
+                let ts = (rx as i64 + self.zigzag()?) as Time;
+                Record::Trade(Trade {
+                    ts,
+                    side: self.side()?,
+                    px: self.decimal()?,
+                    sz: self.decimal()?,
+                })
+            }
+            _ => bail!("corrupt recording: record tag {} at {}", tag, self.pos),
+        };
+        Ok((rx, record))
+    }
+
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.pos + n;
+        let slice = self.bytes.get(self.pos..end).ok_or_else(|| {
+            self.truncated = true;
+            anyhow!("truncated recording at {}", self.pos)
+        })?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        bail!("corrupt recording: varint overflow at {}", self.pos)
+    }
+
+    fn zigzag(&mut self) -> anyhow::Result<i64> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    fn side(&mut self) -> anyhow::Result<Side> {
+        match self.take(1)?[0] {
+            0 => Ok(Side::Bid),
+            1 => Ok(Side::Ask),
+            b => bail!("corrupt recording: side {} at {}", b, self.pos),
+        }
+    }
+
+    fn decimal(&mut self) -> anyhow::Result<Decimal> {
+        let scale = self.take(1)?[0] as u32;
+        let mut z = 0u128;
+        for shift in (0..128).step_by(7) {
+            let b = self.take(1)?[0];
+            z |= ((b & 0x7f) as u128) << shift;
+            if b & 0x80 == 0 {
+                let m = (z >> 1) as i128 ^ -((z & 1) as i128);
+                return Decimal::try_from_i128_with_scale(m, scale)
+                    .map_err(|e| anyhow!("corrupt recording: {} at {}", e, self.pos));
+            }
+        }
+        bail!("corrupt recording: decimal overflow at {}", self.pos)
+    }
+
+    fn f64(&mut self) -> anyhow::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn str(&mut self) -> anyhow::Result<String> {
+        let len = self.varint()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn sample() -> Vec<(Time, Record)> {
+        vec![
+            (1_000, Record::Reset),
+            (
+                1_000,
+                Record::Update(OrderBookUpdate {
+                    ts: 998,
+                    sq: 42,
+                    side: Side::Bid,
+                    px: dec("64123.45"),
+                    sz: dec("0.00012000"),
+                }),
+            ),
+            (
+                1_250,
+                Record::Update(OrderBookUpdate {
+                    ts: 1_300,
+                    sq: 43,
+                    side: Side::Ask,
+                    px: dec("0.000000012345"),
+                    sz: Decimal::ZERO,
+                }),
+            ),
+            (
+                2_000,
+                Record::Kline(Kline {
+                    ts: 1_000,
+                    op: 1.5,
+                    hi: 2.25,
+                    lo: 1.0,
+                    cl: 2.0,
+                    vo: 10.0,
+                    vb: 6.0,
+                    vs: 4.0,
+                    tc: 1_999,
+                }),
+            ),
+            (
+                2_100,
+                Record::Trade(Trade {
+                    ts: 2_099,
+                    px: dec("-3.5"),
+                    sz: dec("79228162514264337593543950335"),
+                    side: Side::Ask,
+                }),
+            ),
+            (3_000, Record::Gap),
+        ]
+    }
+
+    fn encode(records: &[(Time, Record)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_header(&mut buf, "binance", "BTCUSDT");
+        let mut last_rx = 0;
+        for (rx, record) in records {
+            put_record(&mut buf, &mut last_rx, *rx, record);
+        }
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<(Time, Record)> {
+        let mut reader = Reader::new(bytes).unwrap();
+        let mut records = Vec::new();
+        while let Some(entry) = reader.next_record().unwrap() {
+            records.push(entry);
+        }
+        records
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = sample();
+        let bytes = encode(&records);
+        let reader = Reader::new(&bytes).unwrap();
+        assert_eq!(reader.venue(), "binance");
+        assert_eq!(reader.symbol(), "BTCUSDT");
+        assert_eq!(decode(&bytes), records);
+    }
+
+    #[test]
+    fn receive_times_never_go_backwards() {
+        let records = vec![(500, Record::Gap), (400, Record::Gap)];
+        let decoded = decode(&encode(&records));
+        assert_eq!(decoded, vec![(500, Record::Gap), (500, Record::Gap)]);
+    }
+
+    #[test]
+    fn truncated_tail_ends_the_stream() {
+        let records = sample();
+        let bytes = encode(&records);
+        let decoded = decode(&bytes[..bytes.len() - 5]);
+        // the cut lands in the trade, the gap after it is lost with it
+        assert_eq!(decoded, records[..4]);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = encode(&sample());
+        bytes[4] = 1;
+        assert!(Reader::new(&bytes).is_err());
+        assert!(Reader::new(b"nope").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_tags() {
+        let mut bytes = encode(&[]);
+        bytes.extend_from_slice(&[9, 0]);
+        let mut reader = Reader::new(&bytes).unwrap();
+        assert!(reader.next_record().is_err());
+    }
+}
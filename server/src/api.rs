@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use codec::Time;
+use serde::{Deserialize, Serialize};
+use tower_http::cors::CorsLayer;
+
+use crate::store::Store;
+
+/// Longest range a single history request may cover.
+const MAX_RANGE_MS: Time = 24 * 60 * 60 * 1000;
+
+#[derive(Clone)]
+struct AppState {
+    store: Store,
+    symbols: Vec<String>,
+}
+
+/// `GET /api/symbols` lists what is being recorded and since when,
+/// `GET /api/history` serves a time range as a web app recording.
+pub fn router(store: Store, symbols: Vec<String>) -> Router {
+    Router::new()
+        .route("/api/symbols", get(list_symbols))
+        .route("/api/history", get(history))
+        // the web app is served from elsewhere, usually `trunk serve`
+        .layer(CorsLayer::permissive())
+        .with_state(AppState { store, symbols })
+}
+
+#[derive(Serialize)]
+struct SymbolInfo {
+    venue: &'static str,
+    symbol: String,
+    /// Start of the oldest segment on disk.
+    since: Option<Time>,
+}
+
+async fn list_symbols(State(state): State<AppState>) -> Result<Json<Vec<SymbolInfo>>, ApiError> {
+    let mut infos = Vec::new();
+    for symbol in &state.symbols {
+        infos.push(SymbolInfo {
+            venue: state.store.venue(),
+            symbol: symbol.clone(),
+            since: state.store.segments(symbol)?.first().copied(),
+        });
+    }
+    Ok(Json(infos))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    venue: Option<String>,
+    symbol: String,
+    from: Time,
+    to: Option<Time>,
+}
+
+async fn history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response, ApiError> {
+    let symbol = query.symbol.to_uppercase();
+    if query
+        .venue
+        .as_deref()
+        .is_some_and(|v| v != state.store.venue())
+        || !state.symbols.contains(&symbol)
+    {
+        return Err(ApiError::NotFound(format!("{} is not recorded", symbol)));
+    }
+    let to = query.to.unwrap_or(query.from.saturating_add(MAX_RANGE_MS));
+    if to < query.from || to - query.from > MAX_RANGE_MS {
+        return Err(ApiError::BadRequest(format!(
+            "range must be 0..{}ms",
+            MAX_RANGE_MS
+        )));
+    }
+
+    let store = state.store.clone();
+    let from = query.from;
+    let body = tokio::task::spawn_blocking(move || store.history(&symbol, from, to)).await??;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], body).into_response())
+}
+
+//MARK: - Errors ---------------------------------------------
+
+enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError::Internal(e.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+            ApiError::Internal(e) => {
+                tracing::error!("🚫 [api] {:#}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        }
+    }
+}
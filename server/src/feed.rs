@@ -0,0 +1,437 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use codec::{Backoff, Kline, OrderBookUpdate, Record, Side, Time, Trade};
+use futures_util::StreamExt;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use tokio::time::{sleep, timeout, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::store::{SegmentWriter, Store};
+
+/// No message for this long drops the connection and dials again.
+const RECONNECT_AFTER: Duration = Duration::from_secs(15);
+
+//MARK: - Local Book ---------------------------------------------
+
+struct DepthUpdate {
+    first_id: u64,
+    final_id: u64,
+    levels: Vec<OrderBookUpdate>,
+}
+
+struct DepthSnapshot {
+    last_update_id: u64,
+    levels: Vec<OrderBookUpdate>,
+}
+
+/// Binance local order book: levels keyed by side and price.
+#[derive(Default)]
+struct Book {
+    last_update_id: Option<u64>,
+    buffered: VecDeque<DepthUpdate>,
+    levels: HashMap<(Side, Decimal), OrderBookUpdate>,
+}
+
+impl Book {
+    fn image(&self, ts: Time) -> Vec<OrderBookUpdate> {
+        self.levels
+            .values()
+            .map(|l| OrderBookUpdate { ts, ..l.clone() })
+            .collect()
+    }
+
+    fn set(&mut self, level: &OrderBookUpdate) {
+        let key = (level.side, level.px);
+        if level.sz.is_zero() {
+            self.levels.remove(&key);
+        } else {
+            self.levels.insert(key, level.clone());
+        }
+    }
+
+    /// Apply an in-sequence diff, returning its levels. `None` means the
+    /// sequence broke and the book needs a fresh snapshot.
+    fn apply(&mut self, diff: DepthUpdate) -> Option<Vec<OrderBookUpdate>> {
+        let last = self.last_update_id?;
+        if diff.final_id <= last {
+            return Some(Vec::new());
+        }
+        if diff.first_id > last + 1 {
+            tracing::warn!(
+                "🕳 [depth] gap: expected {} got {}..{}",
+                last + 1,
+                diff.first_id,
+                diff.final_id
+            );
+            // the diff and anything buffered behind it wait for the next snapshot
+            self.last_update_id = None;
+            self.buffered.push_front(diff);
+            return None;
+        }
+        for level in &diff.levels {
+            self.set(level);
+        }
+        self.last_update_id = Some(diff.final_id);
+        Some(diff.levels)
+    }
+}
+
+//MARK: - Binance Spot Recorder ---------------------------------------------
+
+/// Record `symbol` from Binance Spot into `store` forever, reconnecting and
+/// resyncing as needed.
+pub async fn run(symbol: String, store: Store, retain: Option<Duration>) {
+    let mut writer = SegmentWriter::new(store.clone(), symbol.clone());
+    let mut backoff = Backoff::default();
+    loop {
+        match session(&symbol, &mut writer, &mut backoff, &store, retain).await {
+            Ok(()) => tracing::warn!("🔌 [ws] {} closed", symbol),
+            Err(e) => tracing::warn!("🚫 [ws] {} {:#}", symbol, e),
+        }
+        // diffs are lost while disconnected, the next sync starts a segment
+        if let Err(e) = writer.gap(now()) {
+            tracing::warn!("🚫 [store] {} {:#}", symbol, e);
+        }
+        let delay = backoff.next_delay();
+        tracing::info!("🔌 [ws] {} reconnecting in {:?}", symbol, delay);
+        sleep(delay).await;
+    }
+}
+
+async fn session(
+    symbol: &str,
+    writer: &mut SegmentWriter,
+    backoff: &mut Backoff,
+    store: &Store,
+    retain: Option<Duration>,
+) -> anyhow::Result<()> {
+    let lower = symbol.to_lowercase();
+    let url = format!(
+        "wss://data-stream.binance.vision/stream?streams={}@depth@100ms/{}@kline_1s/{}@aggTrade",
+        lower, lower, lower
+    );
+    let (mut ws, _) = connect_async(url.as_str()).await?;
+    tracing::info!("🔌 [ws] {} live", symbol);
+
+    let mut book = Book::default();
+    // snapshots are heavy on the rate limit, refetch no faster than this
+    let mut snapshots = Backoff::default();
+    let mut sync_at = Instant::now();
+    loop {
+        let msg = timeout(RECONNECT_AFTER, ws.next())
+            .await
+            .map_err(|_| anyhow!("silent for {:?}", RECONNECT_AFTER))?;
+        let text = match msg {
+            None => return Ok(()),
+            Some(msg) => match msg? {
+                Message::Text(text) => text,
+                Message::Close(_) => return Ok(()),
+                // pings are answered by tungstenite itself
+                _ => continue,
+            },
+        };
+        backoff.reset();
+        let rx = now();
+
+        // a malformed frame only loses itself, the session carries on
+        let msg: Value = match serde_json::from_str(&text) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::warn!("🚫 [ws] {} parse {} {}", symbol, e, text);
+                continue;
+            }
+        };
+        match msg["stream"].as_str() {
+            Some(stream) if stream.contains("kline") => {
+                if let Some(kline) = parse_kline(&msg["data"]["k"]) {
+                    writer.append(rx, [Record::Kline(kline)])?;
+                }
+            }
+            Some(stream) if stream.contains("aggTrade") => match parse_trade(&msg["data"]) {
+                Ok(trade) => writer.append(rx, [Record::Trade(trade)])?,
+                Err(e) => tracing::warn!("🚫 [ws] {} parse {:#} {}", symbol, e, text),
+            },
+            Some(stream) if stream.contains("depth") => {
+                let diff = match parse_depth_update(&msg["data"]) {
+                    Ok(diff) => diff,
+                    Err(e) => {
+                        tracing::warn!("🚫 [ws] {} parse {:#} {}", symbol, e, text);
+                        continue;
+                    }
+                };
+                if book.last_update_id.is_none() {
+                    book.buffered.push_back(diff);
+                    if Instant::now() >= sync_at {
+                        if sync(symbol, &mut book, writer, rx).await? {
+                            snapshots.reset();
+                        } else {
+                            sync_at = Instant::now() + snapshots.next_delay();
+                        }
+                    }
+                } else if let Some(levels) = book.apply(diff) {
+                    writer.append(rx, levels.into_iter().map(Record::Update))?;
+                } else {
+                    // out of sequence, nothing is written until the next sync
+                    writer.gap(rx)?;
+                }
+            }
+            _ => continue,
+        }
+
+        if writer.is_due(rx) {
+            writer.reset(rx, book.image(rx))?;
+            if let Some(retain) = retain {
+                let before = rx.saturating_sub(retain.as_millis() as Time);
+                store.prune(symbol, before)?;
+            }
+        }
+    }
+}
+
+/// Fetch a REST snapshot covering the buffered diffs and replay them on top.
+/// `false` when the book is still out of sync: the fetch failed or the
+/// snapshot is older than the stream, and the caller retries later.
+async fn sync(
+    symbol: &str,
+    book: &mut Book,
+    writer: &mut SegmentWriter,
+    rx: Time,
+) -> anyhow::Result<bool> {
+    let first_id = book.buffered.front().map(|d| d.first_id).unwrap_or(0);
+    let snapshot = match fetch_snapshot(symbol).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            tracing::warn!("🚫 [rest] {} {:#}", symbol, e);
+            return Ok(false);
+        }
+    };
+    if snapshot.last_update_id < first_id {
+        tracing::info!(
+            "⏳ [rest] {} depth snapshot {} behind stream {}",
+            symbol,
+            snapshot.last_update_id,
+            first_id
+        );
+        return Ok(false);
+    }
+    tracing::info!(
+        "📚 [rest] {} depth snapshot lastUpdateId {} levels {}",
+        symbol,
+        snapshot.last_update_id,
+        snapshot.levels.len()
+    );
+
+    book.levels.clear();
+    for level in &snapshot.levels {
+        book.set(level);
+    }
+    book.last_update_id = Some(snapshot.last_update_id);
+    writer.reset(rx, book.image(rx))?;
+
+    while let Some(diff) = book.buffered.pop_front() {
+        match book.apply(diff) {
+            Some(levels) => writer.append(rx, levels.into_iter().map(Record::Update))?,
+            None => {
+                writer.gap(rx)?;
+                break;
+            }
+        }
+    }
+    Ok(book.last_update_id.is_some())
+}
+
+async fn fetch_snapshot(symbol: &str) -> anyhow::Result<DepthSnapshot> {
+    let url = format!(
+        "https://data-api.binance.vision/api/v3/depth?symbol={}&limit=1000",
+        symbol.to_uppercase()
+    );
+    let resp = reqwest::get(&url).await?;
+    if !resp.status().is_success() {
+        bail!("fetch {}: HTTP {}", url, resp.status());
+    }
+    let msg: Value = serde_json::from_str(&resp.text().await?)?;
+    let last_update_id = msg["lastUpdateId"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("depth snapshot without lastUpdateId"))?;
+    let ts = now();
+    let mut levels = parse_levels(&msg["bids"], ts, last_update_id, Side::Bid)?;
+    levels.extend(parse_levels(&msg["asks"], ts, last_update_id, Side::Ask)?);
+    Ok(DepthSnapshot {
+        last_update_id,
+        levels,
+    })
+}
+
+//MARK: - Parsing ---------------------------------------------
+
+fn parse_levels(v: &Value, ts: Time, sq: u64, side: Side) -> anyhow::Result<Vec<OrderBookUpdate>> {
+    let levels = v.as_array().ok_or_else(|| anyhow!("levels: {}", v))?;
+    levels
+        .iter()
+        .map(|l| {
+            let num = |i: usize| -> anyhow::Result<Decimal> {
+                let s = l[i].as_str().ok_or_else(|| anyhow!("level: {}", l))?;
+                Ok(s.parse()?)
+            };
+            Ok(OrderBookUpdate {
+                ts,
+                sq,
+                side,
+                px: num(0)?,
+                sz: num(1)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_depth_update(data: &Value) -> anyhow::Result<DepthUpdate> {
+    let id = |k: &str| {
+        data[k]
+            .as_u64()
+            .ok_or_else(|| anyhow!("depth without {}", k))
+    };
+    let ts = id("E")?;
+    let final_id = id("u")?;
+    let mut levels = parse_levels(&data["b"], ts, final_id, Side::Bid)?;
+    levels.extend(parse_levels(&data["a"], ts, final_id, Side::Ask)?);
+    Ok(DepthUpdate {
+        first_id: id("U")?,
+        final_id,
+        levels,
+    })
+}
+
+fn parse_trade(data: &Value) -> anyhow::Result<Trade> {
+    let num = |k: &str| -> anyhow::Result<Decimal> {
+        let s = data[k]
+            .as_str()
+            .ok_or_else(|| anyhow!("trade without {}", k))?;
+        Ok(s.parse()?)
+    };
+    Ok(Trade {
+        ts: data["T"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("trade without T"))?,
+        px: num("p")?,
+        sz: num("q")?,
+        // `m`: the buyer was the maker, so a seller took
+        side: if data["m"].as_bool().unwrap_or(false) {
+            Side::Ask
+        } else {
+            Side::Bid
+        },
+    })
+}
+
+fn parse_kline(k: &Value) -> Option<Kline> {
+    let num = |key: &str| k[key].as_str()?.parse::<f64>().ok();
+    let vo = num("v")?;
+    let vb = num("V")?;
+    Some(Kline {
+        ts: k["t"].as_u64()?,
+        op: num("o")?,
+        hi: num("h")?,
+        lo: num("l")?,
+        cl: num("c")?,
+        vo,
+        vb,
+        vs: vo - vb,
+        tc: k["T"].as_u64()?,
+    })
+}
+
+fn now() -> Time {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as Time
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(side: Side, px: &str, sz: &str) -> OrderBookUpdate {
+        OrderBookUpdate {
+            ts: 0,
+            sq: 0,
+            side,
+            px: px.parse().unwrap(),
+            sz: sz.parse().unwrap(),
+        }
+    }
+
+    fn diff(first_id: u64, final_id: u64, levels: Vec<OrderBookUpdate>) -> DepthUpdate {
+        DepthUpdate {
+            first_id,
+            final_id,
+            levels,
+        }
+    }
+
+    fn synced(last_update_id: u64) -> Book {
+        let mut book = Book {
+            last_update_id: Some(last_update_id),
+            ..Default::default()
+        };
+        book.set(&level(Side::Bid, "100", "1"));
+        book.set(&level(Side::Ask, "101", "2"));
+        book
+    }
+
+    #[test]
+    fn apply_skips_diffs_the_snapshot_covers() {
+        let mut book = synced(10);
+        let applied = book.apply(diff(5, 10, vec![level(Side::Bid, "100", "0")]));
+        assert_eq!(applied.map(|l| l.len()), Some(0));
+        assert_eq!(book.levels.len(), 2);
+    }
+
+    #[test]
+    fn apply_sets_and_removes_levels() {
+        let mut book = synced(10);
+        let levels = vec![level(Side::Bid, "100", "0"), level(Side::Bid, "99.5", "3")];
+        let applied = book.apply(diff(8, 12, levels)).unwrap();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(book.last_update_id, Some(12));
+        let bid = Decimal::new(995, 1);
+        assert!(!book.levels.contains_key(&(Side::Bid, Decimal::from(100))));
+        assert_eq!(book.levels[&(Side::Bid, bid)].sz, Decimal::from(3));
+    }
+
+    #[test]
+    fn apply_unsyncs_on_a_gap() {
+        let mut book = synced(10);
+        assert!(book.apply(diff(12, 13, Vec::new())).is_none());
+        assert_eq!(book.last_update_id, None);
+        // the diff that broke the sequence waits for the next snapshot
+        assert_eq!(book.buffered.len(), 1);
+        assert!(book.apply(diff(14, 15, Vec::new())).is_none());
+    }
+
+    #[test]
+    fn apply_keeps_the_buffer_behind_a_gap() {
+        let mut book = synced(10);
+        book.buffered.push_back(diff(14, 15, Vec::new()));
+        assert!(book.apply(diff(12, 13, Vec::new())).is_none());
+        let buffered: Vec<_> = book.buffered.iter().map(|d| d.first_id).collect();
+        assert_eq!(buffered, vec![12, 14]);
+    }
+
+    #[test]
+    fn parse_trade_takes_the_taker_side() {
+        let data: Value = serde_json::from_str(
+            r#"{"e":"aggTrade","p":"65000.10","q":"0.00100000","T":1000,"m":true}"#,
+        )
+        .unwrap();
+        let trade = parse_trade(&data).unwrap();
+        assert_eq!(trade.ts, 1000);
+        assert_eq!(trade.px, "65000.10".parse::<Decimal>().unwrap());
+        assert_eq!(trade.sz, "0.001".parse::<Decimal>().unwrap());
+        assert_eq!(trade.side, Side::Ask);
+        assert!(parse_trade(&Value::Null).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+mod api;
+mod feed;
+mod store;
+
+/// Records Binance Spot order books around the clock and serves them back to
+/// the web app as history.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Symbols to record, e.g. `-s BTCUSDT -s ETHUSDT`.
+    #[arg(short, long = "symbol", default_value = "BTCUSDT")]
+    symbols: Vec<String>,
+
+    /// Where segment files are kept.
+    #[arg(short, long, default_value = "data")]
+    data_dir: PathBuf,
+
+    #[arg(short, long, default_value = "127.0.0.1:8787")]
+    listen: SocketAddr,
+
+    /// Delete segments older than this many hours, 0 keeps everything.
+    #[arg(short, long, default_value_t = 24 * 7)]
+    retain_hours: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let symbols: Vec<String> = args.symbols.iter().map(|s| s.to_uppercase()).collect();
+    let store = store::Store::new(args.data_dir, "binance");
+    let retain = (args.retain_hours > 0).then(|| Duration::from_secs(args.retain_hours * 3600));
+    for symbol in &symbols {
+        tokio::spawn(feed::run(symbol.clone(), store.clone(), retain));
+    }
+
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    tracing::info!("🌐 [api] listening on http://{}", args.listen);
+    axum::serve(listener, api::router(store, symbols)).await?;
+    Ok(())
+}
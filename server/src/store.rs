@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use codec::{OrderBookUpdate, Reader, Record, Side, Time};
+use rust_decimal::Decimal;
+
+/// A new segment file is started at least this often, so range queries can
+/// skip most of the history and retention can drop whole files.
+const SEGMENT_MS: Time = 60 * 60 * 1000;
+
+//MARK: - Store ---------------------------------------------
+
+/// Append-only segment files under `<root>/<venue>/<SYMBOL>/<start ms>.lbp`.
+///
+/// Every segment opens with a `Reset` and a full book image, followed by the
+/// diffs and klines received after it, so any segment can be read without
+/// the ones before it. A segment cut short by a sequence gap or a dropped
+/// connection ends with a `Gap`.
+#[derive(Debug, Clone)]
+pub struct Store {
+    root: PathBuf,
+    venue: &'static str,
+}
+
+impl Store {
+    pub fn new(root: PathBuf, venue: &'static str) -> Self {
+        Self { root, venue }
+    }
+
+    pub fn venue(&self) -> &'static str {
+        self.venue
+    }
+
+    fn dir(&self, symbol: &str) -> PathBuf {
+        self.root.join(self.venue).join(symbol)
+    }
+
+    /// Start times of the segments on disk for `symbol`, oldest first.
+    pub fn segments(&self, symbol: &str) -> io::Result<Vec<Time>> {
+        let mut starts = Vec::new();
+        let entries = match fs::read_dir(self.dir(symbol)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(starts),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "lbp") {
+                if let Some(start) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                    starts.push(start);
+                }
+            }
+        }
+        starts.sort_unstable();
+        Ok(starts)
+    }
+
+    fn segment_path(&self, symbol: &str, start: Time) -> PathBuf {
+        self.dir(symbol).join(format!("{}.lbp", start))
+    }
+
+    /// Delete segments that ended before `before`.
+    pub fn prune(&self, symbol: &str, before: Time) -> io::Result<()> {
+        let starts = self.segments(symbol)?;
+        // a segment ends where the next one starts
+        for pair in starts.windows(2) {
+            if pair[1] < before {
+                fs::remove_file(self.segment_path(symbol, pair[0]))?;
+                tracing::info!("🗑 [store] {} pruned segment {}", symbol, pair[0]);
+            }
+        }
+        Ok(())
+    }
+
+    //MARK: - Range Queries ---------------------------------------------
+
+    /// Book and klines for `symbol` between `from` and `to`, encoded as a web
+    /// app recording: the book as it stood at `from`, then every change up to
+    /// `to`, keeping only the last change per price level and kline each
+    /// second.
+    pub fn history(&self, symbol: &str, from: Time, to: Time) -> anyhow::Result<Vec<u8>> {
+        let starts = self.segments(symbol)?;
+        let first = starts.iter().rposition(|&s| s <= from).unwrap_or(0);
+
+        let mut out = History::new(self.venue, symbol, from);
+        'segments: for &start in starts.iter().skip(first).take_while(|&&s| s <= to) {
+            let bytes = fs::read(self.segment_path(symbol, start))?;
+            let mut reader = Reader::new(&bytes)?;
+            while let Some((rx, record)) = reader.next_record()? {
+                if rx > to {
+                    break 'segments;
+                }
+                out.push(rx, record);
+            }
+        }
+        Ok(out.finish())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Level(Side, Decimal),
+    Kline(Time),
+    /// Never folded: trades and gaps.
+    Unique(u64),
+}
+
+/// Folds segment records into the wire format, see `Store::history`.
+struct History {
+    buf: Vec<u8>,
+    last_rx: Time,
+    from: Time,
+    started: bool,
+    book: HashMap<(Side, Decimal), OrderBookUpdate>,
+    second: Time,
+    pending: HashMap<Key, (Time, Record)>,
+    unique: u64,
+}
+
+impl History {
+    fn new(venue: &str, symbol: &str, from: Time) -> Self {
+        let mut buf = Vec::new();
+        codec::put_header(&mut buf, venue, symbol);
+        Self {
+            buf,
+            last_rx: 0,
+            from,
+            started: false,
+            book: HashMap::new(),
+            second: 0,
+            pending: HashMap::new(),
+            unique: 0,
+        }
+    }
+
+    fn push(&mut self, rx: Time, record: Record) {
+        if !self.started && rx >= self.from {
+            // the book as it stood at `from`
+            self.started = true;
+            self.second = self.from / 1000;
+            for (&(side, px), level) in &self.book {
+                let mut level = level.clone();
+                level.ts = self.from;
+                let entry = (self.from, Record::Update(level));
+                self.pending.insert(Key::Level(side, px), entry);
+            }
+        }
+
+        match record {
+            // a fresh image follows, anything it doesn't repeat is gone
+            Record::Reset => self.clear(rx),
+            // nothing is known about the book until the next image
+            Record::Gap => {
+                self.clear(rx);
+                if self.started {
+                    self.stage_unique(rx, Record::Gap);
+                }
+            }
+            Record::Update(update) => {
+                let key = (update.side, update.px);
+                if update.sz.is_zero() {
+                    self.book.remove(&key);
+                } else {
+                    self.book.insert(key, update.clone());
+                }
+                if self.started {
+                    self.stage(rx, Key::Level(key.0, key.1), Record::Update(update));
+                }
+            }
+            Record::Kline(kline) => {
+                if self.started {
+                    self.stage(rx, Key::Kline(kline.ts), Record::Kline(kline));
+                }
+            }
+            Record::Trade(trade) => {
+                if self.started {
+                    self.stage_unique(rx, Record::Trade(trade));
+                }
+            }
+        }
+    }
+
+    /// Drop every level, as zero updates once past `from`.
+    fn clear(&mut self, rx: Time) {
+        let gone: Vec<_> = self.book.drain().collect();
+        if self.started {
+            for ((side, px), level) in gone {
+                let zero = OrderBookUpdate {
+                    ts: rx,
+                    sz: Decimal::ZERO,
+                    ..level
+                };
+                self.stage(rx, Key::Level(side, px), Record::Update(zero));
+            }
+        }
+    }
+
+    fn stage_unique(&mut self, rx: Time, record: Record) {
+        self.unique += 1;
+        self.stage(rx, Key::Unique(self.unique), record);
+    }
+
+    fn stage(&mut self, rx: Time, key: Key, record: Record) {
+        if rx / 1000 != self.second {
+            self.flush();
+            self.second = rx / 1000;
+        }
+        self.pending.insert(key, (rx, record));
+    }
+
+    fn flush(&mut self) {
+        let mut pending: Vec<_> = self.pending.drain().map(|(_, entry)| entry).collect();
+        // a gap goes after the levels it cleared
+        pending.sort_by_key(|(rx, record)| (*rx, *record == Record::Gap));
+        for (rx, record) in &pending {
+            codec::put_record(&mut self.buf, &mut self.last_rx, *rx, record);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.flush();
+        self.buf
+    }
+}
+
+//MARK: - Segment Writer ---------------------------------------------
+
+/// Writes one symbol's live feed into segment files.
+pub struct SegmentWriter {
+    store: Store,
+    symbol: String,
+    file: Option<BufWriter<File>>,
+    start: Time,
+    last_rx: Time,
+    buf: Vec<u8>,
+}
+
+impl SegmentWriter {
+    pub fn new(store: Store, symbol: String) -> Self {
+        Self {
+            store,
+            symbol,
+            file: None,
+            start: 0,
+            last_rx: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Whether a segment is open; records are dropped until `reset` opens one.
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// The open segment has run for `SEGMENT_MS` and should be restarted
+    /// with a fresh image.
+    pub fn is_due(&self, rx: Time) -> bool {
+        self.is_open() && rx / SEGMENT_MS != self.start / SEGMENT_MS
+    }
+
+    /// Start a new segment with `image` as its book.
+    pub fn reset(
+        &mut self,
+        rx: Time,
+        image: impl IntoIterator<Item = OrderBookUpdate>,
+    ) -> io::Result<()> {
+        self.close();
+        fs::create_dir_all(self.store.dir(&self.symbol))?;
+        // never reuse a start time, segments are append-only
+        self.start = rx.max(self.start + 1);
+        let path = self.store.segment_path(&self.symbol, self.start);
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)?;
+        self.file = Some(BufWriter::new(file));
+        self.last_rx = 0;
+
+        codec::put_header(&mut self.buf, self.store.venue, &self.symbol);
+        codec::put_record(&mut self.buf, &mut self.last_rx, rx, &Record::Reset);
+        let image = image.into_iter().map(Record::Update);
+        self.append(rx, image)?;
+        tracing::info!("💾 [store] {} segment {}", self.symbol, path.display());
+        Ok(())
+    }
+
+    pub fn append(
+        &mut self,
+        rx: Time,
+        records: impl IntoIterator<Item = Record>,
+    ) -> io::Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        for record in records {
+            codec::put_record(&mut self.buf, &mut self.last_rx, rx, &record);
+        }
+        file.write_all(&self.buf)?;
+        file.flush()?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// The book fell out of sync with the venue at `rx`: mark it, then stop
+    /// writing until the next `reset`.
+    pub fn gap(&mut self, rx: Time) -> io::Result<()> {
+        if self.is_open() {
+            self.append(rx, [Record::Gap])?;
+            tracing::warn!("🕳 [store] {} gap at {}", self.symbol, rx);
+        }
+        self.close();
+        Ok(())
+    }
+
+    /// Stop writing.
+    pub fn close(&mut self) {
+        self.file = None;
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codec::Trade;
+
+    use super::*;
+
+    fn update(side: Side, px: i64, sz: i64, ts: Time) -> Record {
+        Record::Update(OrderBookUpdate {
+            ts,
+            sq: 0,
+            side,
+            px: Decimal::from(px),
+            sz: Decimal::from(sz),
+        })
+    }
+
+    fn fold(from: Time, records: Vec<(Time, Record)>) -> Vec<(Time, Record)> {
+        let mut history = History::new("binance", "BTCUSDT", from);
+        for (rx, record) in records {
+            history.push(rx, record);
+        }
+        let bytes = history.finish();
+        let mut reader = Reader::new(&bytes).unwrap();
+        let mut out = Vec::new();
+        while let Some(entry) = reader.next_record().unwrap() {
+            out.push(entry);
+        }
+        out
+    }
+
+    #[test]
+    fn history_starts_with_the_book_at_from() {
+        let out = fold(
+            5_000,
+            vec![
+                (1_000, Record::Reset),
+                (1_000, update(Side::Bid, 100, 1, 1_000)),
+                (2_000, update(Side::Ask, 101, 2, 2_000)),
+                (3_000, update(Side::Ask, 101, 0, 3_000)),
+                (6_000, update(Side::Bid, 99, 4, 6_000)),
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                (5_000, update(Side::Bid, 100, 1, 5_000)),
+                (6_000, update(Side::Bid, 99, 4, 6_000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_keeps_the_last_change_per_level_each_second() {
+        let out = fold(
+            0,
+            vec![
+                (1_100, update(Side::Bid, 100, 1, 1_100)),
+                (1_200, update(Side::Bid, 100, 2, 1_200)),
+                (1_300, update(Side::Bid, 100, 3, 1_300)),
+                (2_100, update(Side::Bid, 100, 4, 2_100)),
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                (1_300, update(Side::Bid, 100, 3, 1_300)),
+                (2_100, update(Side::Bid, 100, 4, 2_100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_clears_the_book_at_a_gap() {
+        let out = fold(
+            0,
+            vec![
+                (1_000, Record::Reset),
+                (1_000, update(Side::Bid, 100, 1, 1_000)),
+                (2_000, Record::Gap),
+                (4_000, Record::Reset),
+                (4_000, update(Side::Bid, 99, 1, 4_000)),
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                (1_000, update(Side::Bid, 100, 1, 1_000)),
+                (2_000, update(Side::Bid, 100, 0, 2_000)),
+                (2_000, Record::Gap),
+                (4_000, update(Side::Bid, 99, 1, 4_000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_zeroes_levels_a_new_image_drops() {
+        let out = fold(
+            0,
+            vec![
+                (1_000, Record::Reset),
+                (1_000, update(Side::Bid, 100, 1, 1_000)),
+                (1_000, update(Side::Ask, 101, 1, 1_000)),
+                (3_000, Record::Reset),
+                (3_000, update(Side::Bid, 100, 2, 3_000)),
+            ],
+        );
+        assert_eq!(out.len(), 4);
+        assert!(out[2..].contains(&(3_000, update(Side::Ask, 101, 0, 3_000))));
+        assert!(out[2..].contains(&(3_000, update(Side::Bid, 100, 2, 3_000))));
+    }
+
+    #[test]
+    fn history_keeps_every_trade() {
+        let trade = Record::Trade(Trade {
+            ts: 1_000,
+            px: Decimal::from(100),
+            sz: Decimal::ONE,
+            side: Side::Ask,
+        });
+        let out = fold(
+            0,
+            vec![
+                (1_000, Record::Reset),
+                (1_000, trade.clone()),
+                (1_000, trade.clone()),
+            ],
+        );
+        assert_eq!(out, vec![(1_000, trade.clone()), (1_000, trade)]);
+    }
+}
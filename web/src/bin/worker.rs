@@ -0,0 +1,13 @@
+//! Second wasm entry point: the ingest worker spawned by the UI thread.
+use console_error_panic_hook::set_once as set_panic_hook;
+use gloo_worker::Registrable;
+use web::ingest::Ingest;
+
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+fn main() {
+    set_panic_hook();
+
+    Ingest::registrar().register();
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub use codec::Side;
+
+/// Size resting in some price range, per side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Depth {
+    pub bid: Decimal,
+    pub ask: Decimal,
+}
+
+impl Depth {
+    pub fn side(&self, side: Side) -> Decimal {
+        match side {
+            Side::Bid => self.bid,
+            Side::Ask => self.ask,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bid.is_zero() && self.ask.is_zero()
+    }
+}
+
+/// Size taken out of some price range over a time bin, split by whether
+/// trades at those prices account for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Removal {
+    /// Matched by executions against the resting side.
+    pub filled: Decimal,
+    /// Pulled without trading.
+    pub cancelled: Decimal,
+}
+
+impl Removal {
+    pub fn add(&mut self, other: Removal) {
+        self.filled += other.filled;
+        self.cancelled += other.cancelled;
+    }
+}
+
+/// Best bid and offer with their sizes, the part of the book the UI thread
+/// needs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Touch {
+    pub bid: Option<(Decimal, Decimal)>,
+    pub ask: Option<(Decimal, Decimal)>,
+    /// Levels resting on both sides.
+    pub levels: usize,
+}
+
+impl Touch {
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.ask?.0 - self.bid?.0)
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        Some((self.ask?.0 + self.bid?.0) / Decimal::TWO)
+    }
+}
+
+/// The book as it stood at the end of a time bin, as much of it as the
+/// indicator pane needs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BookSample {
+    /// Best bid and offer, price and size.
+    pub bid: (f64, f64),
+    pub ask: (f64, f64),
+    /// Total bid and ask size over the top `LEVELS[i]` levels of each side.
+    pub depth: [(f64, f64); 5],
+}
+
+impl BookSample {
+    /// Level counts the imbalance can be read over.
+    pub const LEVELS: [usize; 5] = [1, 3, 5, 10, 20];
+
+    pub fn mid(&self) -> f64 {
+        (self.bid.0 + self.ask.0) / 2.0
+    }
+
+    /// Mid weighted towards the side with less size resting at the touch,
+    /// where the next trade is likelier to move the price.
+    pub fn microprice(&self) -> f64 {
+        let ((bid, bid_sz), (ask, ask_sz)) = (self.bid, self.ask);
+        (bid * ask_sz + ask * bid_sz) / (bid_sz + ask_sz)
+    }
+
+    pub fn spread(&self) -> f64 {
+        self.ask.0 - self.bid.0
+    }
+
+    /// `(bids - asks) / (bids + asks)` over the top `levels` of each side,
+    /// from -1 (all asks) to 1 (all bids); `None` if `levels` isn't one of
+    /// `LEVELS`.
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        let i = Self::LEVELS.iter().position(|&n| n == levels)?;
+        let (bids, asks) = self.depth[i];
+        Some((bids - asks) / (bids + asks))
+    }
+}
+
+//MARK: - Order Book ---------------------------------------------
+
+/// Current L2 book, price -> resting size per side, maintained from the same
+/// normalised updates that feed the heatmap.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl OrderBook {
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Set the size resting at `px` on `side`, removing the level at zero.
+    ///
+    /// A level can flip side between updates; anything left on the other
+    /// side at or through `px` is stale and dropped so the book never
+    /// crosses. Returns the prices dropped that way.
+    pub fn set(&mut self, side: Side, px: Decimal, sz: Decimal) -> Vec<Decimal> {
+        let (book, other) = match side {
+            Side::Bid => (&mut self.bids, &mut self.asks),
+            Side::Ask => (&mut self.asks, &mut self.bids),
+        };
+        if sz.is_zero() {
+            book.remove(&px);
+            return Vec::new();
+        }
+        book.insert(px, sz);
+        let crossed: Vec<_> = match side {
+            Side::Bid => other.range(..=px).map(|(&ask, _)| ask).collect(),
+            Side::Ask => other.range(px..).map(|(&bid, _)| bid).collect(),
+        };
+        for px in &crossed {
+            other.remove(px);
+        }
+        crossed
+    }
+
+    /// Size resting at exactly `px` on `side`, zero when there is none.
+    pub fn size(&self, side: Side, px: Decimal) -> Decimal {
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        book.get(&px).copied().unwrap_or_default()
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.bids.iter().next_back().map(|(&px, &sz)| (px, sz))
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.asks.iter().next().map(|(&px, &sz)| (px, sz))
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        self.touch().spread()
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        self.touch().mid()
+    }
+
+    pub fn touch(&self) -> Touch {
+        Touch {
+            bid: self.best_bid(),
+            ask: self.best_ask(),
+            levels: self.len(),
+        }
+    }
+
+    /// Size resting in `[lo, hi)` on each side.
+    pub fn depth_between(&self, lo: Decimal, hi: Decimal) -> Depth {
+        let range = (Bound::Included(lo), Bound::Excluded(hi));
+        Depth {
+            bid: self.bids.range(range).map(|(_, sz)| sz).sum(),
+            ask: self.asks.range(range).map(|(_, sz)| sz).sum(),
+        }
+    }
+
+    /// The touch and top-of-book depth, when both sides have some.
+    pub fn sample(&self) -> Option<BookSample> {
+        let to_f64 = |(px, sz): (Decimal, Decimal)| (px.to_f64().unwrap(), sz.to_f64().unwrap());
+        let (bid, ask) = (to_f64(self.best_bid()?), to_f64(self.best_ask()?));
+        let depth = BookSample::LEVELS.map(|n| {
+            let bids: Decimal = self.bids.values().rev().take(n).sum();
+            let asks: Decimal = self.asks.values().take(n).sum();
+            (bids.to_f64().unwrap(), asks.to_f64().unwrap())
+        });
+        Some(BookSample { bid, ask, depth })
+    }
+
+    /// Every resting level with its side.
+    pub fn levels(&self) -> impl Iterator<Item = (Side, Decimal, Decimal)> + '_ {
+        let bids = self.bids.iter().map(|(&px, &sz)| (Side::Bid, px, sz));
+        let asks = self.asks.iter().map(|(&px, &sz)| (Side::Ask, px, sz));
+        bids.chain(asks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn set_drops_asks_crossed_by_a_bid() {
+        let mut book = OrderBook::default();
+        for px in ["101", "102", "103"] {
+            book.set(Side::Ask, dec(px), dec("1"));
+        }
+        let crossed = book.set(Side::Bid, dec("102"), dec("5"));
+        assert_eq!(crossed, vec![dec("101"), dec("102")]);
+        assert_eq!(book.best_bid(), Some((dec("102"), dec("5"))));
+        assert_eq!(book.best_ask(), Some((dec("103"), dec("1"))));
+    }
+
+    #[test]
+    fn set_drops_bids_crossed_by_an_ask() {
+        let mut book = OrderBook::default();
+        for px in ["98", "99", "100"] {
+            book.set(Side::Bid, dec(px), dec("1"));
+        }
+        let crossed = book.set(Side::Ask, dec("99"), dec("5"));
+        assert_eq!(crossed, vec![dec("99"), dec("100")]);
+        assert_eq!(book.best_bid(), Some((dec("98"), dec("1"))));
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn set_zero_removes_without_crossing() {
+        let mut book = OrderBook::default();
+        book.set(Side::Ask, dec("101"), dec("1"));
+        book.set(Side::Bid, dec("100"), dec("1"));
+        assert!(book.set(Side::Bid, dec("101"), Decimal::ZERO).is_empty());
+        assert!(book.set(Side::Bid, dec("100"), Decimal::ZERO).is_empty());
+        assert_eq!(book.best_ask(), Some((dec("101"), dec("1"))));
+        assert_eq!(book.best_bid(), None);
+    }
+}
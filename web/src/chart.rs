@@ -1,21 +1,33 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    sync::{Arc, RwLock},
-};
+use std::collections::BTreeMap;
+use std::rc::Rc;
 
 use chrono::Duration;
 use plotters::{
+    coord::Shift,
     prelude::*,
-    style::full_palette::{CYAN_300, CYAN_600, GREY},
+    style::full_palette::GREY,
+    style::text_anchor::{HPos, Pos, VPos},
 };
 use plotters_canvas::CanvasBackend;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
-use wasm_bindgen::prelude::*;
-use web_sys::HtmlCanvasElement;
+use rust_decimal::prelude::ToPrimitive;
+use wasm_bindgen::{prelude::*, Clamped};
+use web_sys::{HtmlCanvasElement, ImageData};
 use yew::prelude::*;
 use yew_hooks::prelude::*;
 
-use crate::data::{self, Kline, Time};
+use crate::book::Side;
+use crate::connection::StatusBadge;
+use crate::controls::{
+    memory_readout, BandControls, BinControls, PriceBand, RetentionControls, FIELD,
+};
+use crate::crosshair;
+use crate::data::{self, Time};
+use crate::iceberg::{Iceberg, IcebergDetector, IcebergPanel};
+use crate::indicators::{self, IndicatorControls, IndicatorSettings};
+use crate::layers::{HeatmapLayers, LayerControls};
+use crate::palette::ColourMap;
+use crate::raster::{Backing, Viewport};
+use crate::spoof::{Spoof, SpoofDetector, SpoofPanel, SpoofSettings};
 
 #[function_component(Chart)]
 pub fn chart() -> Html {
@@ -24,24 +36,108 @@ pub fn chart() -> Html {
 
     let data_ctx = use_context::<data::DataContext>().unwrap();
     let klines_len = data_ctx.klines.to_owned().read().unwrap().len();
+    let gap_count = data_ctx.gap_count();
+    let usage = data_ctx.usage();
+    let touch = {
+        let touch = data_ctx.touch.get();
+        match (touch.bid, touch.ask, touch.spread(), touch.mid()) {
+            (Some((bid, _)), Some((ask, _)), Some(spread), Some(mid)) => Some(format!(
+                "{} × {} mid {} spread {} · {} levels",
+                bid,
+                ask,
+                mid.normalize(),
+                spread,
+                touch.levels
+            )),
+            _ => None,
+        }
+    };
+    let bins = data_ctx.bins.get();
+    let layers = use_state_eq(HeatmapLayers::load);
+    let on_layers = {
+        let layers = layers.clone();
+        Callback::from(move |new_layers: HeatmapLayers| {
+            new_layers.save();
+            layers.set(new_layers);
+        })
+    };
+    let band = use_state_eq(PriceBand::load);
+    let on_band = {
+        let band = band.clone();
+        Callback::from(move |new_band: PriceBand| {
+            new_band.save();
+            band.set(new_band);
+        })
+    };
+    let spoof_settings = use_state_eq(SpoofSettings::load);
+    let on_spoof_settings = {
+        let spoof_settings = spoof_settings.clone();
+        Callback::from(move |settings: SpoofSettings| {
+            settings.save();
+            spoof_settings.set(settings);
+        })
+    };
+    let spoofs = use_state_eq(|| Rc::new(Vec::<Spoof>::new()));
+    let icebergs = use_state_eq(|| Rc::new(Vec::<Iceberg>::new()));
+    let indicator_settings = use_state_eq(IndicatorSettings::load);
+    let on_indicator_settings = {
+        let indicator_settings = indicator_settings.clone();
+        Callback::from(move |settings: IndicatorSettings| {
+            settings.save();
+            indicator_settings.set(settings);
+        })
+    };
     let canvas_ref = use_node_ref();
+    let overlay_ref = use_node_ref();
+    let pane_ref = use_node_ref();
     let canvas = use_state_eq(|| None);
+    let view = use_mut_ref(ChartView::default);
+    let following = use_state_eq(|| true);
 
     let draw = {
         let canvas = canvas.clone();
-        let klines = data_ctx.klines.clone();
-        let heatmap = data_ctx.heatmap.clone();
+        let data = (*data_ctx).clone();
+        let layers = *layers;
+        let band = *band;
+        let view = view.clone();
+        let (spoofs, icebergs) = ((*spoofs).clone(), (*icebergs).clone());
+        let (pane_ref, indicator_settings) = (pane_ref.clone(), *indicator_settings);
         move || {
             if let Some(canvas) = canvas.as_ref() as Option<&HtmlCanvasElement> {
-                // TODO: ThemeContext
-                let local_storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
-                let stored_theme = local_storage.get_item("color-theme").unwrap();
-                let is_dark = stored_theme.unwrap_or("dark".to_string()) == "dark";
-
-                let _ = redraw(canvas.clone(), is_dark, klines, heatmap);
+                let _ = redraw(
+                    canvas.clone(),
+                    is_dark(),
+                    &data,
+                    (layers, band),
+                    (&spoofs, &icebergs),
+                    &mut view.borrow_mut(),
+                );
+            }
+            if let Some(pane) = pane_ref.cast::<HtmlCanvasElement>() {
+                let plot = view.borrow().plot;
+                let _ = indicators::draw(pane, is_dark(), &data, indicator_settings, plot);
+            }
+        }
+    };
+    let draw_overlay = {
+        let overlay_ref = overlay_ref.clone();
+        let data = (*data_ctx).clone();
+        let layers = *layers;
+        let view = view.clone();
+        move || {
+            if let Some(overlay) = overlay_ref.cast::<HtmlCanvasElement>() {
+                let view = view.borrow();
+                crosshair::draw(&overlay, is_dark(), &data, layers, view.plot, view.hover);
             }
         }
     };
+    let draw = {
+        let draw_overlay = draw_overlay.clone();
+        move || {
+            draw();
+            draw_overlay();
+        }
+    };
     // draw initial
     if (*canvas).is_none() {
         if let Some(canvas_el) = canvas_ref.cast::<HtmlCanvasElement>() {
@@ -61,29 +157,449 @@ pub fn chart() -> Html {
         );
     }
     {
-        use_effect_with((canvas_container_size, state), move |_| {
-            draw.clone()();
+        // the detectors walk each time bin once it has closed, on the slow tick
+        let (spoofs, icebergs, data) = (spoofs.clone(), icebergs.clone(), (*data_ctx).clone());
+        let detectors = use_mut_ref(|| (SpoofDetector::default(), IcebergDetector::default()));
+        use_effect_with(
+            (state.clone(), bins, *spoof_settings),
+            move |(_, bins, settings)| {
+                let (heatmap, removals) =
+                    (data.heatmap.read().unwrap(), data.removals.read().unwrap());
+                let changed = data.rescan_from.take();
+                // the newest bin is still filling
+                if let Some(&until) = heatmap.values().filter_map(|c| c.keys().next_back()).max() {
+                    let (spoof, iceberg) = &mut *detectors.borrow_mut();
+                    spoof.scan(
+                        &heatmap,
+                        &removals,
+                        &data.samples.read().unwrap(),
+                        until,
+                        changed,
+                        (*settings, *bins),
+                    );
+                    iceberg.scan(&heatmap, &removals, until, changed);
+                    spoofs.set(Rc::new(spoof.spoofs().to_vec()));
+                    icebergs.set(Rc::new(iceberg.icebergs()));
+                }
+                || ()
+            },
+        );
+    }
+    {
+        let draw = draw.clone();
+        use_effect_with(
+            (
+                canvas_container_size,
+                state,
+                bins,
+                *layers,
+                *band,
+                spoofs.clone(),
+                icebergs.clone(),
+                *indicator_settings,
+            ),
+            move |_| {
+                draw();
+                || ()
+            },
+        );
+    }
+    {
+        // a new symbol starts back at the latest data
+        let (view, following) = (view.clone(), following.clone());
+        use_effect_with(data_ctx.symbol.clone(), move |_| {
+            view.borrow_mut().nav = Navigation::default();
+            following.set(true);
             || ()
         });
     }
 
+    // navigation: every handler updates `view.nav` and redraws straight away
+    let navigate = {
+        let (view, following, draw) = (view.clone(), following.clone(), draw.clone());
+        move |f: fn(&mut ChartView, &MouseEvent), e: &MouseEvent| {
+            let mut view = view.borrow_mut();
+            f(&mut view, e);
+            following.set(view.nav.end.is_none());
+            drop(view);
+            draw();
+        }
+    };
+    let on_wheel = {
+        let navigate = navigate.clone();
+        Callback::from(move |e: WheelEvent| {
+            e.prevent_default();
+            navigate(
+                |view, e| {
+                    let e = e.unchecked_ref::<WheelEvent>();
+                    let factor = (e.delta_y() * 0.001).exp();
+                    if e.shift_key() {
+                        view.zoom_prices(e.offset_y() as f64, factor);
+                    } else {
+                        view.zoom_time(e.offset_x() as f64, factor);
+                    }
+                },
+                &e,
+            )
+        })
+    };
+    let on_mouse_down = {
+        let view = view.clone();
+        Callback::from(move |e: MouseEvent| {
+            view.borrow_mut()
+                .start_pan(e.offset_x() as f64, e.offset_y() as f64);
+        })
+    };
+    let on_mouse_move = {
+        let (view, navigate, draw_overlay) = (view.clone(), navigate.clone(), draw_overlay.clone());
+        Callback::from(move |e: MouseEvent| {
+            let at = (e.offset_x() as f64, e.offset_y() as f64);
+            view.borrow_mut().hover = Some(at);
+            if view.borrow().drag.is_some() {
+                navigate(
+                    |view, e| view.pan_to(e.offset_x() as f64, e.offset_y() as f64),
+                    &e,
+                );
+            } else {
+                draw_overlay();
+            }
+        })
+    };
+    let on_mouse_up = {
+        let view = view.clone();
+        Callback::from(move |_: MouseEvent| view.borrow_mut().end_pan())
+    };
+    let on_mouse_leave = {
+        let view = view.clone();
+        Callback::from(move |_: MouseEvent| {
+            let mut view_mut = view.borrow_mut();
+            view_mut.end_pan();
+            view_mut.hover = None;
+            drop(view_mut);
+            draw_overlay();
+        })
+    };
+    let on_double_click = {
+        let navigate = navigate.clone();
+        Callback::from(move |e: MouseEvent| {
+            navigate(|view, _| view.nav = Navigation::default(), &e)
+        })
+    };
+    let jump = {
+        let (view, following, draw) = (view.clone(), following.clone(), draw.clone());
+        move |at: (Time, f64)| {
+            let mut view = view.borrow_mut();
+            view.jump_to(at);
+            following.set(view.nav.end.is_none());
+            drop(view);
+            draw();
+        }
+    };
+    let on_click = {
+        let (view, spoofs, icebergs, jump) =
+            (view.clone(), spoofs.clone(), icebergs.clone(), jump.clone());
+        Callback::from(move |e: MouseEvent| {
+            let Some(plot) = view.borrow().plot else {
+                return;
+            };
+            let at = (e.offset_x() as f64, e.offset_y() as f64);
+            let step = bins.price_step;
+            let hit = spoofs
+                .iter()
+                .map(|s| s.marker(step))
+                .chain(icebergs.iter().map(|i| i.marker(step)))
+                .find(|&marker| plot.near(marker, at));
+            if let Some(marker) = hit {
+                jump(marker);
+            }
+        })
+    };
+    let on_spoof_select = {
+        let jump = jump.clone();
+        Callback::from(move |spoof: Spoof| jump(spoof.marker(bins.price_step)))
+    };
+    let on_iceberg_select =
+        Callback::from(move |iceberg: Iceberg| jump(iceberg.marker(bins.price_step)));
+    let on_follow =
+        Callback::from(move |e: MouseEvent| navigate(|view, _| view.nav.end = None, &e));
+
     html! {
-        <div ref={canvas_container_ref} class="w-full h-full overflow-hidden">
+        <div class="flex w-full h-full">
+        <div class="flex flex-col flex-grow min-w-0 h-full">
+        <div ref={canvas_container_ref} class="relative flex-grow min-h-0 overflow-hidden">
             <canvas
                 ref={canvas_ref.clone()}
+                class="cursor-crosshair"
+                width={canvas_container_size.0.to_string()}
+                height={canvas_container_size.1.to_string()}
+                onwheel={on_wheel}
+                onmousedown={on_mouse_down}
+                onmousemove={on_mouse_move}
+                onmouseup={on_mouse_up}
+                onmouseleave={on_mouse_leave}
+                ondblclick={on_double_click}
+                onclick={on_click}
+            ></canvas>
+            <canvas
+                ref={overlay_ref}
+                class="absolute top-0 left-0 pointer-events-none"
                 width={canvas_container_size.0.to_string()}
                 height={canvas_container_size.1.to_string()}
             ></canvas>
-            <span class="absolute top-0 left-0">{klines_len}</span>
+            <span class="absolute top-0 left-0">
+                if data_ctx.replay.borrow().is_some() {
+                    <span class="px-2 py-0.5 rounded text-xs font-mono border border-gray-300 dark:border-gray-700">
+                        {format!("{} replay", data_ctx.symbol.clone().unwrap_or_default())}
+                    </span>
+                } else {
+                    <StatusBadge label={data_ctx.symbol.clone().unwrap_or_default()} status={data_ctx.status.clone()}/>
+                }
+                <span class="ml-2">{klines_len}</span>
+                if gap_count > 0 {
+                    <span class="ml-2 text-amber-500">{format!("{} gaps", gap_count)}</span>
+                }
+                if let Some(touch) = touch {
+                    <span class="ml-2 text-xs font-mono">{touch}</span>
+                }
+                <span class="ml-2 text-xs font-mono text-gray-500">{memory_readout(usage)}</span>
+                if !*following {
+                    <button class={classes!(FIELD, "ml-2", "text-xs", "font-mono")} onclick={on_follow}>
+                        {"follow latest ⏭"}
+                    </button>
+                }
+            </span>
+            <span class="absolute top-0 right-0 flex flex-col items-end gap-1">
+                <BinControls/>
+                <RetentionControls/>
+                <LayerControls layers={*layers} on_change={on_layers}/>
+                <BandControls band={*band} on_change={on_band}/>
+            </span>
+        </div>
+        <div class="relative shrink-0 border-t border-gray-300 dark:border-gray-700">
+            <canvas
+                ref={pane_ref}
+                width={canvas_container_size.0.to_string()}
+                height={indicators::PANE_HEIGHT.to_string()}
+            ></canvas>
+            <span class="absolute top-0 right-0 p-1">
+                <IndicatorControls settings={*indicator_settings} on_change={on_indicator_settings}/>
+            </span>
+        </div>
+        </div>
+        <div class="flex flex-col w-56 shrink-0 h-full border-l border-gray-300 dark:border-gray-700">
+            <div class="h-1/2 min-h-0">
+                <SpoofPanel
+                    spoofs={(*spoofs).clone()}
+                    settings={*spoof_settings}
+                    on_settings={on_spoof_settings}
+                    on_select={on_spoof_select}
+                />
+            </div>
+            <div class="h-1/2 min-h-0 border-t border-gray-300 dark:border-gray-700">
+                <IcebergPanel icebergs={(*icebergs).clone()} on_select={on_iceberg_select}/>
+            </div>
+        </div>
         </div>
     }
 }
 
+fn is_dark() -> bool {
+    // TODO: ThemeContext
+    let local_storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+    let stored_theme = local_storage.get_item("color-theme").unwrap();
+    stored_theme.unwrap_or("dark".to_string()) == "dark"
+}
+
+//MARK: - Drawing ---------------------------------------------
+
+/// How much time the chart spans until zoomed; it scrolls once the data
+/// fills it.
+const SPAN_MS: Time = 30 * 60 * 1000;
+/// Zoom limits on the time axis.
+const MIN_SPAN_MS: Time = 60 * 1000;
+const MAX_SPAN_MS: Time = 24 * 60 * 60 * 1000;
+
+/// Where the user has zoomed and panned to; the default follows the latest
+/// data with the price axis fitted automatically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Navigation {
+    span: Time,
+    /// Right edge of the chart, `None` while following the latest data.
+    end: Option<Time>,
+    /// Price axis set by hand, overriding the band.
+    prices: Option<(f64, f64)>,
+}
+
+impl Default for Navigation {
+    fn default() -> Self {
+        Self {
+            span: SPAN_MS,
+            end: None,
+            prices: None,
+        }
+    }
+}
+
+/// Plotting area of the last redraw, to map the mouse back to time and
+/// price.
+#[derive(Debug, Clone, Copy)]
+pub struct Plot {
+    pub viewport: Viewport,
+    pub left: f64,
+    pub top: f64,
+    latest: Time,
+}
+
+impl Plot {
+    pub fn time_at(&self, x: f64) -> f64 {
+        let v = self.viewport;
+        v.from as f64 + (x - self.left) / v.width as f64 * (v.to - v.from) as f64
+    }
+
+    pub fn price_at(&self, y: f64) -> f64 {
+        let v = self.viewport;
+        v.max_px - (y - self.top) / v.height as f64 * (v.max_px - v.min_px)
+    }
+
+    /// Whether `(ts, price)` is drawn within a marker's reach of `(x, y)`.
+    fn near(&self, (ts, price): (Time, f64), (x, y): (f64, f64)) -> bool {
+        let v = self.viewport;
+        let px = self.left + (ts as f64 - v.from as f64) / (v.to - v.from) as f64 * v.width as f64;
+        let py = self.top + (v.max_px - price) / (v.max_px - v.min_px) * v.height as f64;
+        (px - x).hypot(py - y) <= MARKER_RADIUS as f64 + 2.0
+    }
+}
+
+/// What the chart kept from its last redraw.
+#[derive(Default)]
+struct ChartView {
+    heatmap: Backing,
+    prices: Option<(f64, f64)>,
+    nav: Navigation,
+    plot: Option<Plot>,
+    /// Mouse position and plot where a drag started.
+    drag: Option<(f64, f64, Plot)>,
+    /// Mouse position over the canvas, for the crosshair.
+    hover: Option<(f64, f64)>,
+}
+
+impl ChartView {
+    /// Zoom the time axis by `factor` around the time under `x`; while
+    /// following, the right edge stays on the latest data.
+    fn zoom_time(&mut self, x: f64, factor: f64) {
+        let Some(plot) = self.plot else {
+            return;
+        };
+        let span = (self.nav.span as f64 * factor).clamp(MIN_SPAN_MS as f64, MAX_SPAN_MS as f64);
+        let factor = span / self.nav.span as f64;
+        self.nav.span = span as Time;
+        if self.nav.end.is_some() {
+            let anchor = plot.time_at(x);
+            let end = anchor + (plot.viewport.to as f64 - anchor) * factor;
+            self.follow_unless_before(end.max(0.0) as Time, plot.latest);
+        }
+    }
+
+    /// Zoom the price axis by `factor` around the price under `y`.
+    fn zoom_prices(&mut self, y: f64, factor: f64) {
+        let Some(plot) = self.plot else {
+            return;
+        };
+        let anchor = plot.price_at(y);
+        let v = plot.viewport;
+        self.nav.prices = Some((
+            anchor - (anchor - v.min_px) * factor,
+            anchor + (v.max_px - anchor) * factor,
+        ));
+    }
+
+    /// Drag the chart so the point grabbed follows the mouse. Dragging back
+    /// in time stops following the latest data, dragging past it resumes.
+    fn pan_to(&mut self, x: f64, y: f64) {
+        let Some((x0, y0, plot)) = self.drag else {
+            return;
+        };
+        let v = plot.viewport;
+        let dt = (x - x0) / v.width as f64 * (v.to - v.from) as f64;
+        let end = (v.to as f64 - dt).max(0.0) as Time;
+        self.follow_unless_before(end, plot.latest);
+
+        // small vertical jitter shouldn't freeze the price axis
+        if (y - y0).abs() > 3.0 || self.nav.prices.is_some() {
+            let dp = (y - y0) / v.height as f64 * (v.max_px - v.min_px);
+            self.nav.prices = Some((v.min_px + dp, v.max_px + dp));
+        }
+    }
+
+    fn start_pan(&mut self, x: f64, y: f64) {
+        self.drag = self.plot.map(|plot| (x, y, plot));
+    }
+
+    fn end_pan(&mut self) {
+        self.drag = None;
+    }
+
+    /// Centre the chart on a detection, keeping the zoom.
+    fn jump_to(&mut self, (ts, price): (Time, f64)) {
+        let latest = self.plot.map_or(Time::MAX, |plot| plot.latest);
+        self.follow_unless_before(ts + self.nav.span / 2, latest);
+        let half = self.plot.map_or(price * 0.001, |p| {
+            (p.viewport.max_px - p.viewport.min_px) / 2.0
+        });
+        self.nav.prices = Some((price - half, price + half));
+    }
+
+    fn follow_unless_before(&mut self, end: Time, latest: Time) {
+        self.nav.end = (end < latest).then_some(end);
+    }
+
+    /// Keep the price axis still while the window scrolls, so the heatmap
+    /// image can shift rather than repaint; refit once the candles leave it
+    /// or shrink to a sliver of it.
+    fn fit_prices(&mut self, lo: f64, hi: f64) -> (f64, f64) {
+        let fits = self
+            .prices
+            .is_some_and(|(min, max)| lo >= min && hi <= max && (hi - lo) * 8.0 >= max - min);
+        if !fits {
+            let pad = ((hi - lo) / 2.0).max(hi.abs() * 1e-4);
+            self.prices = Some((lo - pad, hi + pad));
+        }
+        self.prices.unwrap()
+    }
+
+    /// Centre the price axis on `mid`, `half` either side, only recentring
+    /// once the mid strays out of the middle half of it.
+    fn fit_band(&mut self, mid: f64, half: f64) -> (f64, f64) {
+        let fits = self.prices.is_some_and(|(min, max)| {
+            (max - min - 2.0 * half).abs() <= half * 1e-9
+                && (mid - (min + max) / 2.0).abs() <= half / 2.0
+        });
+        if !fits {
+            self.prices = Some((mid - half, mid + half));
+        }
+        self.prices.unwrap()
+    }
+}
+
+fn layout<'a, 'b, DB: DrawingBackend>(
+    root: &'a DrawingArea<DB, Shift>,
+) -> ChartBuilder<'a, 'b, DB> {
+    let mut builder = ChartBuilder::on(root);
+    builder
+        .margin(10u32)
+        .margin_right(LEGEND_WIDTH)
+        .x_label_area_size(30u32)
+        .y_label_area_size(30u32);
+    builder
+}
+
 fn redraw(
     canvas: HtmlCanvasElement,
     darkmode: bool,
-    klines: Arc<RwLock<BTreeMap<Time, Kline>>>,
-    heatmap: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, f64>>>>,
+    data: &data::Data,
+    (layers, band): (HeatmapLayers, PriceBand),
+    (spoofs, icebergs): (&[Spoof], &[Iceberg]),
+    view: &mut ChartView,
 ) -> anyhow::Result<()> {
     web_sys::console::log_1(&"redraw".into());
 
@@ -99,36 +615,104 @@ fn redraw(
     let backend = CanvasBackend::with_canvas_object(canvas).expect("cannot find canvas");
     let root = backend.into_drawing_area();
 
-    let klines = klines.read().unwrap();
+    let klines = data.klines.read().unwrap();
     if klines.is_empty() {
         return Ok(());
     }
-    let min_ts = klines.keys().min().unwrap();
-    let min_ts = chrono::DateTime::from_timestamp(*min_ts as i64 / 1000, 0).unwrap();
-    let max_ts = klines.keys().max().unwrap();
-    let max_ts = chrono::DateTime::from_timestamp(*max_ts as i64 / 1000, 0).unwrap();
-    let min_px = klines
-        .values()
-        .map(|k| k.lo)
-        .min_by(|a, b| a.partial_cmp(b).unwrap())
-        .unwrap();
-    let max_px = klines
-        .values()
-        .map(|k| k.hi)
-        .max_by(|a, b| a.partial_cmp(b).unwrap())
+    let bins = data.bins.get();
+    let precision = data.instrument.get().map(|i| i.precision());
+
+    // a whole number of ms per pixel column, so scrolling moves whole columns
+    let (x_range, y_range) = layout(&root)
+        .build_cartesian_2d(0f64..1f64, 0f64..1f64)?
+        .plotting_area()
+        .get_pixel_range();
+    let (width, height) = (x_range.len(), y_range.len());
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let col_ms = view.nav.span.div_ceil(width as Time);
+    let span = col_ms * width as Time;
+    let first = *klines.keys().next().unwrap();
+    // the newest kline is keyed by its open, so with 1m or 5m candles the
+    // book and trades run minutes past it
+    let newest_sample = data.samples.read().unwrap().keys().next_back().copied();
+    let newest_trade = data.trades.read().unwrap().last().map(|t| t.ts);
+    let latest = [newest_sample.map(|ts| ts + bins.time_step), newest_trade]
+        .into_iter()
+        .flatten()
+        .fold(*klines.keys().next_back().unwrap(), Time::max);
+    let end = view.nav.end.unwrap_or((first + span).max(latest));
+    let to = end.max(span).div_ceil(col_ms) * col_ms;
+    let from = to - span;
+
+    // panned past the candles: fit the nearest one
+    let visible = match klines.range(from..=to).next() {
+        Some(_) => klines.range(from..=to),
+        None => klines
+            .range(..=to)
+            .next_back()
+            .map_or(klines.range(..), |(ts, _)| klines.range(*ts..=*ts)),
+    }
+    .map(|(_, k)| k);
+    let lo = visible.clone().map(|k| k.lo).fold(f64::INFINITY, f64::min);
+    let hi = visible.map(|k| k.hi).fold(f64::NEG_INFINITY, f64::max);
+    let mid = data
+        .touch
+        .get()
+        .mid()
+        .and_then(|mid| mid.to_f64())
+        .unwrap_or_else(|| klines.values().next_back().unwrap().cl);
+    let tick = data
+        .instrument
+        .get()
+        .map_or(bins.price_step, |i| i.tick_size)
+        .to_f64()
         .unwrap();
-    let min_max_px_spd = (max_px - min_px) / 2.0;
-    let min_px = min_px - min_max_px_spd;
-    let max_px = max_px + min_max_px_spd;
+    let (min_px, max_px) = match (view.nav.prices, band.half_width(mid, tick)) {
+        (Some(prices), _) => prices,
+        (None, Some(half)) => view.fit_band(mid, half),
+        (None, None) => view.fit_prices(lo, hi),
+    };
+    let min_ts = chrono::DateTime::from_timestamp_millis(from as i64).unwrap();
+    let max_ts = chrono::DateTime::from_timestamp_millis(to as i64).unwrap();
 
-    let mut chart = ChartBuilder::on(&root)
-        .margin(10u32)
-        .x_label_area_size(30u32)
-        .y_label_area_size(30u32)
-        .build_cartesian_2d(min_ts..max_ts, min_px..max_px)?;
+    let mut chart = layout(&root).build_cartesian_2d(min_ts..max_ts, min_px..max_px)?;
+
+    // Blit the heatmap first, putImageData replaces whatever is underneath
+    let viewport = Viewport {
+        from,
+        to,
+        min_px,
+        max_px,
+        width,
+        height,
+    };
+    view.plot = Some(Plot {
+        viewport,
+        left: x_range.start as f64,
+        top: y_range.start as f64,
+        latest,
+    });
+    {
+        let heatmap = data.heatmap.read().unwrap();
+        let dirty = data.dirty.take();
+        let removals = data.removals.read().unwrap();
+        let pixels = view
+            .heatmap
+            .update(&heatmap, &removals, bins, layers, darkmode, viewport, dirty);
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(pixels),
+            width as u32,
+            height as u32,
+        )
+        .unwrap();
+        context
+            .put_image_data(&image, x_range.start as f64, y_range.start as f64)
+            .unwrap();
+    }
 
     let axis_color = if darkmode { GREY } else { BLACK };
-    let heatmap_color = if darkmode { CYAN_600 } else { CYAN_300 };
     chart
         .configure_mesh()
         .disable_mesh()
@@ -142,108 +726,50 @@ fn redraw(
                 .color(&axis_color.mix(0.65))
                 .transform(FontTransform::Rotate90),
         )
-        .y_label_formatter(&|y| format!("{}", (*y * 10_000.0).round() / 10_000.0))
+        .y_label_formatter(&|y| match precision {
+            Some(precision) => format!("{:.*}", precision, y),
+            None => format!("{}", (*y * 10_000.0).round() / 10_000.0),
+        })
         .x_labels(8)
         .x_label_style(("monospace", 12).into_font().color(&axis_color.mix(0.65)))
         .x_label_formatter(&|x| x.format("%H:%M:%S").to_string())
         .draw()?;
 
-    // Draw heatmap
-    if let Ok(heatmap) = heatmap.read() {
-        let sizes: Vec<_> = heatmap
-            .values()
-            .flat_map(|m| m.values()) // Accessing all f64 values across all BTreeMaps
-            .map(|&size| size.abs()) // Apply abs() to each f64 value
-            .filter(|&v| v.is_finite() && v != 0.0) // Filter values based on the conditions
-            .collect();
-        // let max_sz = sizes.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-        let median_sz = data::median(&sizes);
-        let deviations: Vec<_> = sizes.iter().map(|&x| (x - median_sz).abs()).collect();
-        let mdev_sz = data::median(&deviations);
-
-        for (price, orders) in heatmap.iter() {
-            let mut points = Vec::new();
-            let price_coord = price.to_f64().unwrap();
-            if price_coord <= min_px || price_coord >= max_px {
+    // Draw resync markers along the time axis
+    if let Ok(resyncs) = data.resyncs.read() {
+        let marker_color = RGBColor(245, 158, 11);
+        for resync in resyncs.iter() {
+            let start = chrono::DateTime::from_timestamp(resync.start as i64 / 1000, 0).unwrap();
+            let end = resync
+                .end
+                .and_then(|end| chrono::DateTime::from_timestamp(end as i64 / 1000, 0))
+                .unwrap_or(max_ts)
+                .max(start + Duration::seconds(1));
+            if end < min_ts || start > max_ts {
                 continue;
             }
-            let mut last_sz = 0.0f64;
-
-            for (&time, &size) in orders.iter() {
-                let size_coord = size.abs(); // Convert size to z-coordinate
-                let time_coord = chrono::DateTime::from_timestamp(time as i64 / 1000, 0).unwrap(); // Convert time to x-coordinate
-                if time_coord < min_ts || time_coord > max_ts {
-                    continue;
-                }
-                points.push((time_coord, price_coord)); // Add point to line
-
-                // Draw line segment if there are at least two points
-                if points.len() >= 2 {
-                    let line_width;
-                    let alpha_scale;
-                    let start_size = last_sz.abs().max(size_coord);
-                    let threshold = (start_size - median_sz) / mdev_sz;
-                    // console::log_1(&format!("size: {:?} threshold: {:?}", start_size, threshold).into());
-                    if threshold > 9.0 {
-                        line_width = 8.0;
-                        alpha_scale = 1.0;
-                    } else {
-                        line_width = 4.0;
-                        alpha_scale = 0.3 * threshold.max(0.1).min(1.0);
-                    }
-                    let line_style = heatmap_color
-                        .mix(alpha_scale)
-                        .stroke_width(line_width as u32);
-                    // if size < 0.0 || last_sz < 0.0 {
-                    //     line_style = plotters::style::RGBAColor(255, 0, 255, alpha_scale)
-                    //         .stroke_width(line_width as u32);
-                    // }
-                    chart
-                        .draw_series(LineSeries::new(points.iter().cloned(), line_style))
-                        .unwrap(); // Draw line
-
-                    points.clear();
-
-                    if size_coord > 0.0 {
-                        points.push((time_coord - Duration::seconds(1), price_coord));
-                    }
-                }
-
-                last_sz = size;
-            }
-
-            if points.len() > 0 {
-                points.push((chrono::Utc::now(), price_coord));
-                let line_width;
-                let alpha_scale;
-                let start_size = last_sz.abs();
-                let threshold = (start_size - median_sz) / mdev_sz;
-                if threshold > 9.0 {
-                    line_width = 8.0;
-                    alpha_scale = 1.0;
-                } else {
-                    line_width = 4.0;
-                    alpha_scale = 0.3 / threshold.max(1.0);
-                }
-                let line_style = heatmap_color
-                    .mix(alpha_scale)
-                    .stroke_width(line_width as u32)
-                    .filled();
-                // if last_sz < 0.0 {
-                //     line_style = plotters::style::RGBAColor(255, 0, 255, alpha_scale)
-                //         .stroke_width(line_width as u32);
-                // }
-                chart
-                    .draw_series(LineSeries::new(points.iter().cloned(), line_style))
-                    .unwrap(); // Draw line
-                points.clear();
+            let start = start.max(min_ts);
+            chart.draw_series(std::iter::once(Rectangle::new(
+                [(start, min_px), (end, max_px)],
+                marker_color.mix(0.08).filled(),
+            )))?;
+            chart.draw_series(std::iter::once(Rectangle::new(
+                [(start, min_px), (end, min_px + (max_px - min_px) * 0.01)],
+                marker_color.mix(0.8).filled(),
+            )))?;
+            if resync.end.is_none() {
+                chart.draw_series(std::iter::once(Text::new(
+                    "resyncing",
+                    (start, min_px + (max_px - min_px) * 0.02),
+                    ("monospace", 12).into_font().color(&marker_color),
+                )))?;
             }
         }
-        drop(heatmap);
     }
 
-    // Draw KLines
-    chart.draw_series(klines.values().map(|k| {
+    // Draw KLines, no wider than the pixels a second covers
+    let candle_width = (1000 / col_ms).clamp(1, 8) as u32;
+    chart.draw_series(klines.range(from..=to).map(|(_, k)| {
         CandleStick::new(
             chrono::DateTime::from_timestamp(k.ts as i64 / 1000, 0).unwrap(),
             k.op,
@@ -252,11 +778,223 @@ fn redraw(
             k.cl,
             RGBColor(81, 205, 160).filled(),
             RGBColor(192, 80, 77).filled(),
-            8,
+            candle_width,
         )
     }))?;
 
+    // Trades over everything else, one bubble per side and bucket
+    let bucket = bins
+        .time_step
+        .max(col_ms * BUBBLE_SPACING)
+        .div_ceil(bins.time_step)
+        * bins.time_step;
+    let bubbles = trade_bubbles(&data.trades.read().unwrap(), from, to, bucket);
+    let max_qty = bubbles.iter().map(|b| b.qty).fold(0.0, f64::max);
+    chart.draw_series(
+        bubbles
+            .iter()
+            .filter(|b| (min_px..=max_px).contains(&b.px))
+            .map(|b| {
+                let color = match b.side {
+                    Side::Bid => RGBColor(81, 205, 160),
+                    Side::Ask => RGBColor(192, 80, 77),
+                };
+                let radius = (b.qty / max_qty).sqrt() * BUBBLE_MAX_RADIUS;
+                let ts =
+                    chrono::DateTime::from_timestamp_millis((b.ts + bucket / 2) as i64).unwrap();
+                Circle::new(
+                    (ts, b.px),
+                    radius.max(BUBBLE_MIN_RADIUS) as u32,
+                    color.mix(0.5).filled(),
+                )
+            }),
+    )?;
+
+    // Pulled walls, where they vanished
+    let marker_color = RGBColor(249, 115, 22);
+    let markers = spoofs
+        .iter()
+        .map(|spoof| spoof.marker(bins.price_step))
+        .filter(|&(ts, price)| (from..=to).contains(&ts) && (min_px..=max_px).contains(&price))
+        .map(|(ts, price)| {
+            (
+                chrono::DateTime::from_timestamp_millis(ts as i64).unwrap(),
+                price,
+            )
+        });
+    chart.draw_series(
+        markers
+            .clone()
+            .map(|at| Circle::new(at, MARKER_RADIUS, marker_color.stroke_width(2))),
+    )?;
+    chart.draw_series(
+        markers.map(|at| Cross::new(at, MARKER_RADIUS / 2, marker_color.stroke_width(2))),
+    )?;
+
+    // Icebergs, along the run of refills with the hidden size at its end
+    let iceberg_color = RGBColor(56, 189, 248);
+    let at_time = |ts: Time| chrono::DateTime::from_timestamp_millis(ts as i64).unwrap();
+    let visible = icebergs.iter().filter(|iceberg| {
+        let (_, price) = iceberg.marker(bins.price_step);
+        iceberg.end >= from && iceberg.start <= to && (min_px..=max_px).contains(&price)
+    });
+    for iceberg in visible {
+        let (end, price) = iceberg.marker(bins.price_step);
+        let start = iceberg.start.max(from);
+        chart.draw_series(std::iter::once(PathElement::new(
+            vec![(at_time(start), price), (at_time(end), price)],
+            iceberg_color.stroke_width(3),
+        )))?;
+        chart.draw_series(std::iter::once(Circle::new(
+            (at_time(end), price),
+            MARKER_RADIUS,
+            iceberg_color.filled(),
+        )))?;
+        chart.draw_series(std::iter::once(Text::new(
+            format!("≈{} hidden", compact(iceberg.hidden.to_f64().unwrap())),
+            (at_time(end), price),
+            ("sans-serif", 12)
+                .into_font()
+                .color(&iceberg_color)
+                .pos(Pos::new(HPos::Left, VPos::Bottom)),
+        )))?;
+    }
+
+    let plot_area = (x_range.end, y_range.start, y_range.end);
+    draw_legend(
+        &root,
+        plot_area,
+        layers,
+        darkmode,
+        &view.heatmap,
+        axis_color,
+    )?;
+
     root.present()?;
 
     Ok(())
 }
+
+/// Right margin holding the colour legend.
+const LEGEND_WIDTH: u32 = 100;
+const LEGEND_TICKS: usize = 5;
+
+/// Colour bar beside the plot, one bar per shown side (one shared bar on a
+/// single-hue map), labelled with the value each colour stands for under
+/// the current normalisation: sizes, or notional for `Notional`.
+fn draw_legend<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    (right, top, bottom): (i32, i32, i32),
+    layers: HeatmapLayers,
+    darkmode: bool,
+    heatmap: &Backing,
+    axis_color: RGBColor,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let sides: Vec<Side> = heatmap
+        .value_at(0.0, layers.clip)
+        .into_iter()
+        .map(|(side, _)| side)
+        .collect();
+    if sides.is_empty() || bottom - top < 2 {
+        return Ok(());
+    }
+    let bars = if layers.colours.blends_sides() || layers.colours == ColourMap::Diverging {
+        sides.clone()
+    } else {
+        sides[..1].to_vec()
+    };
+
+    let (bar_width, gap) = (8, 2);
+    let left = right + 8;
+    let height = (bottom - top - 1) as f64;
+    for (i, side) in bars.iter().enumerate() {
+        let x = left + i as i32 * (bar_width + gap);
+        for y in top..bottom {
+            let t = 1.0 - (y - top) as f64 / height;
+            root.draw(&Rectangle::new(
+                [(x, y), (x + bar_width, y + 1)],
+                layers.color(*side, t, darkmode).filled(),
+            ))?;
+        }
+        root.draw(&Rectangle::new(
+            [(x, top), (x + bar_width, bottom)],
+            axis_color.mix(0.45),
+        ))?;
+    }
+
+    // bid/ask values side by side when both are shown
+    let label_x = left + bars.len() as i32 * (bar_width + gap) + 2;
+    let font = ("monospace", 10).into_font().color(&axis_color.mix(0.65));
+    for tick in 0..LEGEND_TICKS {
+        let t = tick as f64 / (LEGEND_TICKS - 1) as f64;
+        let label = heatmap
+            .value_at(t, layers.clip)
+            .into_iter()
+            .map(|(_, v)| compact(v))
+            .collect::<Vec<_>>()
+            .join("/");
+        let y = top + ((1.0 - t) * height) as i32;
+        let style = font.pos(Pos::new(
+            HPos::Left,
+            match tick {
+                0 => VPos::Bottom,
+                t if t == LEGEND_TICKS - 1 => VPos::Top,
+                _ => VPos::Center,
+            },
+        ));
+        root.draw(&Text::new(label, (label_x, y), style))?;
+    }
+    Ok(())
+}
+
+/// Short label for a legend value, e.g. `0.052`, `3.4`, `12k`, `1.2M`.
+fn compact(v: f64) -> String {
+    match v.abs() {
+        a if a >= 1e6 => format!("{:.1}M", v / 1e6),
+        a if a >= 1e4 => format!("{:.0}k", v / 1e3),
+        a if a >= 1e3 => format!("{:.1}k", v / 1e3),
+        a if a >= 10.0 => format!("{:.0}", v),
+        a if a >= 1.0 => format!("{:.1}", v),
+        _ => format!("{:.3}", v),
+    }
+}
+
+/// Bubble buckets are at least this many pixel columns apart.
+const BUBBLE_SPACING: Time = 6;
+const BUBBLE_MIN_RADIUS: f64 = 2.0;
+const BUBBLE_MAX_RADIUS: f64 = 14.0;
+
+/// Executed volume of one side within one time bucket.
+#[derive(Debug, Clone, Copy)]
+struct Bubble {
+    ts: Time,
+    side: Side,
+    /// Volume weighted price.
+    px: f64,
+    qty: f64,
+}
+
+/// Trades in `from..to` summed per `bucket` ms and taker side.
+fn trade_bubbles(trades: &[data::Trade], from: Time, to: Time, bucket: Time) -> Vec<Bubble> {
+    let start = trades.partition_point(|t| t.ts < from);
+    let end = trades.partition_point(|t| t.ts < to);
+    let mut bubbles: BTreeMap<(Time, u8), Bubble> = BTreeMap::new();
+    for trade in &trades[start..end] {
+        let ts = trade.ts / bucket * bucket;
+        let (px, qty) = (trade.px.to_f64().unwrap(), trade.sz.to_f64().unwrap());
+        if qty <= 0.0 {
+            continue;
+        }
+        let bubble = bubbles.entry((ts, trade.side as u8)).or_insert(Bubble {
+            ts,
+            side: trade.side,
+            px: 0.0,
+            qty: 0.0,
+        });
+        bubble.px = (bubble.px * bubble.qty + px * qty) / (bubble.qty + qty);
+        bubble.qty += qty;
+    }
+    bubbles.into_values().collect()
+}
+
+const MARKER_RADIUS: u32 = 7;
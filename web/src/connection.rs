@@ -0,0 +1,342 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+    time::Duration,
+};
+
+use codec::Backoff;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{console, js_sys, CloseEvent, Event, MessageEvent, WebSocket};
+use yew::prelude::*;
+use yew_hooks::prelude::*;
+
+/// No message for this long marks a live stream as stale.
+const STALE_AFTER: f64 = 5_000.0;
+/// No message for this long drops the connection and dials again.
+const RECONNECT_AFTER: f64 = 15_000.0;
+
+//MARK: - Connection State ---------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionState {
+    Connecting,
+    Live,
+    Stale,
+    Reconnecting,
+    Failed,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Live => "live",
+            ConnectionState::Stale => "stale",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Failed => "failed, retrying",
+        };
+        f.write_str(label)
+    }
+}
+
+/// Shared, pollable view of a socket's `ConnectionState`.
+#[derive(Clone)]
+pub struct SocketStatus(Rc<Cell<ConnectionState>>);
+
+impl SocketStatus {
+    pub fn get(&self) -> ConnectionState {
+        self.0.get()
+    }
+
+    fn set(&self, state: ConnectionState) {
+        if self.0.get() != state {
+            console::log_1(&format!("🔌 [ws] {}", state).into());
+        }
+        self.0.set(state);
+    }
+
+    /// Mirror a state reported by a socket owned elsewhere, like the ingest
+    /// worker's; that side already logged it.
+    pub fn mirror(&self, state: ConnectionState) {
+        self.0.set(state);
+    }
+}
+impl Default for SocketStatus {
+    fn default() -> Self {
+        Self(Rc::new(Cell::new(ConnectionState::Connecting)))
+    }
+}
+impl PartialEq for SocketStatus {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl fmt::Debug for SocketStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SocketStatus").field(&self.get()).finish()
+    }
+}
+
+//MARK: - Reconnecting Socket ---------------------------------------------
+
+pub struct SocketHandlers {
+    /// Text frame received.
+    pub on_message: Box<dyn Fn(String)>,
+    /// A (re)connection opened; anything keyed to a single connection, like
+    /// sequence numbers, should be reset here.
+    pub on_open: Box<dyn Fn(&WebSocket)>,
+    /// The connection was lost and a reconnect is about to be scheduled.
+    pub on_drop: Box<dyn Fn()>,
+}
+
+/// The JS callbacks attached to one connection, kept alive for as long as
+/// it is and dropped with it.
+struct Callbacks {
+    _on_open: Closure<dyn FnMut(Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+struct SocketInner {
+    url: String,
+    epoch: u64,
+    closed: bool,
+    ws: Option<(WebSocket, Callbacks)>,
+    last_msg: f64,
+    backoff: Backoff,
+}
+
+/// A WebSocket that redials with `Backoff` when closed by the remote end and
+/// drops connections that go silent for longer than `RECONNECT_AFTER`.
+#[derive(Clone)]
+pub struct Socket {
+    status: SocketStatus,
+    inner: Rc<RefCell<SocketInner>>,
+    handlers: Rc<SocketHandlers>,
+}
+
+impl Socket {
+    pub fn open(url: String, status: SocketStatus, handlers: SocketHandlers) -> Self {
+        let socket = Self {
+            status,
+            inner: Rc::new(RefCell::new(SocketInner {
+                url,
+                epoch: 0,
+                closed: false,
+                ws: None,
+                last_msg: js_sys::Date::now(),
+                backoff: Backoff::default(),
+            })),
+            handlers: Rc::new(handlers),
+        };
+        socket.connect();
+        socket.spawn_watchdog();
+        socket
+    }
+
+    /// Close for good; no further handlers run and no reconnect is attempted.
+    pub fn close(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.closed = true;
+        inner.epoch += 1;
+        if let Some((ws, _)) = inner.ws.take() {
+            Self::detach(&ws);
+        }
+    }
+
+    fn is_current(&self, epoch: u64) -> bool {
+        let inner = self.inner.borrow();
+        !inner.closed && inner.epoch == epoch
+    }
+
+    fn detach(ws: &WebSocket) {
+        ws.set_onopen(None);
+        ws.set_onmessage(None);
+        ws.set_onerror(None);
+        ws.set_onclose(None);
+        let _ = ws.close();
+    }
+
+    fn connect(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let epoch = inner.epoch;
+        self.status.set(if inner.backoff.is_retrying() {
+            ConnectionState::Reconnecting
+        } else {
+            ConnectionState::Connecting
+        });
+
+        let ws = match WebSocket::new(&inner.url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                console::log_1(&format!("🚫 [ws] dial {} {:?}", inner.url, e).into());
+                drop(inner);
+                self.schedule_reconnect();
+                return;
+            }
+        };
+
+        let on_open = {
+            let socket = self.clone();
+            let ws_ref = ws.clone();
+            let on_open = Closure::wrap(Box::new(move |_: Event| {
+                if socket.is_current(epoch) {
+                    (socket.handlers.on_open)(&ws_ref);
+                }
+            }) as Box<dyn FnMut(Event)>);
+            ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+            on_open
+        };
+        let on_message = {
+            let socket = self.clone();
+            let on_msg = Closure::wrap(Box::new(move |event: MessageEvent| {
+                if !socket.is_current(epoch) {
+                    return;
+                }
+                if let Ok(data) = event.data().dyn_into::<js_sys::JsString>() {
+                    {
+                        let mut inner = socket.inner.borrow_mut();
+                        inner.last_msg = js_sys::Date::now();
+                        if socket.status.get() != ConnectionState::Live {
+                            inner.backoff.reset();
+                            socket.status.set(ConnectionState::Live);
+                        }
+                    }
+                    (socket.handlers.on_message)(data.into());
+                }
+            }) as Box<dyn FnMut(MessageEvent)>);
+            ws.set_onmessage(Some(on_msg.as_ref().unchecked_ref()));
+            on_msg
+        };
+        let on_error = {
+            let url = inner.url.clone();
+            let on_error = Closure::wrap(Box::new(move |_: Event| {
+                // always followed by a close event, reconnect happens there
+                console::log_1(&format!("🚫 [ws] error {}", url).into());
+            }) as Box<dyn FnMut(Event)>);
+            ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            on_error
+        };
+        let on_close = {
+            let socket = self.clone();
+            let on_close = Closure::wrap(Box::new(move |event: CloseEvent| {
+                if socket.is_current(epoch) {
+                    console::log_1(
+                        &format!("🔌 [ws] closed {} {}", event.code(), event.reason()).into(),
+                    );
+                    socket.reconnect();
+                }
+            }) as Box<dyn FnMut(CloseEvent)>);
+            ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+            on_close
+        };
+
+        let callbacks = Callbacks {
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_error: on_error,
+            _on_close: on_close,
+        };
+        inner.ws = Some((ws, callbacks));
+        inner.last_msg = js_sys::Date::now();
+    }
+
+    /// Abandon the current connection (if any) and schedule a new one.
+    pub fn reconnect(&self) {
+        // dropped only after the borrow ends, this may be running inside one
+        // of them
+        let old = {
+            let mut inner = self.inner.borrow_mut();
+            inner.epoch += 1;
+            inner.ws.take()
+        };
+        if let Some((ws, _)) = &old {
+            Self::detach(ws);
+        }
+        drop(old);
+        (self.handlers.on_drop)();
+        self.schedule_reconnect();
+    }
+
+    fn schedule_reconnect(&self) {
+        let (epoch, delay) = {
+            let mut inner = self.inner.borrow_mut();
+            (inner.epoch, inner.backoff.next_delay())
+        };
+        let failed = self.inner.borrow().backoff.has_failed();
+        self.status.set(if failed {
+            ConnectionState::Failed
+        } else {
+            ConnectionState::Reconnecting
+        });
+        console::log_1(&format!("🔌 [ws] reconnecting in {:?}", delay).into());
+
+        let socket = self.clone();
+        yew::platform::spawn_local(async move {
+            yew::platform::time::sleep(delay).await;
+            if socket.is_current(epoch) {
+                socket.connect();
+            }
+        });
+    }
+
+    fn spawn_watchdog(&self) {
+        let socket = self.clone();
+        yew::platform::spawn_local(async move {
+            loop {
+                yew::platform::time::sleep(Duration::from_millis(1000)).await;
+                let idle = {
+                    let inner = socket.inner.borrow();
+                    if inner.closed {
+                        return;
+                    }
+                    if inner.ws.is_none() {
+                        continue;
+                    }
+                    js_sys::Date::now() - inner.last_msg
+                };
+                if idle > RECONNECT_AFTER {
+                    console::log_1(&format!("🔌 [ws] silent for {}ms", idle).into());
+                    socket.reconnect();
+                } else if idle > STALE_AFTER && socket.status.get() == ConnectionState::Live {
+                    socket.status.set(ConnectionState::Stale);
+                }
+            }
+        });
+    }
+}
+
+//MARK: - Status Badge ---------------------------------------------
+
+#[derive(Properties, PartialEq)]
+pub struct StatusBadgeProps {
+    pub label: AttrValue,
+    pub status: SocketStatus,
+}
+
+#[function_component(StatusBadge)]
+pub fn status_badge(props: &StatusBadgeProps) -> Html {
+    let state = use_state_eq(|| props.status.get());
+    {
+        let state = state.clone();
+        let status = props.status.clone();
+        use_interval(move || state.set(status.get()), 500);
+    }
+
+    let color = match *state {
+        ConnectionState::Live => "bg-emerald-500",
+        ConnectionState::Connecting | ConnectionState::Reconnecting => "bg-amber-500 animate-pulse",
+        ConnectionState::Stale => "bg-orange-500",
+        ConnectionState::Failed => "bg-red-600",
+    };
+
+    html! {
+        <span class="inline-flex items-center gap-1 px-2 py-0.5 rounded text-xs font-mono border border-gray-300 dark:border-gray-700">
+            <span class={classes!("inline-block", "w-2", "h-2", "rounded-full", color)}></span>
+            {format!("{} {}", props.label, *state)}
+        </span>
+    }
+}
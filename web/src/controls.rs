@@ -0,0 +1,270 @@
+use rust_decimal::Decimal;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+use yew_hooks::prelude::*;
+
+use crate::data::{self, load_stored, save_stored, Bins, DataAction, Retention, Time, Usage};
+
+//MARK: - Bins and Retention ---------------------------------------------
+
+/// Class of the inputs and selects in the chart's toolbars.
+pub const FIELD: &str =
+    "px-1 rounded border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-900";
+
+#[function_component(BinControls)]
+pub fn bin_controls() -> Html {
+    let data_ctx = use_context::<data::DataContext>().unwrap();
+    // bins can change on their own once the tick size is known, poll them
+    let bins = use_state_eq(|| data_ctx.bins.get());
+    {
+        let (bins, data) = (bins.clone(), (*data_ctx).clone());
+        use_interval(move || bins.set(data.bins.get()), 500);
+    }
+    let bins = *bins;
+
+    let on_price_step = {
+        let data_ref = data_ctx.dispatcher();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            match input.value().parse::<Decimal>() {
+                Ok(price_step) if price_step > Decimal::ZERO => {
+                    data_ref.dispatch(DataAction::SetBins(Bins { price_step, ..bins }))
+                }
+                _ => input.set_value(&bins.price_step.to_string()),
+            }
+        })
+    };
+    let on_time_step = {
+        let data_ref = data_ctx.dispatcher();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Ok(time_step) = select.value().parse::<Time>() {
+                data_ref.dispatch(DataAction::SetBins(Bins { time_step, ..bins }));
+            }
+        })
+    };
+
+    html! {
+        <span class="flex gap-2 items-center text-xs font-mono">
+            <label class="flex gap-1 items-center">
+                {"price bin"}
+                <input
+                    type="number"
+                    min="0"
+                    step="any"
+                    class={classes!(FIELD, "w-20")}
+                    value={bins.price_step.to_string()}
+                    onchange={on_price_step}
+                />
+            </label>
+            <label class="flex gap-1 items-center">
+                {"time bin"}
+                <select class={FIELD} onchange={on_time_step}>
+                    {for Bins::TIME_STEPS.iter().map(|ms| html! {
+                        <option value={ms.to_string()} selected={*ms == bins.time_step}>
+                            {if *ms < 1000 { format!("{}ms", ms) } else { format!("{}s", ms / 1000) }}
+                        </option>
+                    })}
+                </select>
+            </label>
+        </span>
+    }
+}
+
+pub fn memory_readout(usage: Usage) -> String {
+    let count = |n: usize| match n {
+        0..=9_999 => n.to_string(),
+        10_000..=999_999 => format!("{}k", n / 1000),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    };
+    let mb = |bytes: usize| bytes / (1024 * 1024);
+    format!(
+        "{} cells · {} klines · {} trades · {} updates · heap {} + {} MB",
+        count(usage.cells),
+        count(usage.klines),
+        count(usage.trades),
+        count(usage.retained),
+        mb(usage.heap),
+        mb(usage.worker_heap)
+    )
+}
+
+#[function_component(RetentionControls)]
+pub fn retention_controls() -> Html {
+    let data_ctx = use_context::<data::DataContext>().unwrap();
+    let retention = data_ctx.retention.get();
+
+    let on_change = |update: fn(&mut Retention, &str)| {
+        let data_ref = data_ctx.dispatcher();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            let mut retention = retention;
+            update(&mut retention, &select.value());
+            data_ref.dispatch(DataAction::SetRetention(retention));
+        })
+    };
+    let on_span = on_change(|r, v| r.span = v.parse().unwrap_or(r.span));
+    let on_max_cells = on_change(|r, v| r.max_cells = v.parse().unwrap_or(r.max_cells));
+    let on_mode = on_change(|r, v| r.downsample = v == "downsample");
+
+    html! {
+        <span class="flex gap-2 items-center text-xs font-mono">
+            <label class="flex gap-1 items-center">
+                {"keep"}
+                <select class={FIELD} onchange={on_span}>
+                    {for Retention::SPANS.iter().map(|ms| html! {
+                        <option value={ms.to_string()} selected={*ms == retention.span}>
+                            {if *ms < 3_600_000 { format!("{}m", ms / 60_000) } else { format!("{}h", ms / 3_600_000) }}
+                        </option>
+                    })}
+                </select>
+            </label>
+            <label class="flex gap-1 items-center">
+                {"then"}
+                <select class={FIELD} onchange={on_mode}>
+                    <option value="downsample" selected={retention.downsample}>{"1m bins"}</option>
+                    <option value="drop" selected={!retention.downsample}>{"drop"}</option>
+                </select>
+            </label>
+            <label class="flex gap-1 items-center">
+                {"max"}
+                <select class={FIELD} onchange={on_max_cells}>
+                    {for Retention::MAX_CELLS.iter().map(|n| html! {
+                        <option value={n.to_string()} selected={*n == retention.max_cells}>
+                            {format!("{}k cells", n / 1000)}
+                        </option>
+                    })}
+                </select>
+            </label>
+        </span>
+    }
+}
+
+//MARK: - Price Band ---------------------------------------------
+
+/// Price range the chart shows. Every level stays in the heatmap, however
+/// far from the touch; the band only picks which rows are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceBand {
+    /// Fit the visible candles.
+    Candles,
+    /// Within N percent of the mid.
+    Percent(f64),
+    /// Within N ticks of the mid.
+    Ticks(u32),
+}
+
+impl PriceBand {
+    const STORAGE_KEY: &'static str = "price-band";
+    const UNITS: [&'static str; 3] = ["candles", "%", "ticks"];
+
+    /// Stored as `candles`, `%:0.5` or `ticks:200`.
+    pub fn load() -> Self {
+        let stored = load_stored(Self::STORAGE_KEY);
+        stored
+            .and_then(|stored| {
+                let (unit, n) = stored.split_once(':').unwrap_or((&stored, ""));
+                Self::from_unit(unit, n)
+            })
+            .unwrap_or(PriceBand::Candles)
+    }
+
+    pub fn save(&self) {
+        let stored = match self.width() {
+            Some(n) => format!("{}:{}", self.unit(), n),
+            None => self.unit().to_string(),
+        };
+        save_stored(Self::STORAGE_KEY, &stored);
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            PriceBand::Candles => "candles",
+            PriceBand::Percent(_) => "%",
+            PriceBand::Ticks(_) => "ticks",
+        }
+    }
+
+    fn width(&self) -> Option<String> {
+        match self {
+            PriceBand::Candles => None,
+            PriceBand::Percent(pct) => Some(pct.to_string()),
+            PriceBand::Ticks(ticks) => Some(ticks.to_string()),
+        }
+    }
+
+    /// Band in `unit` of width `n`, falling back to a default width.
+    fn from_unit(unit: &str, n: &str) -> Option<Self> {
+        match unit {
+            "candles" => Some(PriceBand::Candles),
+            "%" => Some(PriceBand::Percent(
+                n.parse().ok().filter(|pct: &f64| *pct > 0.0).unwrap_or(0.5),
+            )),
+            "ticks" => Some(PriceBand::Ticks(
+                n.parse().ok().filter(|ticks| *ticks > 0).unwrap_or(200),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Distance from the mid to either edge, for the mid-centred bands.
+    pub fn half_width(&self, mid: f64, tick: f64) -> Option<f64> {
+        match self {
+            PriceBand::Candles => None,
+            PriceBand::Percent(pct) => Some(mid * pct / 100.0),
+            PriceBand::Ticks(ticks) => Some(tick * *ticks as f64),
+        }
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct BandControlsProps {
+    pub band: PriceBand,
+    pub on_change: Callback<PriceBand>,
+}
+
+#[function_component(BandControls)]
+pub fn band_controls(props: &BandControlsProps) -> Html {
+    let band = props.band;
+    let on_unit = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(band) = PriceBand::from_unit(&select.value(), "") {
+                on_change.emit(band);
+            }
+        })
+    };
+    let on_width = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            match PriceBand::from_unit(band.unit(), &input.value()) {
+                Some(band) => on_change.emit(band),
+                None => input.set_value(&band.width().unwrap_or_default()),
+            }
+        })
+    };
+
+    html! {
+        <span class="flex gap-2 items-center text-xs font-mono">
+            <label class="flex gap-1 items-center">
+                {"prices ±"}
+                <input
+                    type="number"
+                    min="0"
+                    step="any"
+                    class={classes!(FIELD, "w-16")}
+                    value={band.width().unwrap_or_default()}
+                    disabled={band == PriceBand::Candles}
+                    onchange={on_width}
+                />
+                <select class={FIELD} onchange={on_unit}>
+                    {for PriceBand::UNITS.iter().map(|unit| html! {
+                        <option value={*unit} selected={*unit == band.unit()}>{*unit}</option>
+                    })}
+                </select>
+            </label>
+        </span>
+    }
+}
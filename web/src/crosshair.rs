@@ -0,0 +1,227 @@
+use rust_decimal::prelude::*;
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{js_sys, CanvasRenderingContext2d, HtmlCanvasElement};
+
+use crate::book::{Depth, Side};
+use crate::chart::Plot;
+use crate::data::{Data, Time};
+use crate::layers::HeatmapLayers;
+
+/// Points in the level size sparkline.
+const HISTORY_POINTS: usize = 120;
+const HISTORY_HEIGHT: f64 = 36.0;
+const LINE_HEIGHT: f64 = 15.0;
+const PADDING: f64 = 6.0;
+
+/// Redraw the crosshair overlay for the mouse at `(x, y)`, or just clear it.
+///
+/// The overlay is its own canvas on top of the chart, so hovering never
+/// repaints the heatmap.
+pub fn draw(
+    canvas: &HtmlCanvasElement,
+    darkmode: bool,
+    data: &Data,
+    layers: HeatmapLayers,
+    plot: Option<Plot>,
+    at: Option<(f64, f64)>,
+) {
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap();
+    context.clear_rect(0.0, 0.0, canvas.width() as f64, canvas.height() as f64);
+
+    let (Some(plot), Some((x, y))) = (plot, at) else {
+        return;
+    };
+    let v = plot.viewport;
+    let (right, bottom) = (plot.left + v.width as f64, plot.top + v.height as f64);
+    if x < plot.left || x >= right || y < plot.top || y >= bottom {
+        return;
+    }
+
+    // crosshair
+    let line = if darkmode {
+        "rgba(255,255,255,0.4)"
+    } else {
+        "rgba(0,0,0,0.4)"
+    };
+    context.set_stroke_style(&line.into());
+    context.set_line_width(1.0);
+    let dash = js_sys::Array::of2(&4.into(), &4.into());
+    context.set_line_dash(&dash).unwrap();
+    context.begin_path();
+    context.move_to(x.floor() + 0.5, plot.top);
+    context.line_to(x.floor() + 0.5, bottom);
+    context.move_to(plot.left, y.floor() + 0.5);
+    context.line_to(right, y.floor() + 0.5);
+    context.stroke();
+    context.set_line_dash(&js_sys::Array::new()).unwrap();
+
+    let ts = plot.time_at(x).max(0.0) as Time;
+    let Some(px) = Decimal::from_f64(plot.price_at(y)) else {
+        return;
+    };
+    let bins = data.bins.get();
+    let price_bin = bins.price_bin(px);
+    let (lines, row) = describe(data, ts, price_bin);
+
+    // tooltip box, flipped to stay inside the plot
+    context.set_font("12px monospace");
+    let text_width = lines
+        .iter()
+        .map(|l| context.measure_text(l).map_or(0.0, |m| m.width()))
+        .fold(0.0, f64::max);
+    let width = text_width.max(HISTORY_POINTS as f64) + 2.0 * PADDING;
+    let height = lines.len() as f64 * LINE_HEIGHT + HISTORY_HEIGHT + 3.0 * PADDING;
+    let left = if x + 12.0 + width > right {
+        x - 12.0 - width
+    } else {
+        x + 12.0
+    };
+    let top = if y + 12.0 + height > bottom {
+        y - 12.0 - height
+    } else {
+        y + 12.0
+    };
+
+    let (background, foreground) = if darkmode {
+        ("rgba(17,24,39,0.9)", "#e5e7eb")
+    } else {
+        ("rgba(255,255,255,0.9)", "#111827")
+    };
+    context.set_fill_style(&background.into());
+    context.fill_rect(left, top, width, height);
+    context.set_stroke_style(&line.into());
+    context.stroke_rect(left + 0.5, top + 0.5, width - 1.0, height - 1.0);
+    context.set_fill_style(&foreground.into());
+    for (i, text) in lines.iter().enumerate() {
+        let baseline = top + PADDING + (i + 1) as f64 * LINE_HEIGHT - 3.0;
+        let _ = context.fill_text(text, left + PADDING, baseline);
+    }
+
+    // the level's size across the visible window
+    if let Some(row) = row {
+        let history = top + height - PADDING - HISTORY_HEIGHT;
+        draw_history(
+            &context,
+            &row,
+            &plot,
+            layers,
+            darkmode,
+            (left + PADDING, history),
+            width - 2.0 * PADDING,
+        );
+    }
+}
+
+/// Tooltip lines for time `ts` and price bin `price_bin`, and that bin's
+/// heatmap row.
+fn describe(
+    data: &Data,
+    ts: Time,
+    price_bin: Decimal,
+) -> (Vec<String>, Option<Vec<(Time, Depth)>>) {
+    let bins = data.bins.get();
+    let time = chrono::DateTime::from_timestamp_millis(ts as i64).unwrap();
+    let mut lines = vec![
+        time.format("%H:%M:%S%.3f").to_string(),
+        format!("px  {} – {}", price_bin, price_bin + bins.price_step),
+    ];
+
+    let heatmap = data.heatmap.read().unwrap();
+    let row = heatmap.get(&price_bin);
+    let depth = row
+        .and_then(|cells| cells.range(..=ts).next_back())
+        .map(|(_, depth)| *depth)
+        .unwrap_or_default();
+    // quote size at the middle of the bin
+    let mid = price_bin + bins.price_step / Decimal::TWO;
+    for (label, side) in [("bid", Side::Bid), ("ask", Side::Ask)] {
+        let size = depth.side(side);
+        if !size.is_zero() {
+            lines.push(format!(
+                "{} {} (≈{:.0} quote)",
+                label,
+                size.normalize(),
+                size * mid
+            ));
+        }
+    }
+    if depth.is_empty() {
+        lines.push("no resting size".to_string());
+    }
+    let removals = data.removals.read().unwrap();
+    if let Some(removal) = removals
+        .get(&price_bin)
+        .and_then(|cells| cells.get(&bins.time_bin(ts)))
+    {
+        lines.push(format!(
+            "filled {} cancelled {}",
+            removal.filled.normalize(),
+            removal.cancelled.normalize()
+        ));
+    }
+
+    let klines = data.klines.read().unwrap();
+    if let Some((_, k)) = klines.range(..=ts).next_back().filter(|(_, k)| ts <= k.tc) {
+        lines.push(format!("O {} H {} L {} C {}", k.op, k.hi, k.lo, k.cl));
+        lines.push(format!("V {:.4}", k.vo));
+    }
+
+    let row = row.map(|cells| cells.iter().map(|(&t, &d)| (t, d)).collect());
+    (lines, row)
+}
+
+/// Sparkline of a price bin's bid and ask size over the plot's time window,
+/// in the heatmap's own colours.
+fn draw_history(
+    context: &CanvasRenderingContext2d,
+    row: &[(Time, Depth)],
+    plot: &Plot,
+    layers: HeatmapLayers,
+    darkmode: bool,
+    (left, top): (f64, f64),
+    width: f64,
+) {
+    let v = plot.viewport;
+    let samples: Vec<Depth> = (0..HISTORY_POINTS)
+        .map(|i| {
+            let t = v.from + (v.to - v.from) * i as Time / (HISTORY_POINTS as Time - 1);
+            let n = row.partition_point(|(ts, _)| *ts <= t);
+            n.checked_sub(1).map(|i| row[i].1).unwrap_or_default()
+        })
+        .collect();
+    let max = samples
+        .iter()
+        .flat_map(|d| [d.bid, d.ask])
+        .max()
+        .and_then(|m| m.to_f64())
+        .filter(|m| *m > 0.0);
+    let Some(max) = max else {
+        return;
+    };
+
+    for side in [Side::Bid, Side::Ask] {
+        if !layers.sides.shows(side) {
+            continue;
+        }
+        let color = layers.color(side, 1.0, darkmode);
+        let css = format!("rgb({},{},{})", color.0, color.1, color.2);
+        context.set_stroke_style(&JsValue::from(css));
+        context.begin_path();
+        for (i, depth) in samples.iter().enumerate() {
+            let x = left + width * i as f64 / (HISTORY_POINTS - 1) as f64;
+            let size = depth.side(side).to_f64().unwrap_or(0.0);
+            let y = top + HISTORY_HEIGHT * (1.0 - size / max);
+            if i == 0 {
+                context.move_to(x, y);
+            } else {
+                context.line_to(x, y);
+            }
+        }
+        context.stroke();
+    }
+}
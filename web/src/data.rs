@@ -1,43 +1,103 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::ops::Deref;
+use std::cell::{Cell, RefCell};
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, Deref};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
+use gloo_worker::{Spawnable, WorkerBridge};
 use rust_decimal::prelude::*;
-use serde_json::Value;
-use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{console, js_sys, MessageEvent, WebSocket};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::{console, js_sys};
 use yew::prelude::*;
 
-pub type Time = u64;
+use crate::book::{BookSample, Depth, Removal, Touch};
+use crate::connection::SocketStatus;
+use crate::ingest::{Batch, Ingest, IngestInput};
+use crate::recorder::{Recording, Replay};
+use crate::source::{self, Instrument, Venue};
+
+pub use codec::{Kline, OrderBookUpdate, Time, Trade};
+
+const HISTORY_SERVER_KEY: &str = "history-server";
+/// Price rows the automatic price bin aims for across the visible range.
+const AUTO_BIN_ROWS: f64 = 200.0;
+/// Script trunk builds for the `worker` binary.
+const WORKER_URL: &str = "worker.js";
 
 //MARK: - Context Interfaces ---------------------------------------------
 
+/// UI-thread view of the market data. The socket, local book and binning
+/// run in the `Ingest` worker; this side mirrors the batches it posts.
 #[derive(Clone)]
 pub struct Data {
     pub symbol: Option<String>,
+    pub venue: Venue,
+    pub status: SocketStatus,
     pub klines: Arc<RwLock<BTreeMap<Time, Kline>>>,
-    pub updates: Arc<Mutex<VecDeque<(Time, OrderBookUpdate)>>>,
-    pub heatmap: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, f64>>>>,
+    /// Executions in time order.
+    pub trades: Arc<RwLock<Vec<Trade>>>,
+    pub heatmap: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, Depth>>>>,
+    /// Size filled or cancelled per heatmap cell, only where some was.
+    pub removals: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, Removal>>>>,
+    /// The book at the end of each time bin, for the indicator pane.
+    pub samples: Arc<RwLock<BTreeMap<Time, BookSample>>>,
+    pub resyncs: Arc<RwLock<Vec<Resync>>>,
+    pub bins: Rc<Cell<Bins>>,
+    /// Earliest heatmap time changed since the chart last painted it.
+    pub dirty: Rc<Cell<Option<Time>>>,
+    /// Earliest heatmap time changed since the detectors last walked it.
+    pub rescan_from: Rc<Cell<Option<Time>>>,
+    /// Best bid and offer as of the last batch.
+    pub touch: Rc<Cell<Touch>>,
+    pub instrument: Rc<Cell<Option<Instrument>>>,
+    pub recording: Arc<Mutex<Option<Recording>>>,
+    pub replay: Rc<RefCell<Option<Replay>>>,
+    pub retention: Rc<Cell<Retention>>,
 
-    ws: Option<WebSocket>,
-    timer_handle: Option<i32>,
+    /// Retained updates and heap size last reported by the worker.
+    worker_usage: Rc<Cell<(usize, usize)>>,
+    recording_on: Rc<Cell<bool>>,
+    /// Pick the price bin from tick size once a price range is known,
+    /// until the user sets one.
+    auto_bins: Rc<Cell<bool>>,
+    session: Rc<Cell<u64>>,
+    worker: Rc<RefCell<Option<WorkerBridge<Ingest>>>>,
 }
 impl PartialEq for Data {
     fn eq(&self, other: &Self) -> bool {
-        self.symbol == other.symbol && self.ws == other.ws
+        self.symbol == other.symbol
+            && self.venue == other.venue
+            && Rc::ptr_eq(&self.worker, &other.worker)
     }
 }
 impl Default for Data {
     fn default() -> Self {
         Self {
             symbol: None,
+            venue: Venue::default(),
+            status: SocketStatus::default(),
             klines: Arc::new(RwLock::new(BTreeMap::new())),
-            updates: Arc::new(Mutex::new(VecDeque::new())),
+            trades: Arc::new(RwLock::new(Vec::new())),
             heatmap: Arc::new(RwLock::new(HashMap::new())),
+            removals: Arc::new(RwLock::new(HashMap::new())),
+            samples: Arc::new(RwLock::new(BTreeMap::new())),
+            resyncs: Arc::new(RwLock::new(Vec::new())),
+            bins: Rc::new(Cell::new(Bins::default())),
+            dirty: Rc::new(Cell::new(None)),
+            rescan_from: Rc::new(Cell::new(None)),
+            touch: Rc::new(Cell::new(Touch::default())),
+            instrument: Rc::new(Cell::new(None)),
+            recording: Arc::new(Mutex::new(None)),
+            replay: Rc::new(RefCell::new(None)),
+            retention: Rc::new(Cell::new(Retention::load())),
 
-            ws: None,
-            timer_handle: None,
+            worker_usage: Rc::new(Cell::new((0, 0))),
+            recording_on: Rc::new(Cell::new(false)),
+            auto_bins: Rc::new(Cell::new(true)),
+            session: Rc::new(Cell::new(0)),
+            worker: Rc::new(RefCell::new(None)),
         }
     }
 }
@@ -46,296 +106,786 @@ impl Reducible for Data {
     type Action = DataAction;
 
     fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
-        let new_state = self.deref().clone();
+        let mut new_state = self.deref().clone();
         match action {
-            DataAction::SetSymbol(symbol) => {
-                let mut data_ref = self.deref().clone();
+            DataAction::SetSymbol(symbol) => new_state.symbol = Some(symbol),
+            DataAction::SetVenue(venue) => new_state.venue = venue,
+            DataAction::Replay(recording) => {
+                new_state.venue = recording.venue;
+                new_state.symbol = Some(recording.symbol.clone());
+                let data_ref = new_state.clone();
                 yew::platform::spawn_local(async move {
-                    data_ref.set_symbol(symbol).await;
+                    data_ref.start_replay(recording).await;
                 });
+                return new_state.into();
+            }
+            DataAction::SetBins(bins) => {
+                new_state.auto_bins.set(false);
+                new_state.set_bins(bins);
+                return new_state.into();
+            }
+            DataAction::SetRetention(retention) => {
+                retention.save();
+                new_state.retention.set(retention);
+                new_state.send(IngestInput::SetRetention(retention));
+                return new_state.into();
+            }
+            DataAction::GoLive => {
+                new_state.subscribe();
+                return new_state.into();
             }
         };
+        if new_state.symbol.is_some()
+            && (new_state.symbol != self.symbol || new_state.venue != self.venue)
+        {
+            new_state.subscribe();
+        }
         new_state.into()
     }
 }
 #[derive(Clone)]
 pub enum DataAction {
     SetSymbol(String),
+    SetVenue(Venue),
+    /// Swap the live stream for a recorded session.
+    Replay(Rc<Recording>),
+    /// Leave replay and resubscribe to the live stream.
+    GoLive,
+    /// Change the heatmap cell size, re-binning everything retained.
+    SetBins(Bins),
+    /// Change how much history is kept.
+    SetRetention(Retention),
 }
 pub type DataContext = UseReducerHandle<Data>;
 
 //MARK: - Data Structures ---------------------------------------------
 
+/// One book diff, covering update ids `first_id..=final_id`.
 #[derive(Debug, Clone)]
-pub struct OrderBookUpdate {
-    pub ts: Time, // timestamp
-    pub sq: u64,  // sequence
-    pub px: f64,  // price
-    pub sz: f64,  // size, (-ve for offers)
+pub struct DepthUpdate {
+    pub ts: Time,
+    pub first_id: u64,              // U
+    pub final_id: u64,              // u
+    pub prev_final_id: Option<u64>, // pu, only sent by Binance futures streams
+    pub levels: Vec<OrderBookUpdate>,
+}
+
+/// Heatmap cell size: updates are bucketed by `floor(px / price_step)` and
+/// `floor(ts / time_step)`, keeping the last size per cell.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bins {
+    pub price_step: Decimal,
+    pub time_step: Time,
+}
+
+impl Default for Bins {
+    fn default() -> Self {
+        Self {
+            price_step: Decimal::ONE,
+            time_step: 1000,
+        }
+    }
 }
 
+impl Bins {
+    pub const TIME_STEPS: [Time; 5] = [500, 1_000, 5_000, 15_000, 60_000];
+
+    pub fn price_bin(&self, px: Decimal) -> Decimal {
+        (px / self.price_step).floor() * self.price_step
+    }
+
+    /// Smallest 1-2-5 multiple of `tick_size` giving at most `rows` rows
+    /// over `range`.
+    pub fn auto_price_step(tick_size: Decimal, range: f64, rows: f64) -> Decimal {
+        let tick = tick_size.to_f64().unwrap_or(1.0);
+        let ticks = (range / rows / tick).max(1.0);
+        let magnitude = 10f64.powi(ticks.log10().floor() as i32);
+        let multiple = [1.0, 2.0, 5.0, 10.0]
+            .into_iter()
+            .find(|m| m * magnitude >= ticks)
+            .unwrap_or(10.0)
+            * magnitude;
+        tick_size * Decimal::from_f64(multiple.round()).unwrap_or(Decimal::ONE)
+    }
+
+    pub fn time_bin(&self, ts: Time) -> Time {
+        (ts / self.time_step) * self.time_step
+    }
+}
+
+/// The bins of a row from `from` up to, not including, `until`.
+pub fn walk<T>(
+    cells: &BTreeMap<Time, T>,
+    from: Option<Time>,
+    until: Time,
+) -> btree_map::Range<'_, Time, T> {
+    let from = from.map_or(Bound::Unbounded, Bound::Included);
+    cells.range((from, Bound::Excluded(until)))
+}
+
+/// A window during which the local book was out of sequence and being
+/// rebuilt from a fresh snapshot; `end` is `None` while still resyncing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resync {
+    pub start: Time,
+    pub end: Option<Time>,
+}
+
+/// Full image of the resting book, from REST or the stream itself.
 #[derive(Debug, Clone)]
-pub struct Kline {
-    pub ts: Time, // open time
-    pub op: f64,
-    pub hi: f64,
-    pub lo: f64,
-    pub cl: f64,
-    pub vb: f64,  // volume buy
-    pub vs: f64,  // volume sell
-    pub tc: Time, // close time
+pub struct DepthSnapshot {
+    pub ts: Time,
+    pub last_update_id: u64,
+    pub levels: Vec<OrderBookUpdate>,
+}
+
+//MARK: - Retention ---------------------------------------------
+
+/// How much history to keep, kept in local storage.
+///
+/// Cells older than `span` behind the latest update are merged into
+/// `COARSE_MS` bins, or dropped when `downsample` is off; past `max_cells`
+/// the oldest cells are dropped whatever their resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Retention {
+    pub span: Time,
+    pub max_cells: usize,
+    pub downsample: bool,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            span: 60 * 60 * 1000,
+            max_cells: 2_000_000,
+            downsample: true,
+        }
+    }
+}
+
+impl Retention {
+    const STORAGE_KEY: &'static str = "heatmap-retention";
+    /// Time bin of downsampled history; every `Bins::TIME_STEPS` divides it.
+    pub const COARSE_MS: Time = 60_000;
+    pub const SPANS: [Time; 5] = [
+        15 * 60 * 1000,
+        30 * 60 * 1000,
+        60 * 60 * 1000,
+        2 * 60 * 60 * 1000,
+        6 * 60 * 60 * 1000,
+    ];
+    pub const MAX_CELLS: [usize; 4] = [500_000, 1_000_000, 2_000_000, 5_000_000];
+
+    /// Stored as `span ms,max cells,downsample|drop`.
+    pub fn load() -> Self {
+        let stored = load_stored(Self::STORAGE_KEY);
+        let mut retention = Self::default();
+        if let Some(stored) = stored {
+            let mut parts = stored.split(',');
+            if let Some(span) = parts.next().and_then(|s| s.parse().ok()) {
+                retention.span = span;
+            }
+            if let Some(max_cells) = parts.next().and_then(|s| s.parse().ok()) {
+                retention.max_cells = max_cells;
+            }
+            if let Some(mode) = parts.next() {
+                retention.downsample = mode != "drop";
+            }
+        }
+        retention
+    }
+
+    pub fn save(&self) {
+        let stored = format!(
+            "{},{},{}",
+            self.span,
+            self.max_cells,
+            if self.downsample {
+                "downsample"
+            } else {
+                "drop"
+            }
+        );
+        save_stored(Self::STORAGE_KEY, &stored);
+    }
+}
+
+/// Retention cut-offs, applied the same way to the worker's heatmap and to
+/// the UI thread's mirror so both stay identical. Both are whole
+/// `Retention::COARSE_MS` bins and only ever move forward.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Compaction {
+    /// Cells before this are merged into coarse bins.
+    pub coarsen_before: Time,
+    /// Cells before this are gone.
+    pub drop_before: Time,
+}
+
+impl Compaction {
+    pub fn apply(&self, heatmap: &mut HashMap<Decimal, BTreeMap<Time, Depth>>) {
+        Compaction::coarsen(heatmap, self.coarsen_before);
+        Compaction::drop_before(heatmap, self.drop_before);
+    }
+
+    /// Removals are flows rather than levels: coarse bins sum them and
+    /// nothing carries forward past `drop_before`.
+    pub fn apply_removals(&self, removals: &mut HashMap<Decimal, BTreeMap<Time, Removal>>) {
+        for cells in removals.values_mut() {
+            *cells = cells.split_off(&self.drop_before);
+            let old: Vec<_> = cells
+                .range(..self.coarsen_before)
+                .filter(|(&ts, _)| ts % Retention::COARSE_MS != 0)
+                .map(|(&ts, &removal)| (ts, removal))
+                .collect();
+            for (ts, removal) in old {
+                cells.remove(&ts);
+                let bin = ts / Retention::COARSE_MS * Retention::COARSE_MS;
+                cells.entry(bin).or_default().add(removal);
+            }
+        }
+        removals.retain(|_, cells| !cells.is_empty());
+    }
+
+    pub fn apply_klines(&self, klines: &mut BTreeMap<Time, Kline>) {
+        let old: Vec<_> = klines
+            .range(..self.coarsen_before)
+            .filter(|(&ts, _)| ts % Retention::COARSE_MS != 0)
+            .map(|(&ts, _)| ts)
+            .collect();
+        for ts in old {
+            let kline = klines.remove(&ts).unwrap();
+            let bin = ts / Retention::COARSE_MS * Retention::COARSE_MS;
+            match klines.get_mut(&bin) {
+                Some(coarse) => coarse.merge(&kline),
+                None => {
+                    klines.insert(bin, Kline { ts: bin, ..kline });
+                }
+            }
+        }
+        *klines = klines.split_off(&self.drop_before);
+    }
+
+    /// Book samples are levels like the heatmap: a coarse bin keeps the last
+    /// one in it.
+    pub fn apply_samples(&self, samples: &mut BTreeMap<Time, BookSample>) {
+        let old: Vec<_> = samples
+            .range(..self.coarsen_before)
+            .filter(|(&ts, _)| ts % Retention::COARSE_MS != 0)
+            .map(|(&ts, &sample)| (ts, sample))
+            .collect();
+        for (ts, sample) in old {
+            samples.remove(&ts);
+            samples.insert(ts / Retention::COARSE_MS * Retention::COARSE_MS, sample);
+        }
+        *samples = samples.split_off(&self.drop_before);
+    }
+
+    /// Trades before `coarsen_before` become one per coarse bin and side at
+    /// their volume weighted price; those before `drop_before` are gone.
+    pub fn apply_trades(&self, trades: &mut Vec<Trade>) {
+        let dropped = trades.partition_point(|t| t.ts < self.drop_before);
+        trades.drain(..dropped);
+        let old = trades.partition_point(|t| t.ts < self.coarsen_before);
+        if trades[..old]
+            .iter()
+            .all(|t| t.ts % Retention::COARSE_MS == 0)
+        {
+            return;
+        }
+        let mut merged: BTreeMap<(Time, u8), Trade> = BTreeMap::new();
+        for t in trades.drain(..old) {
+            let bin = t.ts / Retention::COARSE_MS * Retention::COARSE_MS;
+            let key = (bin, t.side as u8);
+            match merged.get_mut(&key) {
+                Some(m) => {
+                    let sz = m.sz + t.sz;
+                    m.px = (m.px * m.sz + t.px * t.sz) / sz;
+                    m.sz = sz;
+                }
+                None => {
+                    merged.insert(key, Trade { ts: bin, ..t });
+                }
+            }
+        }
+        trades.splice(..0, merged.into_values());
+    }
+
+    /// Keep the last depth of every coarse bin before `before`, which is
+    /// what the chart would have shown at the end of it.
+    pub fn coarsen(heatmap: &mut HashMap<Decimal, BTreeMap<Time, Depth>>, before: Time) {
+        for cells in heatmap.values_mut() {
+            let old: Vec<_> = cells
+                .range(..before)
+                .map(|(&ts, &depth)| (ts, depth))
+                .collect();
+            if old.iter().all(|(ts, _)| ts % Retention::COARSE_MS == 0) {
+                continue;
+            }
+            for (ts, _) in &old {
+                cells.remove(ts);
+            }
+            for (ts, depth) in old {
+                cells.insert(ts / Retention::COARSE_MS * Retention::COARSE_MS, depth);
+            }
+        }
+    }
+
+    /// Drop cells before `before`, carrying the depth in force at `before`
+    /// forward so the rows don't go blank.
+    pub fn drop_before(heatmap: &mut HashMap<Decimal, BTreeMap<Time, Depth>>, before: Time) {
+        for cells in heatmap.values_mut() {
+            let carried = cells.range(..before).next_back().map(|(_, depth)| *depth);
+            *cells = cells.split_off(&before);
+            if let Some(depth) = carried.filter(|d| !d.is_empty()) {
+                cells.entry(before).or_insert(depth);
+            }
+        }
+        heatmap.retain(|_, cells| !cells.is_empty());
+    }
+
+    /// Carry the cells before `before`, which no retained update can
+    /// rebuild, across to `bins`. Rows landing in one bin add up; a row a
+    /// finer step would split stays whole in the bin of its low price.
+    pub fn rebin(
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        bins: Bins,
+        before: Time,
+    ) -> HashMap<Decimal, BTreeMap<Time, Depth>> {
+        let mut rows: HashMap<Decimal, Vec<&BTreeMap<Time, Depth>>> = HashMap::new();
+        for (price, cells) in heatmap {
+            rows.entry(bins.price_bin(*price)).or_default().push(cells);
+        }
+        rows.into_iter()
+            .filter_map(|(price, old)| {
+                let times: BTreeSet<Time> = old
+                    .iter()
+                    .flat_map(|cells| cells.range(..before).map(|(&ts, _)| ts))
+                    .collect();
+                // the depth in force in each merged row at every change in any
+                let cells: BTreeMap<Time, Depth> = times
+                    .into_iter()
+                    .map(|ts| {
+                        let depth = old
+                            .iter()
+                            .filter_map(|cells| cells.range(..=ts).next_back())
+                            .fold(Depth::default(), |sum, (_, depth)| Depth {
+                                bid: sum.bid + depth.bid,
+                                ask: sum.ask + depth.ask,
+                            });
+                        (bins.time_bin(ts), depth)
+                    })
+                    .collect();
+                (!cells.is_empty()).then_some((price, cells))
+            })
+            .collect()
+    }
+
+    /// `rebin` for removals, which add up within a bin.
+    pub fn rebin_removals(
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+        bins: Bins,
+        before: Time,
+    ) -> HashMap<Decimal, BTreeMap<Time, Removal>> {
+        let mut rebinned: HashMap<Decimal, BTreeMap<Time, Removal>> = HashMap::new();
+        for (price, cells) in removals {
+            for (&ts, &removal) in cells.range(..before) {
+                rebinned
+                    .entry(bins.price_bin(*price))
+                    .or_default()
+                    .entry(bins.time_bin(ts))
+                    .or_default()
+                    .add(removal);
+            }
+        }
+        rebinned
+    }
+
+    /// Earliest coarse bin from which on at most `budget` cells remain.
+    pub fn budget_cutoff(heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>, budget: usize) -> Time {
+        let mut per_time: BTreeMap<Time, usize> = BTreeMap::new();
+        for cells in heatmap.values() {
+            for &ts in cells.keys() {
+                *per_time.entry(ts).or_default() += 1;
+            }
+        }
+        let mut kept = 0;
+        for (&ts, &n) in per_time.iter().rev() {
+            kept += n;
+            if kept > budget {
+                return (ts / Retention::COARSE_MS + 1) * Retention::COARSE_MS;
+            }
+        }
+        0
+    }
+}
+
+/// What the market data holds, for the memory readout.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub cells: usize,
+    pub klines: usize,
+    pub trades: usize,
+    /// Raw updates the worker keeps for re-binning.
+    pub retained: usize,
+    /// wasm linear memory of the UI thread and of the worker, in bytes.
+    pub heap: usize,
+    pub worker_heap: usize,
+}
+
+/// Size of this thread's wasm linear memory.
+pub fn wasm_heap() -> usize {
+    let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+    memory
+        .buffer()
+        .unchecked_into::<js_sys::ArrayBuffer>()
+        .byte_length() as usize
 }
 
 //MARK: - Data Provider ---------------------------------------------
 
 impl Data {
-    pub async fn set_symbol(&mut self, symbol: String) {
-        if let Some(sym) = &self.symbol {
-            if *sym == symbol {
-                return;
-            }
+    /// (Re)subscribe to the current venue and symbol, starting from an
+    /// empty chart.
+    pub fn subscribe(&self) {
+        let session = self.next_session();
+        if let Some(replay) = self.replay.take() {
+            replay.stop();
         }
-        self.symbol = Some(symbol);
         self.clear();
 
-        console::log_1(&format!("[data] set_symbol {:?}", self.symbol).into());
-        self.dial().await;
+        console::log_1(&format!("[data] subscribe {} {:?}", self.venue, self.symbol).into());
+        self.load_instrument(session);
+        self.send(IngestInput::Subscribe {
+            session,
+            venue: self.venue,
+            symbol: self.symbol.clone().unwrap(),
+            history: history_server(),
+        });
+    }
+
+    fn next_session(&self) -> u64 {
+        let session = self.session.get() + 1;
+        self.session.set(session);
+        session
     }
 
-    fn clear(&mut self) {
+    /// Number of sequence gaps seen on the depth stream this session,
+    /// including dropped connections.
+    pub fn gap_count(&self) -> usize {
+        self.resyncs.read().unwrap().len()
+    }
+
+    fn clear(&self) {
         self.klines.write().unwrap().clear();
-        self.updates.lock().unwrap().clear();
+        self.trades.write().unwrap().clear();
         self.heatmap.write().unwrap().clear();
+        self.removals.write().unwrap().clear();
+        self.samples.write().unwrap().clear();
+        self.resyncs.write().unwrap().clear();
+        self.touch.set(Touch::default());
+        self.mark_dirty(0);
+    }
 
-        if let Some(handle) = self.timer_handle {
-            let window = web_sys::window().expect("should have a window in this context");
-            window.clear_timeout_with_handle(handle);
-            self.timer_handle = None;
+    /// What the chart data holds right now.
+    pub fn usage(&self) -> Usage {
+        let (retained, worker_heap) = self.worker_usage.get();
+        let cells = self
+            .heatmap
+            .read()
+            .unwrap()
+            .values()
+            .map(BTreeMap::len)
+            .sum();
+        Usage {
+            cells,
+            klines: self.klines.read().unwrap().len(),
+            trades: self.trades.read().unwrap().len(),
+            retained,
+            heap: wasm_heap(),
+            worker_heap,
         }
     }
 
-    async fn dial(&mut self) {
-        if let Some(ws) = &self.ws {
-            ws.close().unwrap();
-            self.clear();
-        }
+    /// Mark the heatmap changed from `ts` on.
+    fn mark_dirty(&self, ts: Time) {
+        let dirty = self.dirty.get().map_or(ts, |d| d.min(ts));
+        self.dirty.set(Some(dirty));
+        let rescan_from = self.rescan_from.get().map_or(ts, |r| r.min(ts));
+        self.rescan_from.set(Some(rescan_from));
+    }
+
+    //MARK: - Worker ---------------------------------------------
 
-        let symbol = self.symbol.as_ref().unwrap().to_lowercase();
-        let url = "wss://data-stream.binance.vision/stream";
-        let ws = WebSocket::new(&format!(
-            "{}?streams={}@depth@100ms/{}@kline_1s",
-            url, symbol, symbol
-        ))
-        .unwrap();
+    /// Post to the ingest worker, spawning it on first use.
+    fn send(&self, input: IngestInput) {
+        let mut worker = self.worker.borrow_mut();
+        let bridge = worker.get_or_insert_with(|| {
+            let data = self.clone();
+            let bridge = Ingest::spawner()
+                .callback(move |batch| data.on_batch(batch))
+                .spawn(WORKER_URL);
+            bridge.send(IngestInput::SetRetention(self.retention.get()));
+            bridge
+        });
+        bridge.send(input);
+    }
 
+    /// Fold a batch from the worker into the UI-thread mirror.
+    fn on_batch(&self, batch: Batch) {
+        if batch.session != self.session.get() {
+            return;
+        }
+        self.status.mirror(batch.status);
         {
-            let updates = self.updates.clone();
-            let klines = self.klines.clone();
-
-            let on_msg = Closure::wrap(Box::new(move |event: MessageEvent| {
-                if let Ok(data) = event.data().dyn_into::<js_sys::JsString>() {
-                    let data_str: String = data.into();
-                    let msg: Value = serde_json::from_str(&data_str).unwrap();
-                    match msg["stream"].as_str() {
-                        Some(stream) if stream.contains("kline") => {
-                            // console::log_1(&format!("📈 [ws] kline {:?}", msg).into());
-                            if let Some(kline) = msg["data"]["k"].as_object() {
-                                let ts = kline["t"].as_u64().unwrap();
-                                let op = kline["o"].as_str().unwrap().parse::<f64>().unwrap();
-                                let hi = kline["h"].as_str().unwrap().parse::<f64>().unwrap();
-                                let lo = kline["l"].as_str().unwrap().parse::<f64>().unwrap();
-                                let cl = kline["c"].as_str().unwrap().parse::<f64>().unwrap();
-                                let vb = kline["V"].as_str().unwrap().parse::<f64>().unwrap();
-                                let vo = kline["v"].as_str().unwrap().parse::<f64>().unwrap();
-                                let tc = kline["T"].as_u64().unwrap();
-                                if let Some(_) = klines.write().unwrap().insert(
-                                    ts,
-                                    Kline {
-                                        ts,
-                                        op,
-                                        hi,
-                                        lo,
-                                        cl,
-                                        vb,
-                                        vs: vo - vb,
-                                        tc,
-                                    },
-                                ) {
-                                } else {
-                                    // let heatmap = heapmap.read().unwrap();
-                                    // let formatted_events: String = heatmap
-                                    //     .iter()
-                                    //     .map(|(price, time_sz_map)| {
-                                    //         let price_str = format!("Price: {:?}", price);
-                                    //         let time_sz_str: String = time_sz_map
-                                    //             .iter()
-                                    //             .map(|(time, sz)| {
-                                    //                 format!("  Time: {}, Size: {}", *time, *sz)
-                                    //             })
-                                    //             .collect::<Vec<String>>()
-                                    //             .join("\n");
-                                    //         format!("{}\n{}", price_str, time_sz_str)
-                                    //     })
-                                    //     .collect::<Vec<String>>()
-                                    //     .join("\n\n");
-                                    // drop(heatmap);
-                                    // console::log_1(&formatted_events.into());
-                                }
-                            }
-                        }
-                        Some(stream) if stream.contains("depth") => {
-                            // console::log_1(&format!("📊 [ws] depth {:?}", msg).into());
-                            let mut updates = updates.lock().unwrap();
-                            // let ts = chrono::Utc::now().timestamp_millis() as u64;
-                            // let sq = msg["data"]["lastUpdateId"].as_u64().unwrap();
-                            // if let Some(v) = msg["data"]["bids"].as_array() {
-                            //     for bid in v {
-                            //         let px = bid[0].as_str().unwrap().parse::<f64>().unwrap();
-                            //         let sz = bid[1].as_str().unwrap().parse::<f64>().unwrap();
-                            //         updates.push_back((ts, OrderBookUpdate { ts, sq, px, sz }));
-                            //     }
-                            // }
-                            // if let Some(v) = msg["data"]["asks"].as_array() {
-                            //     for ask in v {
-                            //         let px = ask[0].as_str().unwrap().parse::<f64>().unwrap();
-                            //         let sz = ask[1].as_str().unwrap().parse::<f64>().unwrap();
-                            //         updates.push_back((
-                            //             ts,
-                            //             OrderBookUpdate {
-                            //                 ts,
-                            //                 sq,
-                            //                 px,
-                            //                 sz: -sz,
-                            //             },
-                            //         ));
-                            //     }
-                            // }
-                            let ts = msg["data"]["E"].as_u64().unwrap();
-                            let sq = msg["data"]["u"].as_u64().unwrap();
-                            if let Some(v) = msg["data"]["b"].as_array() {
-                                for bid in v {
-                                    let px = bid[0].as_str().unwrap().parse::<f64>().unwrap();
-                                    let sz = bid[1].as_str().unwrap().parse::<f64>().unwrap();
-                                    updates.push_back((ts, OrderBookUpdate { ts, sq, px, sz }));
-                                }
-                            }
-                            if let Some(v) = msg["data"]["a"].as_array() {
-                                for ask in v {
-                                    let px = ask[0].as_str().unwrap().parse::<f64>().unwrap();
-                                    let sz = ask[1].as_str().unwrap().parse::<f64>().unwrap();
-                                    updates.push_back((
-                                        ts,
-                                        OrderBookUpdate {
-                                            ts,
-                                            sq,
-                                            px,
-                                            sz: -sz,
-                                        },
-                                    ));
-                                }
-                            }
-                        }
-                        _ => {
-                            console::log_1(&format!("🚫 [ws] stream {:?}", msg).into());
-                        }
-                    }
-                }
-            }) as Box<dyn FnMut(MessageEvent)>);
-            ws.set_onmessage(Some(on_msg.as_ref().unchecked_ref()));
-            on_msg.forget();
+            let mut heatmap = self.heatmap.write().unwrap();
+            let mut removals = self.removals.write().unwrap();
+            if batch.rebuilt {
+                heatmap.clear();
+                removals.clear();
+                self.mark_dirty(0);
+            }
+            for (price, time, depth) in batch.cells {
+                heatmap.entry(price).or_default().insert(time, depth);
+                self.mark_dirty(time);
+            }
+            for (price, time, removal) in batch.removals {
+                removals.entry(price).or_default().insert(time, removal);
+                self.mark_dirty(time);
+            }
+        }
+        let mut samples = self.samples.write().unwrap();
+        if batch.rebuilt {
+            samples.clear();
+        }
+        samples.extend(batch.samples);
+        let mut klines = self.klines.write().unwrap();
+        klines.extend(batch.klines.into_iter().map(|k| (k.ts, k)));
+        let mut trades = self.trades.write().unwrap();
+        trades.extend(batch.trades);
+        if !trades.is_sorted_by_key(|t| t.ts) {
+            trades.sort_by_key(|t| t.ts);
+        }
+        if let Some(compaction) = batch.compaction {
+            compaction.apply(&mut self.heatmap.write().unwrap());
+            compaction.apply_removals(&mut self.removals.write().unwrap());
+            compaction.apply_samples(&mut samples);
+            compaction.apply_klines(&mut klines);
+            compaction.apply_trades(&mut trades);
+            self.mark_dirty(0);
+        }
+        drop((samples, klines, trades));
+        self.worker_usage.set((batch.retained, batch.heap));
+        if let Some(resyncs) = batch.resyncs {
+            *self.resyncs.write().unwrap() = resyncs;
         }
+        self.touch.set(batch.touch);
+        if !batch.recorded.is_empty() {
+            if let Some(recording) = self.recording.lock().unwrap().as_mut() {
+                recording.entries.extend(batch.recorded);
+            }
+        }
+        self.apply_auto_bins();
+    }
 
-        self.schedule_processing();
-        self.ws = Some(ws);
+    fn set_bins(&self, bins: Bins) {
+        self.bins.set(bins);
+        self.send(IngestInput::SetBins(bins));
     }
 
-    fn schedule_processing(&mut self) {
-        let updates = self.updates.clone();
-        let heatmap = self.heatmap.clone();
-        let closure = Closure::<dyn Fn()>::new(Box::new(move || {
-            let mut queue = updates.lock().unwrap();
-            if !queue.is_empty() {
-                let mut heatmap = heatmap.write().unwrap();
-                Data::process_updates(&mut *heatmap, &mut *queue);
-                drop(heatmap);
+    //MARK: - Instrument ---------------------------------------------
+
+    /// Fetch the symbol's tick size in the background; until it arrives the
+    /// heatmap keeps its current bins.
+    fn load_instrument(&self, session: u64) {
+        self.instrument.set(None);
+        self.auto_bins.set(true);
+        let source = self.venue.source();
+        let symbol = self.symbol.clone().unwrap();
+        let Some(url) = source.instrument_url(&symbol) else {
+            return;
+        };
+        let data = self.clone();
+        yew::platform::spawn_local(async move {
+            let instrument = source::fetch_text(&url)
+                .await
+                .and_then(|body| source.parse_instrument(&symbol, &body));
+            match instrument {
+                Ok(instrument) if data.session.get() == session => {
+                    console::log_1(
+                        &format!("📐 [rest] {} tick size {}", symbol, instrument.tick_size).into(),
+                    );
+                    data.instrument.set(Some(instrument));
+                }
+                Ok(_) => {}
+                Err(e) => console::log_1(&format!("🚫 [rest] instrument {:?}", e).into()),
             }
-        }));
+        });
+    }
+
+    /// Once both the tick size and a price range are known, pick a price bin
+    /// that is a whole number of ticks and gives about `AUTO_BIN_ROWS` rows.
+    fn apply_auto_bins(&self) {
+        if !self.auto_bins.get() {
+            return;
+        }
+        let Some(instrument) = self.instrument.get() else {
+            return;
+        };
+        let range = {
+            let klines = self.klines.read().unwrap();
+            let Some(last) = klines.values().last() else {
+                return;
+            };
+            let lo = klines.values().map(|k| k.lo).fold(f64::INFINITY, f64::min);
+            let hi = klines
+                .values()
+                .map(|k| k.hi)
+                .fold(f64::NEG_INFINITY, f64::max);
+            // the chart pads the kline range by half on either side
+            (2.0 * (hi - lo)).max(last.cl * 0.002)
+        };
+        self.auto_bins.set(false);
+        let bins = Bins {
+            price_step: Bins::auto_price_step(instrument.tick_size, range, AUTO_BIN_ROWS),
+            ..self.bins.get()
+        };
+        if bins != self.bins.get() {
+            self.set_bins(bins);
+        }
+    }
+
+    //MARK: - Recording ---------------------------------------------
+
+    pub fn is_recording(&self) -> bool {
+        self.recording_on.get()
+    }
 
-        let window = web_sys::window().expect("should have a window in this context");
-        let handle = window
-            .set_interval_with_callback_and_timeout_and_arguments_0(
-                closure.as_ref().unchecked_ref(),
-                300, // Trigger after 1000 milliseconds
+    /// Entries in the current (or last) recording.
+    pub fn recorded(&self) -> usize {
+        let recording = self.recording.lock().unwrap();
+        recording.as_ref().map_or(0, |r| r.entries.len())
+    }
+
+    /// Start capturing the live stream into a fresh recording.
+    pub fn start_recording(&self) {
+        let Some(symbol) = self.symbol.clone() else {
+            return;
+        };
+        *self.recording.lock().unwrap() = Some(Recording::new(self.venue, symbol));
+        self.recording_on.set(true);
+        self.send(IngestInput::Record(true));
+    }
+
+    /// Stop capturing; the recording is kept for download.
+    pub fn stop_recording(&self) {
+        self.recording_on.set(false);
+        self.send(IngestInput::Record(false));
+    }
+
+    //MARK: - Replay ---------------------------------------------
+
+    async fn start_replay(&self, recording: Rc<Recording>) {
+        let session = self.next_session();
+        if let Some(replay) = self.replay.take() {
+            replay.stop();
+        }
+        self.stop_recording();
+        self.clear();
+        console::log_1(
+            &format!(
+                "⏯ [replay] {} {} entries {}",
+                recording.venue,
+                recording.symbol,
+                recording.entries.len()
             )
-            .unwrap();
-        closure.forget();
-        self.timer_handle = Some(handle);
-    }
-
-    fn process_updates(
-        heatmap: &mut HashMap<Decimal, BTreeMap<Time, f64>>,
-        updates: &mut VecDeque<(u64, OrderBookUpdate)>,
-    ) {
-        let price_step = 1.0;
-        let time_step = 1000;
-
-        while let Some(update) = updates.pop_front() {
-            // console::log_1(&format!("update {:?}", update).into());
-            let price_bin = (update.1.px / price_step).floor() * price_step;
-            let time_bin = (update.0 / time_step) * time_step;
-            // console::log_1(&format!("price {:?} time {:?}", price_bin, time_bin).into());
-
-            let bin = heatmap
-                .entry(Decimal::from_f64(price_bin).unwrap())
-                .or_insert_with(BTreeMap::new);
-
-            if let Some((&last_time_bin, &last_size)) = bin.iter().last() {
-                if last_size == update.1.sz && !last_size.is_zero() {
-                    continue;
-                }
-                if last_time_bin == time_bin && !update.1.sz.is_zero() {
-                    *bin.get_mut(&last_time_bin).unwrap() = update.1.sz;
-                } else {
-                    bin.insert(time_bin, update.1.sz);
+            .into(),
+        );
+
+        self.load_instrument(session);
+        self.send(IngestInput::Replay { session });
+
+        let replay = Replay::new(recording);
+        *self.replay.borrow_mut() = Some(replay.clone());
+        self.run_replay(replay).await;
+    }
+
+    /// Post recorded entries to the worker, which processes them like the
+    /// live stream, on the recording's own clock scaled by the replay speed.
+    async fn run_replay(&self, replay: Replay) {
+        const TICK: Duration = Duration::from_millis(50);
+        const MAX_BATCH: usize = 2_000;
+
+        loop {
+            yew::platform::time::sleep(TICK).await;
+            if replay.is_stopped() {
+                return;
+            }
+            if let Some(to) = replay.take_seek() {
+                if to < replay.position() {
+                    // start over on a fresh session, dropping batches in flight
+                    let session = self.next_session();
+                    self.clear();
+                    self.send(IngestInput::Replay { session });
+                    replay.rewind();
                 }
-            } else {
-                // Insert new entry if the time bin is different
-                bin.insert(time_bin, update.1.sz);
+                self.feed(&replay, to);
+                continue;
+            }
+            if replay.is_paused() || replay.is_finished() {
+                continue;
+            }
+            let until = match replay.speed().factor() {
+                Some(factor) => replay.position() + TICK.as_millis() as Time * factor,
+                None => replay.lookahead(MAX_BATCH).unwrap_or_default(),
             };
+            self.feed(&replay, until);
         }
+    }
 
-        if heatmap.len() > 200 {
-            // Reject outliers
-            let prices: Vec<_> = heatmap
-                .keys()
-                .into_iter()
-                .map(|r| r.to_f64().unwrap())
-                .collect();
-            let m = median(&prices);
-            let deviations: Vec<_> = prices.iter().map(|&x| (x - m).abs()).collect();
-            let mdev = median(&deviations);
-            let to_remove: Vec<_> = prices
-                .iter()
-                .filter(|&r| {
-                    let price = r.to_f64().unwrap();
-                    let dev = (price - m).abs();
-                    dev / mdev > 2.0
-                })
-                .map(|r| Decimal::from_f64(*r).unwrap())
-                .collect();
-            console::log_1(&format!("outliers {:?}", to_remove.len()).into());
-            for price in to_remove {
-                heatmap.remove(&price);
-            }
+    fn feed(&self, replay: &Replay, until: Time) {
+        let records = replay.advance(until).to_vec();
+        if !records.is_empty() {
+            self.send(IngestInput::Feed(records));
         }
     }
 }
 
+//MARK: - Local Storage ---------------------------------------------
+
+/// The value kept under `key`, `None` when there is none or no storage at
+/// all, as in private browsing or a sandboxed iframe. Settings then load
+/// their defaults.
+pub fn load_stored(key: &str) -> Option<String> {
+    let local_storage = web_sys::window()?.local_storage().ok()??;
+    local_storage.get_item(key).ok()?
+}
+
+/// Keep `value` under `key`; without storage it lasts until the page closes.
+pub fn save_stored(key: &str, value: &str) {
+    let local_storage = web_sys::window().and_then(|w| w.local_storage().ok().flatten());
+    if let Some(local_storage) = local_storage {
+        if let Err(e) = local_storage.set_item(key, value) {
+            console::log_1(&format!("🚫 [storage] {} {:?}", key, e).into());
+        }
+    }
+}
+
+/// Base url of the companion server, kept in local storage.
+pub fn history_server() -> Option<String> {
+    load_stored(HISTORY_SERVER_KEY).filter(|url| !url.trim().is_empty())
+}
+
+pub fn set_history_server(url: &str) {
+    save_stored(HISTORY_SERVER_KEY, url.trim());
+}
+
+//MARK: - Statistics ---------------------------------------------
+
 pub fn median(data: &[f64]) -> f64 {
     let mut sorted_data = data.to_vec();
     sorted_data.sort_by(|a, b| a.partial_cmp(b).unwrap());
     let mid = sorted_data.len() / 2;
-    if sorted_data.len() % 2 == 0 {
+    if sorted_data.len().is_multiple_of(2) {
         (sorted_data[mid - 1] + sorted_data[mid]) / 2.0
     } else {
         sorted_data[mid]
@@ -359,10 +909,137 @@ fn histogram(data: &[f64], bins: usize) -> (Vec<usize>, Vec<f64>) {
     for &value in data {
         let mut bin_index = (value - min_value) / bin_width;
         // adjust bin_index to ensure it falls within the valid range
-        bin_index = bin_index.max(0.0).min((bins - 1) as f64);
+        bin_index = bin_index.clamp(0.0, (bins - 1) as f64);
         let bin_index = bin_index as usize;
         bin_counts[bin_index] += 1;
     }
 
     (bin_counts, bin_boundaries)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn depth(bid: &str) -> Depth {
+        Depth {
+            bid: dec(bid),
+            ask: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn auto_price_step_rounds_up_to_1_2_5() {
+        let tick = dec("0.01");
+        // 100 over 100 rows is 100 ticks a row
+        assert_eq!(Bins::auto_price_step(tick, 100.0, 100.0), dec("1.00"));
+        assert_eq!(Bins::auto_price_step(tick, 150.0, 100.0), dec("2.00"));
+        assert_eq!(Bins::auto_price_step(tick, 300.0, 100.0), dec("5.00"));
+        assert_eq!(Bins::auto_price_step(tick, 600.0, 100.0), dec("10.00"));
+    }
+
+    #[test]
+    fn auto_price_step_is_never_below_the_tick() {
+        assert_eq!(Bins::auto_price_step(dec("0.5"), 1.0, 100.0), dec("0.5"));
+    }
+
+    #[test]
+    fn compaction_coarsens_keeping_the_last_depth() {
+        let minute = Retention::COARSE_MS;
+        let cells = BTreeMap::from([
+            (1_000, depth("1")),
+            (2_000, depth("2")),
+            (minute + 1_000, depth("3")),
+        ]);
+        let mut heatmap = HashMap::from([(dec("100"), cells)]);
+        let compaction = Compaction {
+            coarsen_before: minute,
+            drop_before: 0,
+        };
+        compaction.apply(&mut heatmap);
+        let cells = &heatmap[&dec("100")];
+        assert_eq!(
+            cells.iter().map(|(&ts, d)| (ts, d.bid)).collect::<Vec<_>>(),
+            vec![(0, dec("2")), (minute + 1_000, dec("3"))]
+        );
+    }
+
+    #[test]
+    fn compaction_drops_carrying_the_depth_forward() {
+        let minute = Retention::COARSE_MS;
+        let mut heatmap = HashMap::from([
+            (
+                dec("100"),
+                BTreeMap::from([(0, depth("1")), (minute + 1_000, depth("2"))]),
+            ),
+            (
+                dec("101"),
+                BTreeMap::from([(0, depth("1")), (1_000, depth("0"))]),
+            ),
+        ]);
+        let compaction = Compaction {
+            coarsen_before: 0,
+            drop_before: minute,
+        };
+        compaction.apply(&mut heatmap);
+        assert_eq!(
+            heatmap[&dec("100")].keys().copied().collect::<Vec<_>>(),
+            vec![minute, minute + 1_000]
+        );
+        assert_eq!(heatmap[&dec("100")][&minute].bid, dec("1"));
+        // an emptied level leaves nothing to carry
+        assert!(!heatmap.contains_key(&dec("101")));
+    }
+
+    #[test]
+    fn rebin_merges_compacted_rows() {
+        let minute = Retention::COARSE_MS;
+        let heatmap = HashMap::from([
+            (
+                dec("100"),
+                BTreeMap::from([(0, depth("1")), (minute, depth("2"))]),
+            ),
+            (dec("101"), BTreeMap::from([(minute, depth("3"))])),
+            // rebuilt from the retained updates instead
+            (dec("102"), BTreeMap::from([(2 * minute, depth("4"))])),
+        ]);
+        let bins = Bins {
+            price_step: dec("2"),
+            time_step: 1_000,
+        };
+        let rebinned = Compaction::rebin(&heatmap, bins, 2 * minute);
+        assert_eq!(rebinned.len(), 1);
+        assert_eq!(
+            rebinned[&dec("100")]
+                .iter()
+                .map(|(&ts, d)| (ts, d.bid))
+                .collect::<Vec<_>>(),
+            vec![(0, dec("1")), (minute, dec("5"))]
+        );
+    }
+
+    #[test]
+    fn rebin_removals_adds_them_up() {
+        let removal = |filled: &str| Removal {
+            filled: dec(filled),
+            cancelled: Decimal::ZERO,
+        };
+        let removals = HashMap::from([
+            (dec("100"), BTreeMap::from([(0, removal("1"))])),
+            (
+                dec("101"),
+                BTreeMap::from([(0, removal("2")), (60_000, removal("5"))]),
+            ),
+        ]);
+        let bins = Bins {
+            price_step: dec("2"),
+            time_step: 1_000,
+        };
+        let rebinned = Compaction::rebin_removals(&removals, bins, 60_000);
+        assert_eq!(rebinned[&dec("100")], BTreeMap::from([(0, removal("3"))]));
+    }
+}
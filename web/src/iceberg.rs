@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use rust_decimal::prelude::*;
+use yew::prelude::*;
+
+use crate::book::{Depth, Removal, Side};
+use crate::data::{walk, Time};
+
+/// Refills needed before a level counts as an iceberg.
+const MIN_REFILLS: usize = 3;
+/// Refills further apart than this start a new run.
+const MAX_GAP_MS: Time = 30_000;
+/// A level refilled when it is back to at least this share of what was
+/// shown before the fills.
+const REFILL_SHARE: Decimal = Decimal::from_parts(9, 0, 0, false, 1);
+/// A refill continues a run only while the size it showed is within this
+/// share of the run's median display.
+const REFILL_TOLERANCE: Decimal = Decimal::from_parts(25, 0, 0, false, 2);
+/// Detections kept, newest first.
+const MAX_ICEBERGS: usize = 100;
+
+//MARK: - Detector ---------------------------------------------
+
+/// A level traded through again and again while its displayed size kept
+/// coming back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Iceberg {
+    pub side: Side,
+    /// Low price of the heatmap row.
+    pub price: Decimal,
+    /// First and last time bin with a refill.
+    pub start: Time,
+    pub end: Time,
+    pub refills: usize,
+    /// Median size shown before each refill.
+    pub display: Decimal,
+    /// Executed beyond what the book showed going.
+    pub hidden: Decimal,
+}
+
+impl Iceberg {
+    /// Where the chart marks it: the middle of its row of `step` at the last
+    /// refill.
+    pub fn marker(&self, step: Decimal) -> (Time, f64) {
+        let price = (self.price + step / Decimal::TWO).to_f64().unwrap();
+        (self.end, price)
+    }
+}
+
+/// One bin where a level traded through and came back.
+#[derive(Debug, Clone, Copy)]
+struct Refill {
+    ts: Time,
+    side: Side,
+    /// Size shown going into the bin.
+    shown: Decimal,
+    hidden: Decimal,
+}
+
+/// Finds runs of refills: time bins where trades filled size at a level
+/// yet it ended the bin back near what it showed before, each showing about
+/// the same size. Whatever the fills took beyond the displayed decrease
+/// came out of hidden size.
+///
+/// Walks each time bin once, as it closes, keeping the runs still open
+/// between scans. Removal cells don't carry a side; the level's side is
+/// whichever one was resting in the row.
+#[derive(Default)]
+pub struct IcebergDetector {
+    /// Bins before this have been walked.
+    scanned_to: Option<Time>,
+    runs: HashMap<Decimal, Vec<Refill>>,
+    icebergs: Vec<Iceberg>,
+}
+
+impl IcebergDetector {
+    /// Walk the bins closed since the last scan, up to `until`. Anything
+    /// changed at or before a bin already walked starts over from the
+    /// beginning.
+    pub fn scan(
+        &mut self,
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+        until: Time,
+        changed: Option<Time>,
+    ) {
+        if changed.is_some_and(|c| self.scanned_to.is_some_and(|s| c < s)) {
+            *self = Self::default();
+        }
+        let from = self.scanned_to;
+        if from.is_some_and(|from| from >= until) {
+            return;
+        }
+        for (price, fills) in removals {
+            let Some(cells) = heatmap.get(price) else {
+                continue;
+            };
+            for (&ts, removal) in walk(fills, from, until).filter(|(_, r)| !r.filled.is_zero()) {
+                // what the level showed going into the bin, and how it left it
+                let Some((_, before)) = cells.range(..ts).next_back() else {
+                    continue;
+                };
+                let Some((_, after)) = cells.range(..=ts).next_back() else {
+                    continue;
+                };
+                let side = if before.bid >= before.ask {
+                    Side::Bid
+                } else {
+                    Side::Ask
+                };
+                let (shown, left) = (before.side(side), after.side(side));
+                if shown.is_zero() || left < shown * REFILL_SHARE {
+                    continue;
+                }
+                let hidden =
+                    (removal.filled - (shown - left).max(Decimal::ZERO)).max(Decimal::ZERO);
+                let run = self.runs.entry(*price).or_default();
+                let continues = run.last().is_some_and(|last| {
+                    let display = display(run);
+                    last.side == side
+                        && ts - last.ts <= MAX_GAP_MS
+                        && (shown - display).abs() <= display * REFILL_TOLERANCE
+                });
+                if !continues {
+                    flush(&mut self.icebergs, *price, run);
+                }
+                run.push(Refill {
+                    ts,
+                    side,
+                    shown,
+                    hidden,
+                });
+            }
+        }
+        // runs no refill can extend any more
+        self.runs.retain(|price, run| {
+            if run.last().is_some_and(|last| until - last.ts > MAX_GAP_MS) {
+                flush(&mut self.icebergs, *price, run);
+            }
+            !run.is_empty()
+        });
+        self.icebergs.sort_by_key(|i| std::cmp::Reverse(i.end));
+        self.icebergs.truncate(MAX_ICEBERGS);
+        self.scanned_to = Some(until);
+    }
+
+    /// Detections so far, runs still open included, newest first.
+    pub fn icebergs(&self) -> Vec<Iceberg> {
+        let mut icebergs: Vec<_> = self
+            .runs
+            .iter()
+            .filter_map(|(price, run)| iceberg(*price, run))
+            .chain(self.icebergs.iter().cloned())
+            .collect();
+        icebergs.sort_by_key(|i| std::cmp::Reverse(i.end));
+        icebergs.truncate(MAX_ICEBERGS);
+        icebergs
+    }
+}
+
+/// Median size shown before each refill of `run`.
+fn display(run: &[Refill]) -> Decimal {
+    let mut shown: Vec<_> = run.iter().map(|r| r.shown).collect();
+    shown.sort();
+    shown[shown.len() / 2]
+}
+
+/// `run` as an iceberg, if it has enough refills.
+fn iceberg(price: Decimal, run: &[Refill]) -> Option<Iceberg> {
+    (run.len() >= MIN_REFILLS).then(|| Iceberg {
+        side: run[0].side,
+        price,
+        start: run[0].ts,
+        end: run[run.len() - 1].ts,
+        refills: run.len(),
+        display: display(run),
+        hidden: run.iter().map(|r| r.hidden).sum(),
+    })
+}
+
+/// Record `run` if it has enough refills, then start over.
+fn flush(icebergs: &mut Vec<Iceberg>, price: Decimal, run: &mut Vec<Refill>) {
+    icebergs.extend(iceberg(price, run));
+    run.clear();
+}
+
+//MARK: - Panel ---------------------------------------------
+
+#[derive(Properties, PartialEq)]
+pub struct IcebergPanelProps {
+    pub icebergs: Rc<Vec<Iceberg>>,
+    /// A detection was clicked.
+    pub on_select: Callback<Iceberg>,
+}
+
+/// Detected icebergs, newest first.
+#[function_component(IcebergPanel)]
+pub fn iceberg_panel(props: &IcebergPanelProps) -> Html {
+    html! {
+        <div class="flex flex-col h-full text-xs font-mono">
+            <div class="p-2 border-b border-gray-300 dark:border-gray-700">
+                <span class="font-bold">{format!("icebergs ({})", props.icebergs.len())}</span>
+            </div>
+            <ul class="flex-1 overflow-auto">
+                {for props.icebergs.iter().map(|iceberg| {
+                    let onclick = {
+                        let (on_select, iceberg) = (props.on_select.clone(), iceberg.clone());
+                        Callback::from(move |_: MouseEvent| on_select.emit(iceberg.clone()))
+                    };
+                    let time = chrono::DateTime::from_timestamp_millis(iceberg.start as i64)
+                        .map(|t| t.format("%H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    let (label, color) = match iceberg.side {
+                        Side::Bid => ("bid", "text-emerald-500"),
+                        Side::Ask => ("ask", "text-red-500"),
+                    };
+                    html! {
+                        <li class="px-2 py-1 cursor-pointer hover:bg-gray-200 dark:hover:bg-gray-800" {onclick}>
+                            <div class="flex gap-2">
+                                <span>{time}</span>
+                                <span class={color}>{label}</span>
+                                <span>{iceberg.price.normalize().to_string()}</span>
+                            </div>
+                            <div class="text-gray-500">
+                                {format!(
+                                    "{} refills of ~{} · ≈{} hidden",
+                                    iceberg.refills,
+                                    iceberg.display.normalize(),
+                                    iceberg.hidden.normalize()
+                                )}
+                            </div>
+                        </li>
+                    }
+                })}
+            </ul>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    /// Bids on row 100 every second for a minute, showing each size from
+    /// its time on.
+    fn heatmap(sizes: &[(Time, &str)]) -> HashMap<Decimal, BTreeMap<Time, Depth>> {
+        let cells = (0..60)
+            .map(|i| {
+                let ts = i * 1_000;
+                let (_, size) = sizes.iter().rev().find(|(from, _)| *from <= ts).unwrap();
+                let depth = Depth {
+                    bid: dec(size),
+                    ask: Decimal::ZERO,
+                };
+                (ts, depth)
+            })
+            .collect();
+        HashMap::from([(Decimal::from(100), cells)])
+    }
+
+    fn fills(fills: &[(Time, &str)]) -> HashMap<Decimal, BTreeMap<Time, Removal>> {
+        let cells = fills
+            .iter()
+            .map(|&(ts, filled)| {
+                let removal = Removal {
+                    filled: dec(filled),
+                    cancelled: Decimal::ZERO,
+                };
+                (ts, removal)
+            })
+            .collect();
+        HashMap::from([(Decimal::from(100), cells)])
+    }
+
+    fn scan(
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+    ) -> Vec<Iceberg> {
+        let mut detector = IcebergDetector::default();
+        detector.scan(heatmap, removals, 60_000, None);
+        detector.icebergs()
+    }
+
+    #[test]
+    fn flags_a_level_refilled_min_refills_times() {
+        let heatmap = heatmap(&[(0, "10")]);
+        let removals = fills(&[(2_000, "8"), (3_000, "8"), (4_000, "8")]);
+        let icebergs = scan(&heatmap, &removals);
+        assert_eq!(icebergs.len(), 1);
+        let iceberg = &icebergs[0];
+        assert_eq!(
+            (iceberg.side, iceberg.price),
+            (Side::Bid, Decimal::from(100))
+        );
+        assert_eq!(
+            (iceberg.start, iceberg.end, iceberg.refills),
+            (2_000, 4_000, 3)
+        );
+        assert_eq!(iceberg.display, dec("10"));
+        // two refills are not enough
+        let removals = fills(&[(2_000, "8"), (3_000, "8")]);
+        assert!(scan(&heatmap, &removals).is_empty());
+    }
+
+    #[test]
+    fn hidden_is_what_fills_took_beyond_the_displayed_decrease() {
+        // 10 shown, 9.5 left: 0.5 came off the display, 7.5 out of hiding
+        let heatmap = heatmap(&[(0, "10"), (2_000, "9.5"), (3_000, "10"), (4_000, "9.6")]);
+        let removals = fills(&[(2_000, "8"), (3_000, "8"), (4_000, "0.2")]);
+        let icebergs = scan(&heatmap, &removals);
+        // a fill smaller than the displayed decrease hides nothing
+        assert_eq!(icebergs[0].hidden, dec("7.5") + dec("8") + Decimal::ZERO);
+    }
+
+    #[test]
+    fn a_gap_past_max_gap_ms_breaks_the_run() {
+        let heatmap = heatmap(&[(0, "10")]);
+        let late = 3_000 + MAX_GAP_MS + 1_000;
+        let removals = fills(&[(2_000, "8"), (3_000, "8"), (late, "8")]);
+        assert!(scan(&heatmap, &removals).is_empty());
+    }
+
+    #[test]
+    fn a_display_outside_the_tolerance_breaks_the_run() {
+        let removals = fills(&[(2_000, "8"), (3_000, "8"), (4_000, "8")]);
+        // 20 shown going into the third refill, twice the run's median
+        let heatmap = heatmap(&[(0, "10"), (3_000, "20")]);
+        assert!(scan(&heatmap, &removals).is_empty());
+        // 11 is within REFILL_TOLERANCE of 10
+        let heatmap = self::heatmap(&[(0, "10"), (3_000, "11")]);
+        assert_eq!(scan(&heatmap, &removals).len(), 1);
+    }
+
+    #[test]
+    fn scans_incrementally() {
+        let heatmap = heatmap(&[(0, "10")]);
+        let removals = fills(&[(2_000, "8"), (3_000, "8"), (4_000, "8"), (5_000, "8")]);
+        let mut detector = IcebergDetector::default();
+        detector.scan(&heatmap, &removals, 4_000, None);
+        assert!(detector.icebergs().is_empty());
+        // the open run reaches MIN_REFILLS and shows up before it ends
+        detector.scan(&heatmap, &removals, 5_000, Some(4_000));
+        assert_eq!(detector.icebergs()[0].refills, 3);
+        detector.scan(&heatmap, &removals, 60_000, None);
+        assert_eq!(detector.icebergs(), scan(&heatmap, &removals));
+        assert_eq!(detector.icebergs()[0].refills, 4);
+        // a change behind the scan walks it all again
+        detector.scan(&heatmap, &fills(&[]), 60_000, Some(0));
+        assert!(detector.icebergs().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use plotters::prelude::*;
+use plotters::style::full_palette::GREY;
+use plotters_canvas::CanvasBackend;
+use rust_decimal::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlSelectElement};
+use yew::prelude::*;
+
+use crate::book::BookSample;
+use crate::chart::Plot;
+use crate::data::{load_stored, save_stored, Data};
+
+/// Height of the pane under the chart, in pixels.
+pub const PANE_HEIGHT: u32 = 150;
+/// Width of the value labels left of each row; the chart's own is the same
+/// so the time axes line up.
+const Y_LABEL_AREA: u32 = 30;
+
+//MARK: - Settings ---------------------------------------------
+
+/// Indicator pane options, kept in local storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndicatorSettings {
+    /// Levels per side the imbalance is taken over, one of
+    /// `BookSample::LEVELS`.
+    pub levels: usize,
+}
+
+impl Default for IndicatorSettings {
+    fn default() -> Self {
+        Self { levels: 5 }
+    }
+}
+
+impl IndicatorSettings {
+    const STORAGE_KEY: &'static str = "indicator-pane";
+
+    /// Stored as `levels`.
+    pub fn load() -> Self {
+        let stored = load_stored(Self::STORAGE_KEY);
+        let mut settings = Self::default();
+        if let Some(levels) = stored.and_then(|s| s.parse().ok()) {
+            if BookSample::LEVELS.contains(&levels) {
+                settings.levels = levels;
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        save_stored(Self::STORAGE_KEY, &self.levels.to_string());
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct IndicatorControlsProps {
+    pub settings: IndicatorSettings,
+    pub on_change: Callback<IndicatorSettings>,
+}
+
+#[function_component(IndicatorControls)]
+pub fn indicator_controls(props: &IndicatorControlsProps) -> Html {
+    let on_levels = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Ok(levels) = select.value().parse() {
+                on_change.emit(IndicatorSettings { levels });
+            }
+        })
+    };
+
+    html! {
+        <label class="flex gap-1 items-center text-xs font-mono">
+            {"top"}
+            <select
+                class="px-1 rounded border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-900"
+                onchange={on_levels}
+            >
+                {for BookSample::LEVELS.iter().map(|n| html! {
+                    <option value={n.to_string()} selected={*n == props.settings.levels}>{*n}</option>
+                })}
+            </select>
+            {"levels"}
+        </label>
+    }
+}
+
+//MARK: - Drawing ---------------------------------------------
+
+/// Redraw the pane under the chart: top-of-book imbalance, microprice
+/// against mid and the spread, one row each, over the chart's time window.
+///
+/// Prices are in ticks, or in price bins until the tick size is known.
+pub fn draw(
+    canvas: HtmlCanvasElement,
+    darkmode: bool,
+    data: &Data,
+    settings: IndicatorSettings,
+    plot: Option<Plot>,
+) -> anyhow::Result<()> {
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap();
+    context.clear_rect(0.0, 0.0, canvas.width() as f64, canvas.height() as f64);
+    let Some(plot) = plot else {
+        return Ok(());
+    };
+    let v = plot.viewport;
+    let margin_left = (plot.left as u32).saturating_sub(Y_LABEL_AREA);
+    let margin_right = canvas
+        .width()
+        .saturating_sub(plot.left as u32 + v.width as u32);
+
+    let tick = data
+        .instrument
+        .get()
+        .map_or(data.bins.get().price_step, |i| i.tick_size)
+        .to_f64()
+        .unwrap();
+    let samples = data.samples.read().unwrap();
+    let visible: Vec<_> = samples
+        .range(v.from..=v.to)
+        .map(|(&ts, sample)| {
+            let at = chrono::DateTime::from_timestamp_millis(ts as i64).unwrap();
+            (at, sample)
+        })
+        .collect();
+    let imbalance: Vec<_> = visible
+        .iter()
+        .filter_map(|(at, s)| Some((*at, s.imbalance(settings.levels)?)))
+        .collect();
+    let micro: Vec<_> = visible
+        .iter()
+        .map(|(at, s)| (*at, (s.microprice() - s.mid()) / tick))
+        .collect();
+    let spread: Vec<_> = visible
+        .iter()
+        .map(|(at, s)| (*at, s.spread() / tick))
+        .collect();
+    drop(samples);
+
+    let backend = CanvasBackend::with_canvas_object(canvas).expect("cannot find canvas");
+    let root = backend.into_drawing_area();
+    let rows = root.split_evenly((3, 1));
+    let x_range = chrono::DateTime::from_timestamp_millis(v.from as i64).unwrap()
+        ..chrono::DateTime::from_timestamp_millis(v.to as i64).unwrap();
+    let axis_color = if darkmode { GREY } else { BLACK };
+    let (bid_color, ask_color) = (RGBColor(16, 185, 129), RGBColor(239, 68, 68));
+    let line_color = RGBColor(56, 189, 248);
+
+    let latest = |series: &[(chrono::DateTime<chrono::Utc>, f64)]| {
+        series
+            .last()
+            .map_or(String::new(), |(_, y)| format!("{:+.2}", y))
+    };
+    let captions = [
+        format!("imbalance top {} {}", settings.levels, latest(&imbalance)),
+        format!("micro − mid ticks {}", latest(&micro)),
+        format!("spread ticks {}", latest(&spread).trim_start_matches('+')),
+    ];
+    let limit = |series: &[(_, f64)]| series.iter().map(|(_, y)| y.abs()).fold(0.0, f64::max);
+    let y_ranges = [
+        (-1.0, 1.0),
+        (-limit(&micro).max(0.5), limit(&micro).max(0.5)),
+        (0.0, limit(&spread).max(1.0) * 1.1),
+    ];
+
+    for (i, row) in rows.iter().enumerate() {
+        let (lo, hi) = y_ranges[i];
+        let mut chart = ChartBuilder::on(row)
+            .margin_left(margin_left)
+            .margin_right(margin_right)
+            .margin_top(4u32)
+            .margin_bottom(4u32)
+            .y_label_area_size(Y_LABEL_AREA)
+            .build_cartesian_2d(x_range.clone(), lo..hi)?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .axis_style(ShapeStyle::from(axis_color.mix(0.45)).stroke_width(1))
+            .y_labels(3)
+            .y_label_style(("monospace", 10).into_font().color(&axis_color.mix(0.65)))
+            .y_label_formatter(&|y| format!("{:.1}", y))
+            .draw()?;
+        if lo < 0.0 {
+            chart.draw_series(LineSeries::new(
+                [(x_range.start, 0.0), (x_range.end, 0.0)],
+                axis_color.mix(0.2),
+            ))?;
+        }
+        match i {
+            // bid-heavy above zero, ask-heavy below
+            0 => {
+                chart.draw_series(AreaSeries::new(
+                    imbalance.iter().map(|&(at, y)| (at, y.max(0.0))),
+                    0.0,
+                    bid_color.mix(0.5),
+                ))?;
+                chart.draw_series(AreaSeries::new(
+                    imbalance.iter().map(|&(at, y)| (at, y.min(0.0))),
+                    0.0,
+                    ask_color.mix(0.5),
+                ))?;
+            }
+            1 => {
+                chart.draw_series(LineSeries::new(micro.iter().copied(), line_color))?;
+            }
+            _ => {
+                chart.draw_series(LineSeries::new(spread.iter().copied(), axis_color.mix(0.7)))?;
+            }
+        }
+        row.draw(&Text::new(
+            captions[i].clone(),
+            ((margin_left + Y_LABEL_AREA + 4) as i32, 4),
+            ("monospace", 11).into_font().color(&axis_color.mix(0.8)),
+        ))?;
+    }
+    root.present()?;
+    Ok(())
+}
@@ -0,0 +1,1231 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use gloo_worker::{HandlerId, Worker, WorkerScope};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use web_sys::{console, js_sys, WebSocket};
+
+use crate::book::{BookSample, Depth, OrderBook, Removal, Side, Touch};
+use crate::connection::{ConnectionState, Socket, SocketHandlers, SocketStatus};
+use crate::data::{
+    self, Bins, Compaction, DepthSnapshot, DepthUpdate, Kline, OrderBookUpdate, Resync, Retention,
+    Time, Trade,
+};
+use crate::recorder::{Record, Recording};
+use crate::source::{self, MarketDataSource, MarketEvent, Venue};
+
+/// How often queued updates are folded into the heatmap and posted to the
+/// UI thread.
+const BATCH_EVERY: Duration = Duration::from_millis(300);
+/// How far back to preload from the history server.
+const HISTORY_MS: Time = 60 * 60 * 1000;
+/// How long executed volume at a price can still explain a size decrease
+/// there; depth diffs and trades arrive on separate streams.
+const MATCH_MS: Time = 2_000;
+/// Delay before refetching a depth snapshot that failed or was too old,
+/// doubling per attempt up to `SNAPSHOT_RETRY_MAX`; each fetch costs
+/// request weight.
+const SNAPSHOT_RETRY: Duration = Duration::from_millis(1000);
+const SNAPSHOT_RETRY_MAX: Duration = Duration::from_millis(16_000);
+
+//MARK: - Messages ---------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IngestInput {
+    /// Drop everything and stream `symbol` live, preloading from the history
+    /// server first when one is given.
+    Subscribe {
+        session: u64,
+        venue: Venue,
+        symbol: String,
+        history: Option<String>,
+    },
+    /// Drop everything and wait for replayed records.
+    Replay {
+        session: u64,
+    },
+    /// Recorded entries to process as if they had just arrived.
+    Feed(Vec<(Time, Record)>),
+    /// Re-bin everything retained.
+    SetBins(Bins),
+    /// Post accepted records back for the recording.
+    Record(bool),
+    SetRetention(Retention),
+}
+
+/// What changed since the last batch, posted every `BATCH_EVERY`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    /// Session the batch belongs to; anything older is stale.
+    pub session: u64,
+    pub status: ConnectionState,
+    /// The heatmap was rebuilt: drop every row before applying `cells`.
+    pub rebuilt: bool,
+    /// Heatmap cells set, as (price bin, time bin, depth).
+    pub cells: Vec<(Decimal, Time, Depth)>,
+    /// Removal cells changed, with their totals so far.
+    pub removals: Vec<(Decimal, Time, Removal)>,
+    /// Book samples taken or retaken, by time bin.
+    pub samples: Vec<(Time, BookSample)>,
+    pub klines: Vec<Kline>,
+    /// Trades since the last batch, in time order.
+    pub trades: Vec<Trade>,
+    /// Every resync so far, when any changed.
+    pub resyncs: Option<Vec<Resync>>,
+    pub touch: Touch,
+    /// Entries accepted while recording, with their receive time.
+    pub recorded: Vec<(Time, Record)>,
+    /// Retention cut-offs moved, applied after `cells`, `removals`,
+    /// `samples`, `klines` and `trades`.
+    pub compaction: Option<Compaction>,
+    /// Raw updates kept for re-binning.
+    pub retained: usize,
+    /// The worker's wasm linear memory, in bytes.
+    pub heap: usize,
+}
+
+//MARK: - Worker ---------------------------------------------
+
+/// Dedicated worker running the WebSocket, parsing, local book and heatmap
+/// binning, so bursts never block the UI thread.
+pub struct Ingest {
+    pipeline: Pipeline,
+}
+
+impl Worker for Ingest {
+    type Message = ();
+    type Input = IngestInput;
+    type Output = Batch;
+
+    fn create(scope: &WorkerScope<Self>) -> Self {
+        let pipeline = Pipeline::new(scope.clone());
+        pipeline.schedule_batches();
+        Self { pipeline }
+    }
+
+    fn update(&mut self, _scope: &WorkerScope<Self>, _msg: Self::Message) {}
+
+    fn connected(&mut self, _scope: &WorkerScope<Self>, id: HandlerId) {
+        self.pipeline.client.set(Some(id));
+    }
+
+    fn received(&mut self, _scope: &WorkerScope<Self>, msg: Self::Input, id: HandlerId) {
+        self.pipeline.client.set(Some(id));
+        match msg {
+            IngestInput::Subscribe {
+                session,
+                venue,
+                symbol,
+                history,
+            } => {
+                let pipeline = self.pipeline.clone();
+                pipeline.venue.set(venue);
+                *pipeline.symbol.borrow_mut() = symbol;
+                yew::platform::spawn_local(async move {
+                    pipeline.subscribe(session, history).await;
+                });
+            }
+            IngestInput::Replay { session } => self.pipeline.start_replay(session),
+            IngestInput::Feed(records) => self.pipeline.feed(records),
+            IngestInput::SetBins(bins) => {
+                self.pipeline.bins.set(bins);
+                self.pipeline.rebin();
+            }
+            IngestInput::Record(on) => self.pipeline.recording_on.set(on),
+            IngestInput::SetRetention(retention) => self.pipeline.retention.set(retention),
+        }
+    }
+}
+
+//MARK: - Pipeline ---------------------------------------------
+
+/// Ingest state, shared between the worker and its socket callbacks.
+#[derive(Clone)]
+struct Pipeline {
+    scope: WorkerScope<Ingest>,
+    client: Rc<Cell<Option<HandlerId>>>,
+    session: Rc<Cell<u64>>,
+    venue: Rc<Cell<Venue>>,
+    symbol: Rc<RefCell<String>>,
+    status: SocketStatus,
+    updates: Arc<Mutex<VecDeque<(Time, OrderBookUpdate)>>>,
+    heatmap: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, Depth>>>>,
+    removals: Arc<RwLock<HashMap<Decimal, BTreeMap<Time, Removal>>>>,
+    samples: Arc<RwLock<BTreeMap<Time, BookSample>>>,
+    resyncs: Arc<RwLock<Vec<Resync>>>,
+    /// Every update folded into `heatmap` since `base`, for re-binning.
+    retained: Arc<RwLock<Vec<(Time, OrderBookUpdate)>>>,
+    /// Classified decreases at exact prices over the same span, for
+    /// re-binning `removals`.
+    flows: Arc<RwLock<Vec<(Time, Decimal, Removal)>>>,
+    executions: Rc<RefCell<Executions>>,
+    /// The book as of the oldest retained update, and that time.
+    base: Rc<RefCell<(Time, OrderBook)>>,
+    /// Latest update time folded, the clock retention runs on.
+    latest: Rc<Cell<Time>>,
+    retention: Rc<Cell<Retention>>,
+    compacted: Rc<Cell<Compaction>>,
+    bins: Rc<Cell<Bins>>,
+    book: Arc<RwLock<OrderBook>>,
+    depth: Arc<Mutex<DepthSync>>,
+    socket: Rc<RefCell<Option<Socket>>>,
+    recording_on: Rc<Cell<bool>>,
+    /// Not yet posted to the UI thread.
+    pending: Rc<RefCell<Pending>>,
+}
+
+/// Changes accumulated between batches.
+#[derive(Debug, Default)]
+struct Pending {
+    status: Option<ConnectionState>,
+    rebuilt: bool,
+    cells: HashSet<(Decimal, Time)>,
+    removals: HashSet<(Decimal, Time)>,
+    samples: BTreeMap<Time, BookSample>,
+    klines: BTreeMap<Time, Kline>,
+    trades: Vec<Trade>,
+    resyncs: bool,
+    touch: Touch,
+    recorded: Vec<(Time, Record)>,
+}
+
+impl Pipeline {
+    fn new(scope: WorkerScope<Ingest>) -> Self {
+        Self {
+            scope,
+            client: Rc::new(Cell::new(None)),
+            session: Rc::new(Cell::new(0)),
+            venue: Rc::new(Cell::new(Venue::default())),
+            symbol: Rc::new(RefCell::new(String::new())),
+            status: SocketStatus::default(),
+            updates: Arc::new(Mutex::new(VecDeque::new())),
+            heatmap: Arc::new(RwLock::new(HashMap::new())),
+            removals: Arc::new(RwLock::new(HashMap::new())),
+            samples: Arc::new(RwLock::new(BTreeMap::new())),
+            resyncs: Arc::new(RwLock::new(Vec::new())),
+            retained: Arc::new(RwLock::new(Vec::new())),
+            flows: Arc::new(RwLock::new(Vec::new())),
+            executions: Rc::new(RefCell::new(Executions::default())),
+            base: Rc::new(RefCell::new((0, OrderBook::default()))),
+            latest: Rc::new(Cell::new(0)),
+            retention: Rc::new(Cell::new(Retention::default())),
+            compacted: Rc::new(Cell::new(Compaction::default())),
+            bins: Rc::new(Cell::new(Bins::default())),
+            book: Arc::new(RwLock::new(OrderBook::default())),
+            depth: Arc::new(Mutex::new(DepthSync::default())),
+            socket: Rc::new(RefCell::new(None)),
+            recording_on: Rc::new(Cell::new(false)),
+            pending: Rc::new(RefCell::new(Pending::default())),
+        }
+    }
+
+    /// (Re)subscribe to the current venue and symbol, starting from an
+    /// empty chart.
+    async fn subscribe(&self, session: u64, history: Option<String>) {
+        self.session.set(session);
+        if let Some(socket) = self.socket.take() {
+            socket.close();
+        }
+        self.clear();
+
+        let symbol = self.symbol.borrow().clone();
+        console::log_1(&format!("[ingest] subscribe {} {}", self.venue.get(), symbol).into());
+        if let Some(server) = history {
+            self.load_history(&server).await;
+        }
+        if self.session.get() != session {
+            // another subscribe or replay took over while history loaded
+            return;
+        }
+        self.dial();
+    }
+
+    fn start_replay(&self, session: u64) {
+        self.session.set(session);
+        if let Some(socket) = self.socket.take() {
+            socket.close();
+        }
+        self.clear();
+    }
+
+    fn clear(&self) {
+        self.updates.lock().unwrap().clear();
+        self.heatmap.write().unwrap().clear();
+        self.removals.write().unwrap().clear();
+        self.samples.write().unwrap().clear();
+        self.resyncs.write().unwrap().clear();
+        self.retained.write().unwrap().clear();
+        self.flows.write().unwrap().clear();
+        *self.executions.borrow_mut() = Executions::default();
+        *self.base.borrow_mut() = (0, OrderBook::default());
+        self.latest.set(0);
+        self.compacted.set(Compaction::default());
+        self.book.write().unwrap().clear();
+        *self.depth.lock().unwrap() = DepthSync::default();
+        *self.pending.borrow_mut() = Pending {
+            rebuilt: true,
+            resyncs: true,
+            ..Pending::default()
+        };
+    }
+
+    fn dial(&self) {
+        let symbol = self.symbol.borrow().clone();
+        let source = self.venue.get().source();
+        let url = source.stream_url(&symbol);
+
+        let handlers = {
+            let (pipeline, src) = (self.clone(), source.clone());
+            let on_message = move |msg: String| pipeline.on_message(&*src, &msg);
+            let (pipeline, src) = (self.clone(), source.clone());
+            let on_open = move |ws: &WebSocket| pipeline.on_open(&src, ws);
+            let pipeline = self.clone();
+            SocketHandlers {
+                on_message: Box::new(on_message),
+                on_open: Box::new(on_open),
+                on_drop: Box::new(move || pipeline.on_drop()),
+            }
+        };
+        let socket = Socket::open(url, self.status.clone(), handlers);
+        *self.socket.borrow_mut() = Some(socket);
+    }
+
+    fn on_message(&self, source: &dyn MarketDataSource, msg: &str) {
+        let events = match source.parse(msg) {
+            Ok(events) => events,
+            Err(e) => {
+                console::log_1(&format!("🚫 [ws] parse {} {}", e, msg).into());
+                return;
+            }
+        };
+        for event in events {
+            match event {
+                MarketEvent::Kline(kline) => {
+                    self.record(|| vec![Record::Kline(kline.clone())]);
+                    self.pending.borrow_mut().klines.insert(kline.ts, kline);
+                }
+                MarketEvent::Trade(trade) => {
+                    self.record(|| vec![Record::Trade(trade.clone())]);
+                    self.executions.borrow_mut().add(&trade);
+                    self.pending.borrow_mut().trades.push(trade);
+                }
+                MarketEvent::Depth(diff) => {
+                    let ts = diff.ts;
+                    let mut sync = self.depth.lock().unwrap();
+                    if sync.last_update_id.is_none() {
+                        // still waiting on the snapshot
+                        sync.buffered.push_back(diff);
+                        continue;
+                    }
+                    let mut updates = self.updates.lock().unwrap();
+                    let from = updates.len();
+                    let applied = sync.apply(diff, &mut updates);
+                    self.record_updates(&updates, from);
+                    drop((sync, updates));
+                    if let Err(gap) = applied {
+                        console::log_1(&format!("🕳 [ws] depth gap: {}", gap).into());
+                        self.resync(source, ts);
+                    }
+                }
+                MarketEvent::Snapshot(snapshot) => {
+                    let mut sync = self.depth.lock().unwrap();
+                    self.apply_snapshot(snapshot, &mut sync);
+                }
+                MarketEvent::Gap => {
+                    console::log_1(&format!("🕳 [ws] {} sequence gap", source.venue()).into());
+                    self.resync(source, js_sys::Date::now() as Time);
+                }
+            }
+        }
+    }
+
+    /// Every connection starts unsynchronised and rebuilds the book from its
+    /// own snapshot, either fetched over REST or sent by the venue on
+    /// subscribe.
+    fn on_open(&self, source: &Rc<dyn MarketDataSource>, ws: &WebSocket) {
+        *self.depth.lock().unwrap() = DepthSync::default();
+        source.reset();
+
+        let symbol = self.symbol.borrow().clone();
+        for msg in source.subscriptions(&symbol) {
+            let _ = ws.send_with_str(&msg);
+        }
+
+        if let Some((every, ping)) = source.heartbeat() {
+            let ws = ws.clone();
+            yew::platform::spawn_local(async move {
+                loop {
+                    yew::platform::time::sleep(every).await;
+                    if ws.ready_state() != WebSocket::OPEN {
+                        return;
+                    }
+                    let _ = ws.send_with_str(&ping);
+                }
+            });
+        }
+
+        if source.snapshot_url(&symbol).is_some() {
+            let pipeline = self.clone();
+            let source = source.clone();
+            let ws = ws.clone();
+            yew::platform::spawn_local(async move {
+                pipeline.sync_depth(source, symbol, ws).await;
+            });
+        }
+    }
+
+    /// The local book can't be trusted any more: mark it on the chart and
+    /// rebuild it. REST venues refetch from `sync_depth`, the rest replay
+    /// their subscribe-time snapshot by reconnecting.
+    fn resync(&self, source: &dyn MarketDataSource, ts: Time) {
+        self.mark_resync(ts);
+        let symbol = self.symbol.borrow().clone();
+        if source.snapshot_url(&symbol).is_none() {
+            let socket = self.socket.borrow().clone();
+            if let Some(socket) = socket {
+                socket.reconnect();
+            }
+        }
+    }
+
+    /// Diffs are lost while disconnected, treat it like a sequence gap.
+    fn on_drop(&self) {
+        self.mark_resync(js_sys::Date::now() as Time);
+    }
+
+    fn mark_resync(&self, ts: Time) {
+        let mut resyncs = self.resyncs.write().unwrap();
+        if resyncs.last().is_none_or(|r| r.end.is_some()) {
+            resyncs.push(Resync {
+                start: ts,
+                end: None,
+            });
+            self.pending.borrow_mut().resyncs = true;
+            drop(resyncs);
+            self.record(|| vec![Record::Gap]);
+        }
+    }
+
+    /// The book is whole again as of `ts`.
+    fn end_resync(&self, ts: Time) {
+        let mut resyncs = self.resyncs.write().unwrap();
+        if let Some(resync) = resyncs.last_mut().filter(|r| r.end.is_none()) {
+            resync.end = Some(ts);
+            self.pending.borrow_mut().resyncs = true;
+        }
+    }
+
+    /// Binance local order book procedure: wait for buffered diffs, fetch a
+    /// REST snapshot that covers them, drop diffs older than the snapshot's
+    /// `lastUpdateId` and replay the rest on top of it. Runs for as long as
+    /// the socket is open, resyncing whenever a sequence gap unsyncs the book.
+    async fn sync_depth(&self, source: Rc<dyn MarketDataSource>, symbol: String, ws: WebSocket) {
+        let url = source.snapshot_url(&symbol).unwrap();
+        let mut retry = SNAPSHOT_RETRY;
+        while ws.ready_state() <= WebSocket::OPEN {
+            let first_id = {
+                let sync = self.depth.lock().unwrap();
+                match sync.last_update_id {
+                    Some(_) => None,
+                    None => sync.buffered.front().map(|d| d.first_id),
+                }
+            };
+            let Some(first_id) = first_id else {
+                yew::platform::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            };
+
+            let snapshot = source::fetch_text(&url)
+                .await
+                .and_then(|body| source.parse_snapshot(&body));
+            let snapshot = match snapshot {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    console::log_1(&format!("🚫 [rest] depth snapshot {:?}", e).into());
+                    yew::platform::time::sleep(retry).await;
+                    retry = (retry * 2).min(SNAPSHOT_RETRY_MAX);
+                    continue;
+                }
+            };
+            if snapshot.last_update_id < first_id {
+                // snapshot predates the buffered stream, fetch a fresher one
+                // once the venue's snapshot cache has had time to catch up
+                console::log_1(
+                    &format!(
+                        "⏳ [rest] depth snapshot {} behind stream {}, retry in {:?}",
+                        snapshot.last_update_id, first_id, retry
+                    )
+                    .into(),
+                );
+                yew::platform::time::sleep(retry).await;
+                retry = (retry * 2).min(SNAPSHOT_RETRY_MAX);
+                continue;
+            }
+            retry = SNAPSHOT_RETRY;
+            if ws.ready_state() > WebSocket::OPEN {
+                return;
+            }
+
+            console::log_1(
+                &format!(
+                    "📚 [rest] depth snapshot {} lastUpdateId {} levels {}",
+                    symbol,
+                    snapshot.last_update_id,
+                    snapshot.levels.len()
+                )
+                .into(),
+            );
+            let mut sync = self.depth.lock().unwrap();
+            self.apply_snapshot(snapshot, &mut sync);
+        }
+    }
+
+    /// Reset the local book to `snapshot` and replay any diffs buffered on
+    /// top of it.
+    fn apply_snapshot(&self, mut snapshot: DepthSnapshot, sync: &mut DepthSync) {
+        let mut updates = self.updates.lock().unwrap();
+        // stamp the snapshot with the event time of the diff it lands in,
+        // so replayed diffs never sort before it in the heatmap
+        if let Some(ts) = sync
+            .buffered
+            .iter()
+            .find(|d| d.final_id > snapshot.last_update_id)
+            .or(sync.buffered.back())
+            .map(|d| d.ts)
+        {
+            snapshot.ts = ts;
+        }
+
+        // bring the book up to date, then clear any level the snapshot
+        // doesn't carry: it vanished while we weren't in sequence
+        self.fold_updates(&mut updates);
+        let from = updates.len();
+        let live: HashSet<(Side, Decimal)> =
+            snapshot.levels.iter().map(|l| (l.side, l.px)).collect();
+        let book = self.book.read().unwrap();
+        for (side, px, _) in book.levels() {
+            if !live.contains(&(side, px)) {
+                let update = OrderBookUpdate {
+                    ts: snapshot.ts,
+                    sq: snapshot.last_update_id,
+                    side,
+                    px,
+                    sz: Decimal::ZERO,
+                };
+                updates.push_back((snapshot.ts, update));
+            }
+        }
+        drop(book);
+
+        self.end_resync(snapshot.ts);
+
+        let ts = snapshot.ts;
+        updates.extend(snapshot.levels.into_iter().map(|mut l| {
+            l.ts = ts;
+            (l.ts, l)
+        }));
+        sync.last_update_id = Some(snapshot.last_update_id);
+        sync.after_snapshot = true;
+        while let Some(diff) = sync.buffered.pop_front() {
+            let ts = diff.ts;
+            if let Err(gap) = sync.apply(diff, &mut updates) {
+                console::log_1(&format!("🕳 [ws] depth gap: {}", gap).into());
+                self.mark_resync(ts);
+                break;
+            }
+        }
+        self.record_updates(&updates, from);
+    }
+
+    //MARK: - History ---------------------------------------------
+
+    /// Preload the last `HISTORY_MS` from the companion server, when it
+    /// records this venue and symbol.
+    async fn load_history(&self, server: &str) {
+        let to = js_sys::Date::now() as Time;
+        let url = format!(
+            "{}/api/history?venue={}&symbol={}&from={}&to={}",
+            server.trim_end_matches('/'),
+            self.venue.get().id(),
+            self.symbol.borrow(),
+            to - HISTORY_MS,
+            to
+        );
+        let recording = match source::fetch_bytes(&url)
+            .await
+            .and_then(|bytes| Recording::decode(&bytes))
+        {
+            Ok(recording) => recording,
+            Err(e) => {
+                console::log_1(&format!("🚫 [history] {:?}", e).into());
+                return;
+            }
+        };
+        console::log_1(&format!("📜 [history] entries {}", recording.entries.len()).into());
+        let Some((_, end)) = recording.span() else {
+            return;
+        };
+
+        let mut queue = VecDeque::new();
+        let mut gaps: Vec<Resync> = Vec::new();
+        let mut pending = self.pending.borrow_mut();
+        for (rx, record) in recording.entries {
+            match record {
+                Record::Update(u) => {
+                    // the server's fresh image after a gap
+                    if let Some(gap) = gaps.last_mut().filter(|g| g.end.is_none()) {
+                        gap.end = Some(u.ts);
+                    }
+                    queue.push_back((u.ts, u))
+                }
+                Record::Kline(k) => {
+                    pending.klines.insert(k.ts, k);
+                }
+                Record::Trade(t) => pending.trades.push(t),
+                Record::Gap => gaps.push(Resync {
+                    start: rx,
+                    end: None,
+                }),
+                Record::Reset => {}
+            }
+        }
+        pending.resyncs |= !gaps.is_empty();
+        drop(pending);
+        self.fold_updates(&mut queue);
+        self.resyncs.write().unwrap().extend(gaps);
+        // the live stream picks up from its own snapshot
+        self.mark_resync(end);
+    }
+
+    //MARK: - Recording ---------------------------------------------
+
+    fn record(&self, records: impl FnOnce() -> Vec<Record>) {
+        if !self.recording_on.get() {
+            return;
+        }
+        let rx = js_sys::Date::now() as Time;
+        let mut pending = self.pending.borrow_mut();
+        pending
+            .recorded
+            .extend(records().into_iter().map(|r| (rx, r)));
+    }
+
+    /// Record whatever landed in `updates` past index `from`.
+    fn record_updates(&self, updates: &VecDeque<(Time, OrderBookUpdate)>, from: usize) {
+        self.record(|| {
+            updates
+                .range(from..)
+                .map(|(_, u)| Record::Update(u.clone()))
+                .collect()
+        });
+    }
+
+    /// Queue replayed entries exactly as the live stream would.
+    fn feed(&self, records: Vec<(Time, Record)>) {
+        let mut updates = self.updates.lock().unwrap();
+        for (rx, record) in records {
+            match record {
+                Record::Update(u) => {
+                    self.end_resync(u.ts);
+                    updates.push_back((u.ts, u))
+                }
+                Record::Kline(k) => {
+                    self.pending.borrow_mut().klines.insert(k.ts, k);
+                }
+                Record::Trade(t) => {
+                    self.executions.borrow_mut().add(&t);
+                    self.pending.borrow_mut().trades.push(t);
+                }
+                Record::Gap => self.mark_resync(rx),
+                Record::Reset => {}
+            }
+        }
+    }
+
+    //MARK: - Batches ---------------------------------------------
+
+    fn schedule_batches(&self) {
+        let pipeline = self.clone();
+        yew::platform::spawn_local(async move {
+            loop {
+                yew::platform::time::sleep(BATCH_EVERY).await;
+                let mut queue = pipeline.updates.lock().unwrap();
+                if !queue.is_empty() {
+                    pipeline.fold_updates(&mut queue);
+                }
+                drop(queue);
+                pipeline.post_batch();
+            }
+        });
+    }
+
+    /// Post whatever changed since the last batch, if anything did.
+    fn post_batch(&self) {
+        let Some(client) = self.client.get() else {
+            return;
+        };
+        let mut pending = self.pending.borrow_mut();
+        let status = self.status.get();
+        let touch = self.book.read().unwrap().touch();
+        if pending.status == Some(status)
+            && pending.touch == touch
+            && !pending.rebuilt
+            && !pending.resyncs
+            && pending.cells.is_empty()
+            && pending.removals.is_empty()
+            && pending.samples.is_empty()
+            && pending.klines.is_empty()
+            && pending.trades.is_empty()
+            && pending.recorded.is_empty()
+        {
+            return;
+        }
+
+        let heatmap = self.heatmap.read().unwrap();
+        let cells = pending
+            .cells
+            .drain()
+            .filter_map(|(price, time)| {
+                let depth = heatmap.get(&price)?.get(&time)?;
+                Some((price, time, *depth))
+            })
+            .collect();
+        drop(heatmap);
+        let removals = self.removals.read().unwrap();
+        let removal_cells = pending
+            .removals
+            .drain()
+            .filter_map(|(price, time)| {
+                let removal = removals.get(&price)?.get(&time)?;
+                Some((price, time, *removal))
+            })
+            .collect();
+        drop(removals);
+        let compaction = self.compact();
+        let batch = Batch {
+            session: self.session.get(),
+            status,
+            rebuilt: pending.rebuilt,
+            cells,
+            removals: removal_cells,
+            samples: std::mem::take(&mut pending.samples).into_iter().collect(),
+            klines: std::mem::take(&mut pending.klines).into_values().collect(),
+            trades: {
+                let mut trades = std::mem::take(&mut pending.trades);
+                trades.sort_by_key(|t| t.ts);
+                trades
+            },
+            resyncs: pending
+                .resyncs
+                .then(|| self.resyncs.read().unwrap().clone()),
+            touch,
+            recorded: std::mem::take(&mut pending.recorded),
+            compaction,
+            retained: self.retained.read().unwrap().len(),
+            heap: data::wasm_heap(),
+        };
+        pending.status = Some(status);
+        pending.touch = touch;
+        pending.rebuilt = false;
+        pending.resyncs = false;
+        drop(pending);
+        self.scope.respond(client, batch);
+    }
+
+    //MARK: - Retention ---------------------------------------------
+
+    /// Move the retention cut-offs up to the latest update and apply them to
+    /// the heatmap, returning them for the UI thread when they moved.
+    fn compact(&self) -> Option<Compaction> {
+        let retention = self.retention.get();
+        let prev = self.compacted.get();
+        let span_cutoff = self.latest.get().saturating_sub(retention.span) / Retention::COARSE_MS
+            * Retention::COARSE_MS;
+
+        let mut next = prev;
+        if retention.downsample {
+            next.coarsen_before = next.coarsen_before.max(span_cutoff);
+        } else {
+            next.drop_before = next.drop_before.max(span_cutoff);
+        }
+        let mut heatmap = self.heatmap.write().unwrap();
+        if next.coarsen_before > prev.coarsen_before {
+            Compaction::coarsen(&mut heatmap, next.coarsen_before);
+        }
+        let cells: usize = heatmap.values().map(BTreeMap::len).sum();
+        if cells > retention.max_cells {
+            // leave some headroom so this doesn't run on every batch
+            let cutoff = Compaction::budget_cutoff(&heatmap, retention.max_cells * 9 / 10);
+            next.drop_before = next.drop_before.max(cutoff);
+        }
+        if next.drop_before > prev.drop_before {
+            Compaction::drop_before(&mut heatmap, next.drop_before);
+        }
+        drop(heatmap);
+        if next == prev {
+            return None;
+        }
+        next.apply_removals(&mut self.removals.write().unwrap());
+        next.apply_samples(&mut self.samples.write().unwrap());
+
+        console::log_1(&format!("🗜 [ingest] compact {:?} cells {}", next, cells).into());
+        self.compacted.set(next);
+        self.evict(next.coarsen_before.max(next.drop_before));
+        Some(next)
+    }
+
+    /// Fold retained updates older than `before` into the base book;
+    /// re-binning starts from there.
+    fn evict(&self, before: Time) {
+        let mut retained = self.retained.write().unwrap();
+        let n = retained
+            .iter()
+            .position(|(ts, _)| *ts >= before)
+            .unwrap_or(retained.len());
+        let mut base = self.base.borrow_mut();
+        for (_, update) in retained.drain(..n) {
+            base.1.set(update.side, update.px, update.sz);
+        }
+        base.0 = base.0.max(before);
+        let mut flows = self.flows.write().unwrap();
+        let n = flows.partition_point(|(ts, _, _)| *ts < before);
+        flows.drain(..n);
+    }
+
+    //MARK: - Binning ---------------------------------------------
+
+    /// Rebuild the book and heatmap from the base book and retained updates
+    /// with the current bins; the next batch replaces the UI thread's copy
+    /// outright. History already compacted into the base book has no
+    /// updates left, its cells are carried across to the new bins as is.
+    fn rebin(&self) {
+        let bins = self.bins.get();
+        let mut queue = self.updates.lock().unwrap();
+        {
+            let mut heatmap = self.heatmap.write().unwrap();
+            let mut removals = self.removals.write().unwrap();
+            let mut samples = self.samples.write().unwrap();
+            let mut book = self.book.write().unwrap();
+            let retained = self.retained.read().unwrap();
+            let mut pending = self.pending.borrow_mut();
+            let (base_ts, base) = &*self.base.borrow();
+            *heatmap = Compaction::rebin(&heatmap, bins, *base_ts);
+            *removals = Compaction::rebin_removals(&removals, bins, *base_ts);
+            samples.split_off(base_ts);
+            book.clear();
+            pending.rebuilt = true;
+            pending.cells = heatmap
+                .iter()
+                .flat_map(|(&price, cells)| cells.keys().map(move |&ts| (price, ts)))
+                .collect();
+            pending.removals = removals
+                .iter()
+                .flat_map(|(&price, cells)| cells.keys().map(move |&ts| (price, ts)))
+                .collect();
+            pending.samples = samples.clone();
+            let seed: Vec<_> = base
+                .levels()
+                .map(|(side, px, sz)| {
+                    let update = OrderBookUpdate {
+                        ts: *base_ts,
+                        sq: 0,
+                        side,
+                        px,
+                        sz,
+                    };
+                    (*base_ts, update)
+                })
+                .collect();
+            let mut open_bin = None;
+            for update in seed.iter().chain(retained.iter()) {
+                let time_bin = bins.time_bin(update.0);
+                if let Some(bin) = open_bin.filter(|&bin| bin != time_bin) {
+                    Pipeline::sample_book(&mut samples, &book, bin, &mut pending);
+                }
+                open_bin = Some(time_bin);
+                Pipeline::bin_update(&mut heatmap, &mut book, bins, update, &mut pending);
+            }
+            if let Some(bin) = open_bin {
+                Pipeline::sample_book(&mut samples, &book, bin, &mut pending);
+            }
+            for &(ts, px, removal) in self.flows.read().unwrap().iter() {
+                Pipeline::bin_removal(&mut removals, bins, ts, px, removal, &mut pending);
+            }
+            console::log_1(
+                &format!(
+                    "🧮 [ingest] rebin {} x {}ms from {} updates",
+                    bins.price_step,
+                    bins.time_step,
+                    retained.len()
+                )
+                .into(),
+            );
+        }
+        self.fold_updates(&mut queue);
+    }
+
+    /// Apply queued updates to the book and heatmap, retaining them for
+    /// re-binning, and attribute every size decrease to fills or cancels.
+    /// Callers hold the `updates` lock, if `queue` is it.
+    fn fold_updates(&self, queue: &mut VecDeque<(Time, OrderBookUpdate)>) {
+        let bins = self.bins.get();
+        let mut heatmap = self.heatmap.write().unwrap();
+        let mut removals = self.removals.write().unwrap();
+        let mut samples = self.samples.write().unwrap();
+        let mut book = self.book.write().unwrap();
+        let mut retained = self.retained.write().unwrap();
+        let mut flows = self.flows.write().unwrap();
+        let mut executions = self.executions.borrow_mut();
+        let mut pending = self.pending.borrow_mut();
+        // a rebuilt book's differences from the old one are not real flow
+        let resyncs: Vec<Resync> = self.resyncs.read().unwrap().clone();
+        let resyncing = |ts: Time| {
+            resyncs
+                .iter()
+                .any(|r| ts >= r.start && r.end.is_none_or(|end| ts <= end))
+        };
+        // sample each time bin once its last update is in
+        let mut open_bin = None;
+        while let Some(update) = queue.pop_front() {
+            let (ts, u) = &update;
+            let time_bin = bins.time_bin(*ts);
+            if let Some(bin) = open_bin.filter(|&bin| bin != time_bin) {
+                Pipeline::sample_book(&mut samples, &book, bin, &mut pending);
+            }
+            open_bin = Some(time_bin);
+            let before = book.size(u.side, u.px);
+            Pipeline::bin_update(&mut heatmap, &mut book, bins, &update, &mut pending);
+            if before > u.sz && !resyncing(*ts) {
+                if let Some(removal) = executions.classify(*ts, u.side, u.px, before - u.sz) {
+                    Pipeline::bin_removal(&mut removals, bins, *ts, u.px, removal, &mut pending);
+                    flows.push((*ts, u.px, removal));
+                }
+            }
+            self.latest.set(self.latest.get().max(update.0));
+            retained.push(update);
+        }
+        if let Some(bin) = open_bin {
+            Pipeline::sample_book(&mut samples, &book, bin, &mut pending);
+        }
+        executions.expire(self.latest.get().saturating_sub(MATCH_MS));
+    }
+
+    /// Record the book as it stands for `time_bin`; a later update in the
+    /// same bin takes it again.
+    fn sample_book(
+        samples: &mut BTreeMap<Time, BookSample>,
+        book: &OrderBook,
+        time_bin: Time,
+        pending: &mut Pending,
+    ) {
+        if let Some(sample) = book.sample() {
+            samples.insert(time_bin, sample);
+            pending.samples.insert(time_bin, sample);
+        }
+    }
+
+    /// Add a classified decrease at `px` to its removal cell.
+    fn bin_removal(
+        removals: &mut HashMap<Decimal, BTreeMap<Time, Removal>>,
+        bins: Bins,
+        ts: Time,
+        px: Decimal,
+        removal: Removal,
+        pending: &mut Pending,
+    ) {
+        let (price_bin, time_bin) = (bins.price_bin(px), bins.time_bin(ts));
+        removals
+            .entry(price_bin)
+            .or_default()
+            .entry(time_bin)
+            .or_default()
+            .add(removal);
+        pending.removals.insert((price_bin, time_bin));
+    }
+
+    /// Apply one update to the book, then set the heatmap cells it touched
+    /// to the size now resting in their price bins. A level that crossed to
+    /// the other side also empties the bins it pushed out of the book.
+    fn bin_update(
+        heatmap: &mut HashMap<Decimal, BTreeMap<Time, Depth>>,
+        book: &mut OrderBook,
+        bins: Bins,
+        update: &(Time, OrderBookUpdate),
+        pending: &mut Pending,
+    ) {
+        let (ts, update) = update;
+        let crossed = book.set(update.side, update.px, update.sz);
+
+        let time_bin = bins.time_bin(*ts);
+        let mut price_bins = vec![bins.price_bin(update.px)];
+        for px in crossed {
+            let price_bin = bins.price_bin(px);
+            if !price_bins.contains(&price_bin) {
+                price_bins.push(price_bin);
+            }
+        }
+        for price_bin in price_bins {
+            let depth = book.depth_between(price_bin, price_bin + bins.price_step);
+            let bin = heatmap.entry(price_bin).or_default();
+
+            if let Some((&last_time_bin, &last_depth)) = bin.iter().last() {
+                if last_depth == depth && !last_depth.is_empty() {
+                    continue;
+                }
+                if last_time_bin == time_bin && !depth.is_empty() {
+                    *bin.get_mut(&last_time_bin).unwrap() = depth;
+                } else {
+                    bin.insert(time_bin, depth);
+                }
+            } else {
+                // Insert new entry if the time bin is different
+                bin.insert(time_bin, depth);
+            };
+            pending.cells.insert((price_bin, time_bin));
+        }
+    }
+}
+
+//MARK: - Executions ---------------------------------------------
+
+/// Executed volume not yet matched to a book decrease, by the resting side
+/// it traded against and exact price.
+#[derive(Debug, Default)]
+struct Executions {
+    /// No decrease is classified before the first trade: without a trade
+    /// stream, as in history preloads, everything would look cancelled.
+    seen: bool,
+    open: HashMap<(Side, Decimal), (Time, Decimal)>,
+}
+
+impl Executions {
+    fn add(&mut self, trade: &Trade) {
+        self.seen = true;
+        // a buying taker lifts resting asks, a selling one hits bids
+        let resting = match trade.side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let open = self.open.entry((resting, trade.px)).or_default();
+        open.0 = open.0.max(trade.ts);
+        open.1 += trade.sz;
+    }
+
+    /// Split `decrease` at `px` on `side` into what recent executions
+    /// there account for and the rest, consuming the matched volume.
+    fn classify(
+        &mut self,
+        ts: Time,
+        side: Side,
+        px: Decimal,
+        decrease: Decimal,
+    ) -> Option<Removal> {
+        if !self.seen {
+            return None;
+        }
+        let filled = match self.open.get_mut(&(side, px)) {
+            Some((last, volume)) if *last + MATCH_MS >= ts => {
+                let filled = decrease.min(*volume);
+                *volume -= filled;
+                filled
+            }
+            _ => Decimal::ZERO,
+        };
+        Some(Removal {
+            filled,
+            cancelled: decrease - filled,
+        })
+    }
+
+    /// Forget volume last traded before `before`.
+    fn expire(&mut self, before: Time) {
+        self.open
+            .retain(|_, (last, volume)| *last >= before && !volume.is_zero());
+    }
+}
+
+//MARK: - Depth Sync ---------------------------------------------
+
+/// Local book synchronisation state: diffs are buffered until a snapshot
+/// has been applied, after which they flow straight into `updates`.
+#[derive(Debug, Default)]
+struct DepthSync {
+    last_update_id: Option<u64>,
+    /// No diff applied since the snapshot; the first one straddles
+    /// `last_update_id` rather than chaining onto it.
+    after_snapshot: bool,
+    buffered: VecDeque<DepthUpdate>,
+}
+
+impl DepthSync {
+    /// Apply a diff on top of the synchronised book, dropping anything the
+    /// snapshot (or an earlier diff) already covers.
+    ///
+    /// Fails with the ids around the gap if the diff does not chain onto
+    /// the previous one; the book is then marked unsynchronised and the diff
+    /// goes back to the head of the buffer for replay on top of the next
+    /// snapshot.
+    fn apply(
+        &mut self,
+        diff: DepthUpdate,
+        updates: &mut VecDeque<(Time, OrderBookUpdate)>,
+    ) -> Result<(), String> {
+        if let Some(last_update_id) = self.last_update_id {
+            if diff.final_id <= last_update_id {
+                return Ok(());
+            }
+            let in_sequence = match diff.prev_final_id {
+                Some(pu) if !self.after_snapshot => pu == last_update_id,
+                _ => diff.first_id <= last_update_id + 1,
+            };
+            if !in_sequence {
+                let gap = format!(
+                    "last {} next {}..={}",
+                    last_update_id, diff.first_id, diff.final_id
+                );
+                // the diff and anything buffered behind it wait for the next
+                // snapshot, which skips whatever it already covers
+                self.last_update_id = None;
+                self.buffered.push_front(diff);
+                return Err(gap);
+            }
+        }
+        self.last_update_id = Some(diff.final_id);
+        self.after_snapshot = false;
+        updates.extend(diff.levels.into_iter().map(|l| (l.ts, l)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn diff(first_id: u64, final_id: u64, prev_final_id: Option<u64>) -> DepthUpdate {
+        DepthUpdate {
+            ts: 0,
+            first_id,
+            final_id,
+            prev_final_id,
+            levels: vec![OrderBookUpdate {
+                ts: 0,
+                sq: final_id,
+                side: Side::Bid,
+                px: dec("100"),
+                sz: dec("1"),
+            }],
+        }
+    }
+
+    fn synced(last_update_id: u64) -> DepthSync {
+        DepthSync {
+            last_update_id: Some(last_update_id),
+            after_snapshot: true,
+            ..DepthSync::default()
+        }
+    }
+
+    #[test]
+    fn depth_sync_skips_what_the_snapshot_covers() {
+        let (mut sync, mut updates) = (synced(10), VecDeque::new());
+        assert!(sync.apply(diff(5, 10, None), &mut updates).is_ok());
+        assert!(updates.is_empty());
+        assert_eq!(sync.last_update_id, Some(10));
+    }
+
+    #[test]
+    fn depth_sync_chains_diffs() {
+        let (mut sync, mut updates) = (synced(10), VecDeque::new());
+        // the first diff straddles the snapshot, later ones follow on
+        assert!(sync.apply(diff(8, 12, None), &mut updates).is_ok());
+        assert!(sync.apply(diff(13, 15, None), &mut updates).is_ok());
+        assert_eq!(updates.len(), 2);
+        assert_eq!(sync.last_update_id, Some(15));
+    }
+
+    #[test]
+    fn depth_sync_follows_previous_final_ids() {
+        let (mut sync, mut updates) = (synced(10), VecDeque::new());
+        assert!(sync.apply(diff(8, 12, Some(7)), &mut updates).is_ok());
+        assert!(sync.apply(diff(20, 25, Some(12)), &mut updates).is_ok());
+        assert!(sync.apply(diff(26, 30, Some(24)), &mut updates).is_err());
+        assert_eq!(updates.len(), 2);
+    }
+
+    #[test]
+    fn depth_sync_buffers_past_a_gap() {
+        let (mut sync, mut updates) = (synced(10), VecDeque::new());
+        assert!(sync.apply(diff(11, 12, None), &mut updates).is_ok());
+        let gap = sync.apply(diff(14, 15, None), &mut updates).unwrap_err();
+        assert_eq!(gap, "last 12 next 14..=15");
+        assert_eq!(sync.last_update_id, None);
+        assert_eq!(sync.buffered.len(), 1);
+        assert_eq!(updates.len(), 1);
+    }
+
+    #[test]
+    fn depth_sync_keeps_the_buffer_behind_a_gap() {
+        let (mut sync, mut updates) = (synced(10), VecDeque::new());
+        sync.buffered.push_back(diff(16, 17, None));
+        assert!(sync.apply(diff(14, 15, None), &mut updates).is_err());
+        let buffered: Vec<_> = sync.buffered.iter().map(|d| d.first_id).collect();
+        assert_eq!(buffered, vec![14, 16]);
+    }
+
+    fn trade(ts: Time, side: Side, px: &str, sz: &str) -> Trade {
+        Trade {
+            ts,
+            px: dec(px),
+            sz: dec(sz),
+            side,
+        }
+    }
+
+    #[test]
+    fn executions_classify_nothing_before_the_first_trade() {
+        let mut executions = Executions::default();
+        assert_eq!(
+            executions.classify(0, Side::Bid, dec("100"), dec("1")),
+            None
+        );
+    }
+
+    #[test]
+    fn executions_split_fills_from_cancels() {
+        let mut executions = Executions::default();
+        // a seller hits the resting bid
+        executions.add(&trade(1_000, Side::Ask, "100", "2"));
+        let removal = executions.classify(1_500, Side::Bid, dec("100"), dec("3"));
+        assert_eq!(
+            removal,
+            Some(Removal {
+                filled: dec("2"),
+                cancelled: dec("1"),
+            })
+        );
+        // the volume is used up
+        let removal = executions.classify(1_600, Side::Bid, dec("100"), dec("1"));
+        assert_eq!(removal.unwrap().filled, Decimal::ZERO);
+    }
+
+    #[test]
+    fn executions_match_only_the_resting_side_and_recent_trades() {
+        let mut executions = Executions::default();
+        executions.add(&trade(1_000, Side::Bid, "100", "2"));
+        let removal = executions.classify(1_000, Side::Bid, dec("100"), dec("1"));
+        assert_eq!(removal.unwrap().filled, Decimal::ZERO);
+        let late = 1_000 + MATCH_MS + 1;
+        let removal = executions.classify(late, Side::Ask, dec("100"), dec("1"));
+        assert_eq!(removal.unwrap().filled, Decimal::ZERO);
+        let removal = executions.classify(1_000, Side::Ask, dec("100"), dec("1"));
+        assert_eq!(removal.unwrap().filled, dec("1"));
+    }
+}
@@ -0,0 +1,463 @@
+use plotters::style::{
+    full_palette::{
+        AMBER_200, AMBER_600, AMBER_800, BLUE_200, BLUE_600, BLUE_800, CYAN_200, CYAN_600,
+        CYAN_800, GREEN_200, GREEN_600, GREEN_800, PINK_200, PINK_600, PINK_800, RED_200, RED_600,
+        RED_800,
+    },
+    Color, RGBAColor, RGBColor,
+};
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+use crate::book::Side;
+use crate::controls::FIELD;
+use crate::data::{load_stored, save_stored};
+use crate::palette::ColourMap;
+
+//MARK: - Heatmap Layers ---------------------------------------------
+
+/// Which sides of the book the heatmap shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sides {
+    Both,
+    Bids,
+    Asks,
+}
+
+impl Sides {
+    const ALL: [Sides; 3] = [Sides::Both, Sides::Bids, Sides::Asks];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Sides::Both => "both",
+            Sides::Bids => "bids",
+            Sides::Asks => "asks",
+        }
+    }
+
+    pub fn shows(&self, side: Side) -> bool {
+        match self {
+            Sides::Both => true,
+            Sides::Bids => side == Side::Bid,
+            Sides::Asks => side == Side::Ask,
+        }
+    }
+}
+
+/// Which size decreases are highlighted over the heatmap: pulled walls,
+/// executed ones, or both in their own colours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemovalView {
+    Off,
+    Cancelled,
+    Filled,
+    Both,
+}
+
+impl RemovalView {
+    const ALL: [RemovalView; 4] = [
+        RemovalView::Off,
+        RemovalView::Cancelled,
+        RemovalView::Filled,
+        RemovalView::Both,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            RemovalView::Off => "off",
+            RemovalView::Cancelled => "cancelled",
+            RemovalView::Filled => "filled",
+            RemovalView::Both => "both",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    pub fn shows_cancelled(&self) -> bool {
+        matches!(self, RemovalView::Cancelled | RemovalView::Both)
+    }
+
+    pub fn shows_filled(&self) -> bool {
+        matches!(self, RemovalView::Filled | RemovalView::Both)
+    }
+
+    /// Cancels stand out in orange whatever the colour map; fills are a
+    /// neutral that reads against either theme.
+    pub fn color(cancelled: bool, darkmode: bool) -> RGBColor {
+        match (cancelled, darkmode) {
+            (true, _) => RGBColor(249, 115, 22),
+            (false, true) => RGBColor(229, 231, 235),
+            (false, false) => RGBColor(55, 65, 81),
+        }
+    }
+}
+
+/// Colour ramp for one side of the heatmap, from faint (thin liquidity) to
+/// strong (walls).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ramp {
+    Cyan,
+    Green,
+    Blue,
+    Magenta,
+    Red,
+    Amber,
+}
+
+impl Ramp {
+    const ALL: [Ramp; 6] = [
+        Ramp::Cyan,
+        Ramp::Green,
+        Ramp::Blue,
+        Ramp::Magenta,
+        Ramp::Red,
+        Ramp::Amber,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Ramp::Cyan => "cyan",
+            Ramp::Green => "green",
+            Ramp::Blue => "blue",
+            Ramp::Magenta => "magenta",
+            Ramp::Red => "red",
+            Ramp::Amber => "amber",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.name() == name)
+    }
+
+    /// Faint and strong ends; strong is the brighter shade on a dark
+    /// background and the deeper one on a light background.
+    fn ends(&self, darkmode: bool) -> (RGBColor, RGBColor) {
+        let (light, mid, deep) = match self {
+            Ramp::Cyan => (CYAN_200, CYAN_600, CYAN_800),
+            Ramp::Green => (GREEN_200, GREEN_600, GREEN_800),
+            Ramp::Blue => (BLUE_200, BLUE_600, BLUE_800),
+            Ramp::Magenta => (PINK_200, PINK_600, PINK_800),
+            Ramp::Red => (RED_200, RED_600, RED_800),
+            Ramp::Amber => (AMBER_200, AMBER_600, AMBER_800),
+        };
+        if darkmode {
+            (mid, light)
+        } else {
+            (light, deep)
+        }
+    }
+
+    /// Colour for intensity `t` in `0..=1`, fading out towards zero.
+    pub fn color(&self, t: f64, darkmode: bool) -> RGBAColor {
+        let t = t.clamp(0.0, 1.0);
+        let (lo, hi) = self.ends(darkmode);
+        let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        RGBColor(lerp(lo.0, hi.0), lerp(lo.1, hi.1), lerp(lo.2, hi.2)).mix(t)
+    }
+}
+
+/// How resting size maps to intensity, relative to the sizes visible on
+/// the same side of the book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalisation {
+    /// Size over the largest size.
+    Linear,
+    /// `ln(1 + size)` over the same for the largest size.
+    Log,
+    /// Share of visible cells with at most this size.
+    Percentile,
+    /// Standard deviations above the mean, full strength at `Z_MAX`.
+    ZScore,
+    /// Like `Linear`, on price × size.
+    Notional,
+}
+
+impl Normalisation {
+    const ALL: [Normalisation; 5] = [
+        Normalisation::Linear,
+        Normalisation::Log,
+        Normalisation::Percentile,
+        Normalisation::ZScore,
+        Normalisation::Notional,
+    ];
+    pub const Z_MAX: f64 = 5.0;
+
+    fn name(&self) -> &'static str {
+        match self {
+            Normalisation::Linear => "linear",
+            Normalisation::Log => "log",
+            Normalisation::Percentile => "percentile",
+            Normalisation::ZScore => "z-score",
+            Normalisation::Notional => "notional",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|n| n.name() == name)
+    }
+}
+
+/// Heatmap display options, kept in local storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeatmapLayers {
+    pub sides: Sides,
+    pub bid_ramp: Ramp,
+    pub ask_ramp: Ramp,
+    pub normalisation: Normalisation,
+    /// Normalised range stretched over the ramp, in percent: cells below
+    /// the low end are not drawn, cells above the high end are at full
+    /// strength.
+    pub clip: (u8, u8),
+    pub colours: ColourMap,
+    pub removals: RemovalView,
+}
+
+impl Default for HeatmapLayers {
+    fn default() -> Self {
+        Self {
+            sides: Sides::Both,
+            bid_ramp: Ramp::Cyan,
+            ask_ramp: Ramp::Magenta,
+            normalisation: Normalisation::Log,
+            clip: (10, 100),
+            colours: ColourMap::Ramps,
+            removals: RemovalView::Off,
+        }
+    }
+}
+
+impl HeatmapLayers {
+    const STORAGE_KEY: &'static str = "heatmap-layers";
+
+    /// Stored as `sides,bid ramp,ask ramp,normalisation,clip low,clip high,colours,removals`,
+    /// e.g. `both,cyan,magenta,log,10,100,ramps,off`.
+    pub fn load() -> Self {
+        let stored = load_stored(Self::STORAGE_KEY);
+        let mut layers = Self::default();
+        if let Some(stored) = stored {
+            let mut parts = stored.split(',');
+            if let Some(sides) = parts.next() {
+                layers.sides = Sides::ALL
+                    .into_iter()
+                    .find(|s| s.name() == sides)
+                    .unwrap_or(layers.sides);
+            }
+            if let Some(ramp) = parts.next().and_then(Ramp::from_name) {
+                layers.bid_ramp = ramp;
+            }
+            if let Some(ramp) = parts.next().and_then(Ramp::from_name) {
+                layers.ask_ramp = ramp;
+            }
+            if let Some(normalisation) = parts.next().and_then(Normalisation::from_name) {
+                layers.normalisation = normalisation;
+            }
+            let lo = parts.next().and_then(|s| s.parse().ok());
+            let hi = parts.next().and_then(|s| s.parse().ok());
+            if let (Some(lo), Some(hi)) = (lo, hi) {
+                if lo < hi && hi <= 100 {
+                    layers.clip = (lo, hi);
+                }
+            }
+            if let Some(colours) = parts.next().and_then(ColourMap::from_name) {
+                layers.colours = colours;
+            }
+            if let Some(removals) = parts.next().and_then(RemovalView::from_name) {
+                layers.removals = removals;
+            }
+        }
+        layers
+    }
+
+    pub fn save(&self) {
+        let stored = format!(
+            "{},{},{},{},{},{},{},{}",
+            self.sides.name(),
+            self.bid_ramp.name(),
+            self.ask_ramp.name(),
+            self.normalisation.name(),
+            self.clip.0,
+            self.clip.1,
+            self.colours.name(),
+            self.removals.name()
+        );
+        save_stored(Self::STORAGE_KEY, &stored);
+    }
+
+    pub fn ramp(&self, side: Side) -> Ramp {
+        match side {
+            Side::Bid => self.bid_ramp,
+            Side::Ask => self.ask_ramp,
+        }
+    }
+
+    /// Colour of `side` at intensity `t`, on its ramp or the shared map.
+    pub fn color(&self, side: Side, t: f64, darkmode: bool) -> RGBAColor {
+        self.colours
+            .color(side, t, darkmode)
+            .unwrap_or_else(|| self.ramp(side).color(t, darkmode))
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct LayerControlsProps {
+    pub layers: HeatmapLayers,
+    pub on_change: Callback<HeatmapLayers>,
+}
+
+#[function_component(LayerControls)]
+pub fn layer_controls(props: &LayerControlsProps) -> Html {
+    let layers = props.layers;
+    let on_sides = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(sides) = Sides::ALL.into_iter().find(|s| s.name() == select.value()) {
+                on_change.emit(HeatmapLayers { sides, ..layers });
+            }
+        })
+    };
+    let on_ramp = |side: Side| {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(ramp) = Ramp::from_name(&select.value()) {
+                on_change.emit(match side {
+                    Side::Bid => HeatmapLayers {
+                        bid_ramp: ramp,
+                        ..layers
+                    },
+                    Side::Ask => HeatmapLayers {
+                        ask_ramp: ramp,
+                        ..layers
+                    },
+                });
+            }
+        })
+    };
+    let on_normalisation = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(normalisation) = Normalisation::from_name(&select.value()) {
+                on_change.emit(HeatmapLayers {
+                    normalisation,
+                    ..layers
+                });
+            }
+        })
+    };
+    let on_colours = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(colours) = ColourMap::from_name(&select.value()) {
+                on_change.emit(HeatmapLayers { colours, ..layers });
+            }
+        })
+    };
+    let on_removals = {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Some(removals) = RemovalView::from_name(&select.value()) {
+                on_change.emit(HeatmapLayers { removals, ..layers });
+            }
+        })
+    };
+    let on_clip = |high: bool| {
+        let on_change = props.on_change.clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let (mut lo, mut hi) = layers.clip;
+            match input.value().parse::<u8>() {
+                Ok(v) if high => hi = v.min(100),
+                Ok(v) => lo = v,
+                Err(_) => {}
+            }
+            if lo < hi {
+                on_change.emit(HeatmapLayers {
+                    clip: (lo, hi),
+                    ..layers
+                });
+            } else {
+                let current = if high { layers.clip.1 } else { layers.clip.0 };
+                input.set_value(&current.to_string());
+            }
+        })
+    };
+    let ramp_select = |side: Side| {
+        html! {
+            <select class={FIELD} onchange={on_ramp(side)} disabled={!layers.colours.blends_sides()}>
+                {for Ramp::ALL.iter().map(|r| html! {
+                    <option value={r.name()} selected={*r == layers.ramp(side)}>{r.name()}</option>
+                })}
+            </select>
+        }
+    };
+
+    html! {
+        <>
+        <span class="flex gap-2 items-center text-xs font-mono">
+            <label class="flex gap-1 items-center">
+                {"show"}
+                <select class={FIELD} onchange={on_sides}>
+                    {for Sides::ALL.iter().map(|s| html! {
+                        <option value={s.name()} selected={*s == layers.sides}>{s.name()}</option>
+                    })}
+                </select>
+            </label>
+            <label class="flex gap-1 items-center">
+                {"colours"}
+                <select class={FIELD} onchange={on_colours}>
+                    {for ColourMap::ALL.iter().map(|m| html! {
+                        <option value={m.name()} selected={*m == layers.colours}>{m.name()}</option>
+                    })}
+                </select>
+            </label>
+            <label class="flex gap-1 items-center">{"bids"}{ramp_select(Side::Bid)}</label>
+            <label class="flex gap-1 items-center">{"asks"}{ramp_select(Side::Ask)}</label>
+        </span>
+        <span class="flex gap-2 items-center text-xs font-mono">
+            <label class="flex gap-1 items-center">
+                {"scale"}
+                <select class={FIELD} onchange={on_normalisation}>
+                    {for Normalisation::ALL.iter().map(|n| html! {
+                        <option value={n.name()} selected={*n == layers.normalisation}>{n.name()}</option>
+                    })}
+                </select>
+            </label>
+            <label class="flex gap-1 items-center">
+                {"clip"}
+                <input
+                    type="number"
+                    min="0"
+                    max="99"
+                    class={classes!(FIELD, "w-12")}
+                    value={layers.clip.0.to_string()}
+                    onchange={on_clip(false)}
+                />
+                {"–"}
+                <input
+                    type="number"
+                    min="1"
+                    max="100"
+                    class={classes!(FIELD, "w-12")}
+                    value={layers.clip.1.to_string()}
+                    onchange={on_clip(true)}
+                />
+                {"%"}
+            </label>
+            <label class="flex gap-1 items-center" title="size taken out of the book, by whether trades account for it">
+                {"removed"}
+                <select class={FIELD} onchange={on_removals}>
+                    {for RemovalView::ALL.iter().map(|v| html! {
+                        <option value={v.name()} selected={*v == layers.removals}>{v.name()}</option>
+                    })}
+                </select>
+            </label>
+        </span>
+        </>
+    }
+}
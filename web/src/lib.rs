@@ -0,0 +1,17 @@
+pub mod book;
+pub mod chart;
+pub mod connection;
+pub mod controls;
+pub mod crosshair;
+pub mod data;
+pub mod iceberg;
+pub mod indicators;
+pub mod ingest;
+pub mod layers;
+pub mod palette;
+pub mod raster;
+pub mod recorder;
+pub mod source;
+pub mod spoof;
+pub mod theme_switch;
+pub mod tickers;
@@ -1,9 +1,7 @@
 use console_error_panic_hook::set_once as set_panic_hook;
+use web::{chart, connection, data, recorder, source, theme_switch, tickers};
+use web_sys::HtmlSelectElement;
 use yew::prelude::*;
-mod chart;
-mod data;
-mod theme_switch;
-mod tickers;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
@@ -23,13 +21,36 @@ pub fn App() -> Html {
             data_ref.dispatch(data::DataAction::SetSymbol(symbol.to_string()));
         });
     }
+    let on_venue_change = {
+        let data_ref = data_ctx.dispatcher().clone();
+        Callback::from(move |e: Event| {
+            let select = e.target_unchecked_into::<HtmlSelectElement>();
+            if let Ok(venue) = select.value().parse::<source::Venue>() {
+                data_ref.dispatch(data::DataAction::SetVenue(venue));
+            }
+        })
+    };
 
     html! {
+        <ContextProvider<data::DataContext> context={data_ctx.clone()}>
+        <tickers::TickerProvider>
         <div class="min-h-screen bg-gray-50 dark:bg-gray-900 text-black dark:text-white">
             <nav class="w-full h-16 py-2 bg-gray-100 dark:bg-gray-950">
                 <div class="container flex mx-auto gap-6 items-center h-full">
                     <h1 class="font-bold text-2xl text-black dark:text-white">{"OrderBook Visualisation Demo"}</h1>
                     <div class="flex-1"></div>
+                    <select
+                        class="px-2 py-1 rounded border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-900"
+                        onchange={on_venue_change}
+                    >
+                        {for source::Venue::ALL.iter().map(|venue| html! {
+                            <option value={venue.id()} selected={*venue == data_ctx.venue}>{venue.to_string()}</option>
+                        })}
+                    </select>
+                    <div class="flex flex-col gap-1">
+                        <connection::StatusBadge label="book" status={data_ctx.status.clone()}/>
+                        <tickers::TickerStatus/>
+                    </div>
                     {for links.iter().map(|(label, href)| html! {
                         <a target="_blank" class="block px-4 py-2 hover:bg-black hover:text-white dark:text-white dark:bg-indigo-500 dark:hover:bg-white dark:hover:text-black rounded border-black border" href={*href}>{label}</a>
                     })}
@@ -37,20 +58,21 @@ pub fn App() -> Html {
                 </div>
             </nav>
 
-            <ContextProvider<data::DataContext> context={data_ctx}>
             <div class="flex flex-row flex-grow h-[calc(100vh-64px)]">
                 <div class="w-1/5 min-w-[200px] h-full overflow-auto bg-gray-100 dark:bg-gray-950">
-                   <tickers::TickerProvider>
                    <tickers::TickerList/>
-                   </tickers::TickerProvider>
                 </div>
 
-                <div class="flex-grow h-full overflow-auto">
-                    <chart::Chart/>
+                <div class="flex flex-col flex-grow h-full overflow-auto">
+                    <recorder::Controls/>
+                    <div class="flex-grow min-h-0">
+                        <chart::Chart/>
+                    </div>
                 </div>
             </div>
-            </ContextProvider<data::DataContext>>
         </div>
+        </tickers::TickerProvider>
+        </ContextProvider<data::DataContext>>
     }
 }
 
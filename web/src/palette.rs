@@ -0,0 +1,105 @@
+use plotters::style::{Color, RGBAColor, RGBColor};
+
+use crate::book::Side;
+
+/// Heatmap colouring: the per-side hue ramps, or one map shared by both
+/// sides that draws whichever side is stronger in a cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourMap {
+    Ramps,
+    Viridis,
+    Magma,
+    Inferno,
+    Turbo,
+    Greyscale,
+    /// Blue for bids, red for asks, fading to neutral at zero.
+    Diverging,
+}
+
+impl ColourMap {
+    pub const ALL: [ColourMap; 7] = [
+        ColourMap::Ramps,
+        ColourMap::Viridis,
+        ColourMap::Magma,
+        ColourMap::Inferno,
+        ColourMap::Turbo,
+        ColourMap::Greyscale,
+        ColourMap::Diverging,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColourMap::Ramps => "ramps",
+            ColourMap::Viridis => "viridis",
+            ColourMap::Magma => "magma",
+            ColourMap::Inferno => "inferno",
+            ColourMap::Turbo => "turbo",
+            ColourMap::Greyscale => "greyscale",
+            ColourMap::Diverging => "diverging",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    /// Bids and asks are composited over each other on their own ramps;
+    /// every other map draws only the stronger side of a cell.
+    pub fn blends_sides(&self) -> bool {
+        *self == ColourMap::Ramps
+    }
+
+    /// Colour for intensity `t` in `0..=1` on a shared map; `None` for
+    /// `Ramps`, which colours by side.
+    pub fn color(&self, side: Side, t: f64, darkmode: bool) -> Option<RGBAColor> {
+        let t = t.clamp(0.0, 1.0);
+        let color = match self {
+            ColourMap::Ramps => return None,
+            ColourMap::Viridis => sample(&VIRIDIS, t).mix(1.0),
+            ColourMap::Magma => sample(&MAGMA, t).mix(1.0),
+            ColourMap::Inferno => sample(&INFERNO, t).mix(1.0),
+            ColourMap::Turbo => sample(&TURBO, t).mix(1.0),
+            // strong is whichever end contrasts with the background
+            ColourMap::Greyscale if darkmode => sample(&[0x303030, 0xffffff], t).mix(1.0),
+            ColourMap::Greyscale => sample(&[0xd0d0d0, 0x000000], t).mix(1.0),
+            ColourMap::Diverging => {
+                let stops = match side {
+                    Side::Bid => &BLUES,
+                    Side::Ask => &REDS,
+                };
+                sample(stops, t).mix(t)
+            }
+        };
+        Some(color)
+    }
+}
+
+/// Piecewise linear interpolation over evenly spaced `0xRRGGBB` stops.
+fn sample(stops: &[u32], t: f64) -> RGBColor {
+    let x = t * (stops.len() - 1) as f64;
+    let i = (x.floor() as usize).min(stops.len() - 2);
+    let f = x - i as f64;
+    let channel = |c: u32, shift: u32| ((c >> shift) & 0xff) as f64;
+    let lerp = |shift: u32| {
+        let (a, b) = (channel(stops[i], shift), channel(stops[i + 1], shift));
+        (a + (b - a) * f).round() as u8
+    };
+    RGBColor(lerp(16), lerp(8), lerp(0))
+}
+
+// matplotlib's perceptually uniform maps and Google's Turbo, at nine stops
+const VIRIDIS: [u32; 9] = [
+    0x440154, 0x472d7b, 0x3b528b, 0x2c728e, 0x21918c, 0x28ae80, 0x5ec962, 0xaddc30, 0xfde725,
+];
+const MAGMA: [u32; 9] = [
+    0x000004, 0x1c1044, 0x4f127b, 0x812581, 0xb5367a, 0xe55064, 0xfb8861, 0xfec287, 0xfcfdbf,
+];
+const INFERNO: [u32; 9] = [
+    0x000004, 0x1f0c48, 0x550f6d, 0x88226a, 0xba3655, 0xe35933, 0xf98c0a, 0xf9c932, 0xfcffa4,
+];
+const TURBO: [u32; 9] = [
+    0x30123b, 0x4662d7, 0x36aaf9, 0x1ae4b6, 0x72fe5e, 0xc8ef34, 0xfaba39, 0xf66b19, 0x7a0403,
+];
+// the two halves of ColorBrewer's RdBu, from the neutral middle outwards
+const BLUES: [u32; 6] = [0xf7f7f7, 0xd1e5f0, 0x92c5de, 0x4393c3, 0x2166ac, 0x053061];
+const REDS: [u32; 6] = [0xf7f7f7, 0xfddbc7, 0xf4a582, 0xd6604d, 0xb2182b, 0x67001f];
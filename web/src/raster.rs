@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::book::{Depth, Removal, Side};
+use crate::data::{Bins, Time};
+use crate::layers::{HeatmapLayers, Normalisation, RemovalView};
+
+/// Visible window of the chart and the pixel size of its plotting area.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub from: Time,
+    pub to: Time,
+    pub min_px: f64,
+    pub max_px: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Viewport {
+    /// Milliseconds per pixel column; `from` and `to` are whole columns
+    /// apart so the image can scroll by whole columns.
+    pub fn col_ms(&self) -> Time {
+        ((self.to - self.from) / self.width.max(1) as Time).max(1)
+    }
+
+    /// Time at the centre of pixel column `x`.
+    fn time_at(&self, x: usize) -> Time {
+        self.from + x as Time * self.col_ms() + self.col_ms() / 2
+    }
+
+    /// Column showing time `t`, clamped to the viewport.
+    fn column(&self, t: Time) -> usize {
+        (t.saturating_sub(self.from) / self.col_ms()).min(self.width as Time) as usize
+    }
+
+    /// Same pixels for the same prices and durations, only scrolled.
+    fn same_grid(&self, other: &Viewport) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.min_px == other.min_px
+            && self.max_px == other.max_px
+            && self.to - self.from == other.to - other.from
+    }
+
+    /// Pixel rows covered by the price bin `[lo, hi)`, at least one.
+    fn rows(&self, lo: f64, hi: f64) -> (usize, usize) {
+        let scale = self.height as f64 / (self.max_px - self.min_px);
+        let y = |px: f64| ((self.max_px - px) * scale).clamp(0.0, self.height as f64);
+        let top = y(hi).floor() as usize;
+        let bottom = y(lo).ceil() as usize;
+        (top, bottom.max((top + 1).min(self.height)))
+    }
+}
+
+/// Distribution of the values visible on one side, which sizes are
+/// normalised against. Every cell goes through the same mapping: the
+/// normalised score, stretched from the clip range onto `0..=1`.
+#[derive(Debug, Clone)]
+struct Scale {
+    normalisation: Normalisation,
+    sorted: Vec<f64>,
+    max: f64,
+    mean: f64,
+    std: f64,
+}
+
+impl Scale {
+    fn new(normalisation: Normalisation, mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        Some(Self {
+            normalisation,
+            max: *values.last().unwrap(),
+            mean,
+            std: var.sqrt(),
+            sorted: values,
+        })
+    }
+
+    /// Where `value` sits in the distribution, `0..=1`.
+    fn score(&self, value: f64) -> f64 {
+        let score = match self.normalisation {
+            Normalisation::Linear | Normalisation::Notional => value / self.max,
+            Normalisation::Log => value.ln_1p() / self.max.ln_1p(),
+            Normalisation::Percentile => {
+                let rank = self.sorted.partition_point(|&v| v <= value);
+                rank as f64 / self.sorted.len() as f64
+            }
+            Normalisation::ZScore if self.std > 0.0 => {
+                (value - self.mean) / self.std / Normalisation::Z_MAX
+            }
+            Normalisation::ZScore => 0.0,
+        };
+        if score.is_finite() {
+            score.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Inverse of `score`: the value scoring `score`.
+    fn value_at(&self, score: f64) -> f64 {
+        match self.normalisation {
+            Normalisation::Linear | Normalisation::Notional => score * self.max,
+            Normalisation::Log => (score * self.max.ln_1p()).exp_m1(),
+            Normalisation::Percentile => {
+                let rank = (score * self.sorted.len() as f64).ceil() as usize;
+                self.sorted[rank.clamp(1, self.sorted.len()) - 1]
+            }
+            Normalisation::ZScore => self.mean + score * Normalisation::Z_MAX * self.std,
+        }
+        .max(0.0)
+    }
+
+    fn intensity(&self, value: f64, (lo, hi): (u8, u8)) -> f64 {
+        if value <= 0.0 {
+            return 0.0;
+        }
+        let (lo, hi) = (lo as f64 / 100.0, hi as f64 / 100.0);
+        ((self.score(value) - lo) / (hi - lo)).clamp(0.0, 1.0)
+    }
+}
+
+/// What a cell's size is normalised as: the size itself, or its notional
+/// at the bin's middle price.
+fn value(normalisation: Normalisation, size: f64, price: f64) -> f64 {
+    match normalisation {
+        Normalisation::Notional => size * price,
+        _ => size,
+    }
+}
+
+//MARK: - Backing Buffer ---------------------------------------------
+
+/// Offscreen RGBA image of the heatmap, one cell per price bin × time bin,
+/// kept between redraws so only new or changed time columns are painted.
+///
+/// Scrolling shifts the image left by whole columns. Anything that changes
+/// the pixel grid or the colours (size, price range, zoom, bins, layers,
+/// theme) repaints it from scratch.
+#[derive(Debug, Default)]
+pub struct Backing {
+    pixels: Vec<u8>,
+    view: Option<Viewport>,
+    style: Option<(Bins, HeatmapLayers, bool)>,
+    scales: Vec<(Side, Scale)>,
+    /// Largest highlighted removal in one cell, what removals are scaled by.
+    removal_max: f64,
+}
+
+impl Backing {
+    /// Bring the image up to `view`. `dirty` is the earliest heatmap time
+    /// changed since the last call; columns from there on are repainted
+    /// along with the ones that scrolled in.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+        bins: Bins,
+        layers: HeatmapLayers,
+        darkmode: bool,
+        view: Viewport,
+        dirty: Option<Time>,
+    ) -> &[u8] {
+        let style = (bins, layers, darkmode);
+        let rows = visible_rows(heatmap, removals, bins, view);
+        let scales = scales(&rows, step(bins), layers, view);
+        let removal_max = removal_max(&rows, bins, layers.removals, view);
+
+        let full = match self.view {
+            Some(prev) => {
+                self.style != Some(style)
+                    || !prev.same_grid(&view)
+                    || view.from < prev.from
+                    || self.drifted(&scales)
+                    || drifted(removal_max, self.removal_max)
+            }
+            None => true,
+        };
+        let first_column = if full {
+            self.pixels = vec![0u8; view.width * view.height * 4];
+            self.scales = scales;
+            self.removal_max = removal_max;
+            self.style = Some(style);
+            0
+        } else {
+            let prev = self.view.unwrap();
+            let shift = ((view.from - prev.from) / view.col_ms()) as usize;
+            self.shift(shift.min(view.width), view);
+            // the trailing time bin may still have been filling
+            let settled = prev.to.saturating_sub(bins.time_step);
+            let since = dirty.map_or(settled, |d| d.min(settled));
+            view.column(since).min(view.width.saturating_sub(shift))
+        };
+        self.view = Some(view);
+
+        if first_column < view.width {
+            paint(
+                &mut self.pixels,
+                &rows,
+                bins,
+                layers,
+                darkmode,
+                view,
+                (&self.scales, self.removal_max),
+                first_column,
+            );
+        }
+        &self.pixels
+    }
+
+    /// Value painted at intensity `t` on each shown side, by the scales the
+    /// image was last painted with.
+    pub fn value_at(&self, t: f64, (lo, hi): (u8, u8)) -> Vec<(Side, f64)> {
+        let score = (lo as f64 + t * (hi as f64 - lo as f64)) / 100.0;
+        self.scales
+            .iter()
+            .map(|(side, scale)| (*side, scale.value_at(score)))
+            .collect()
+    }
+
+    /// Intensities are scaled by the stats the image was painted with;
+    /// repaint once the book has moved far enough to make them stale.
+    fn drifted(&self, scales: &[(Side, Scale)]) -> bool {
+        scales.len() != self.scales.len()
+            || scales
+                .iter()
+                .zip(&self.scales)
+                .any(|((_, a), (_, b))| drifted(a.max, b.max) || drifted(a.mean, b.mean))
+    }
+
+    /// Move every row `columns` to the left, clearing what scrolls in.
+    fn shift(&mut self, columns: usize, view: Viewport) {
+        if columns == 0 {
+            return;
+        }
+        let stride = view.width * 4;
+        for line in self.pixels.chunks_exact_mut(stride) {
+            line.copy_within(columns * 4.., 0);
+            line[stride - columns * 4..].fill(0);
+        }
+    }
+}
+
+/// Moved more than a quarter away from what the image was painted with.
+fn drifted(now: f64, painted: f64) -> bool {
+    (now - painted).abs() > 0.25 * painted.abs()
+}
+
+//MARK: - Rasteriser ---------------------------------------------
+
+/// A price bin's low price, depth cells and removal cells, if any.
+type Row<'a> = (
+    f64,
+    &'a BTreeMap<Time, Depth>,
+    Option<&'a BTreeMap<Time, Removal>>,
+);
+
+fn step(bins: Bins) -> f64 {
+    bins.price_step.to_f64().unwrap()
+}
+
+/// Price bins overlapping the viewport's price range, by their low price.
+fn visible_rows<'a>(
+    heatmap: &'a HashMap<Decimal, BTreeMap<Time, Depth>>,
+    removals: &'a HashMap<Decimal, BTreeMap<Time, Removal>>,
+    bins: Bins,
+    view: Viewport,
+) -> Vec<Row<'a>> {
+    let step = step(bins);
+    heatmap
+        .iter()
+        .map(|(price, cells)| (price.to_f64().unwrap(), cells, removals.get(price)))
+        .filter(|&(lo, _, _)| lo + step > view.min_px && lo < view.max_px)
+        .collect()
+}
+
+/// Intensity scale per shown side, from the depths in force across the
+/// window.
+fn scales(rows: &[Row], step: f64, layers: HeatmapLayers, view: Viewport) -> Vec<(Side, Scale)> {
+    [Side::Bid, Side::Ask]
+        .into_iter()
+        .filter(|side| layers.sides.shows(*side))
+        .filter_map(|side| {
+            let values: Vec<_> = rows
+                .iter()
+                .flat_map(|&(lo, cells, _)| {
+                    let carried = cells.range(..=view.from).next_back();
+                    carried
+                        .into_iter()
+                        .chain(cells.range(view.from + 1..=view.to))
+                        .map(move |(_, depth)| (lo + step / 2.0, depth))
+                })
+                .map(|(price, depth)| {
+                    let size = depth.side(side).to_f64().unwrap();
+                    value(layers.normalisation, size, price)
+                })
+                .filter(|&v| v.is_finite() && v != 0.0)
+                .collect();
+            Some((side, Scale::new(layers.normalisation, values)?))
+        })
+        .collect()
+}
+
+/// Largest highlighted removal in a single cell across the window.
+fn removal_max(rows: &[Row], bins: Bins, shown: RemovalView, view: Viewport) -> f64 {
+    if shown == RemovalView::Off {
+        return 0.0;
+    }
+    rows.iter()
+        .filter_map(|(_, _, removals)| *removals)
+        .flat_map(|cells| cells.range(view.from.saturating_sub(bins.time_step)..=view.to))
+        .map(|(_, r)| highlighted(r, shown))
+        .fold(0.0, f64::max)
+}
+
+/// The part of a removal `shown` highlights, as (filled, cancelled).
+fn highlighted_parts(removal: &Removal, shown: RemovalView) -> (f64, f64) {
+    let part = |on: bool, size: Decimal| if on { size.to_f64().unwrap() } else { 0.0 };
+    (
+        part(shown.shows_filled(), removal.filled),
+        part(shown.shows_cancelled(), removal.cancelled),
+    )
+}
+
+fn highlighted(removal: &Removal, shown: RemovalView) -> f64 {
+    let (filled, cancelled) = highlighted_parts(removal, shown);
+    filled.max(cancelled)
+}
+
+/// Paint columns `first_column..` of every row. Each cell holds the last
+/// depth of its price bin at or before the column's time; bids and asks are
+/// composited on their own ramps, or the stronger one drawn on a shared map.
+/// Highlighted removals go on top, summed over the time bins each column
+/// covers.
+#[allow(clippy::too_many_arguments)]
+fn paint(
+    pixels: &mut [u8],
+    rows: &[Row],
+    bins: Bins,
+    layers: HeatmapLayers,
+    darkmode: bool,
+    view: Viewport,
+    (scales, removal_max): (&[(Side, Scale)], f64),
+    first_column: usize,
+) {
+    let step = step(bins);
+    let stride = view.width * 4;
+    for line in pixels.chunks_exact_mut(stride) {
+        line[first_column * 4..].fill(0);
+    }
+    if scales.is_empty() {
+        return;
+    }
+
+    let mut column = vec![[0u8; 4]; view.width - first_column];
+    for &(lo, cells, removals) in rows {
+        let (top, bottom) = view.rows(lo, lo + step);
+        if top >= bottom {
+            continue;
+        }
+
+        // walk the row's cells alongside the pixel columns
+        let start = view.time_at(first_column);
+        let mut depth = cells
+            .range(..=start)
+            .next_back()
+            .map(|(_, d)| *d)
+            .unwrap_or_default();
+        let mut next = cells.range(start + 1..).peekable();
+        for (x, pixel) in column.iter_mut().enumerate() {
+            let t = view.time_at(first_column + x);
+            while let Some((_, d)) = next.next_if(|(&ts, _)| ts <= t) {
+                depth = *d;
+            }
+            *pixel = [0; 4];
+            let mut strongest: Option<(Side, f64)> = None;
+            for (side, scale) in scales {
+                let size = depth.side(*side).to_f64().unwrap();
+                let value = value(layers.normalisation, size, lo + step / 2.0);
+                let intensity = scale.intensity(value, layers.clip);
+                if intensity <= 0.0 {
+                    continue;
+                }
+                if layers.colours.blends_sides() {
+                    let color = layers.color(*side, intensity, darkmode);
+                    over(pixel, [color.0, color.1, color.2], color.3);
+                } else if strongest.is_none_or(|(_, t)| intensity > t) {
+                    strongest = Some((*side, intensity));
+                }
+            }
+            if let Some((side, intensity)) = strongest {
+                let color = layers.color(side, intensity, darkmode);
+                over(pixel, [color.0, color.1, color.2], color.3);
+            }
+
+            let Some(removals) = removals.filter(|_| removal_max > 0.0) else {
+                continue;
+            };
+            let from = view.from + (first_column + x) as Time * view.col_ms();
+            let (filled, cancelled) = removals
+                .range(from.saturating_sub(bins.time_step - 1)..from + view.col_ms())
+                .map(|(_, r)| highlighted_parts(r, layers.removals))
+                .fold((0.0, 0.0), |(f, c), (df, dc)| (f + df, c + dc));
+            for (size, is_cancel) in [(filled, false), (cancelled, true)] {
+                if size > 0.0 {
+                    let t = (size / removal_max).min(1.0).sqrt();
+                    let color = RemovalView::color(is_cancel, darkmode);
+                    over(pixel, [color.0, color.1, color.2], 0.3 + 0.7 * t);
+                }
+            }
+        }
+
+        for line in pixels[top * stride..bottom * stride].chunks_exact_mut(stride) {
+            let line = &mut line[first_column * 4..];
+            for (dst, src) in line.chunks_exact_mut(4).zip(&column) {
+                if src[3] > 0 {
+                    dst.copy_from_slice(src);
+                }
+            }
+        }
+    }
+}
+
+/// Composite `rgb` at `alpha` over a straight-alpha RGBA pixel.
+fn over(pixel: &mut [u8; 4], rgb: [u8; 3], alpha: f64) {
+    let below = pixel[3] as f64 / 255.0;
+    let out = alpha + below * (1.0 - alpha);
+    if out <= 0.0 {
+        return;
+    }
+    for i in 0..3 {
+        let c = rgb[i] as f64 * alpha + pixel[i] as f64 * below * (1.0 - alpha);
+        pixel[i] = (c / out).round() as u8;
+    }
+    pixel[3] = (out * 255.0).round() as u8;
+}
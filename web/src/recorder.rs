@@ -0,0 +1,403 @@
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{anyhow, bail};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{js_sys, Blob, BlobPropertyBag, HtmlAnchorElement, HtmlInputElement, Url};
+use yew::prelude::*;
+use yew_hooks::prelude::*;
+
+use crate::data::{self, DataAction, DataContext, Time};
+use crate::source::Venue;
+
+//MARK: - Recording ---------------------------------------------
+
+pub use codec::Record;
+
+/// Normalised updates, klines and trades as they were accepted into `Data`, each
+/// tagged with the local receive time.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub venue: Venue,
+    pub symbol: String,
+    pub entries: Vec<(Time, Record)>,
+}
+
+impl Recording {
+    pub fn new(venue: Venue, symbol: String) -> Self {
+        Self {
+            venue,
+            symbol,
+            entries: Vec::new(),
+        }
+    }
+
+    /// First and last receive time.
+    pub fn span(&self) -> Option<(Time, Time)> {
+        Some((self.entries.first()?.0, self.entries.last()?.0))
+    }
+
+    pub fn file_name(&self) -> String {
+        let started = self
+            .span()
+            .and_then(|(start, _)| chrono::DateTime::from_timestamp_millis(start as i64))
+            .map(|t| t.format("%Y%m%dT%H%M%S").to_string())
+            .unwrap_or_default();
+        format!("lbplot-{}-{}-{}.lbp", self.venue.id(), self.symbol, started)
+    }
+
+    /// Encoded with `codec`, see `codec::put_header` for the layout.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16 + self.entries.len() * 24);
+        codec::put_header(&mut buf, self.venue.id(), &self.symbol);
+        let mut last_rx = 0;
+        for (rx, record) in &self.entries {
+            codec::put_record(&mut buf, &mut last_rx, *rx, record);
+        }
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = codec::Reader::new(bytes)?;
+        let venue = reader.venue().parse::<Venue>()?;
+        let symbol = reader.symbol().to_string();
+
+        let mut entries = Vec::new();
+        while let Some((rx, record)) = reader.next_record()? {
+            if record == Record::Reset {
+                bail!("segment file, not a recording");
+            }
+            entries.push((rx, record));
+        }
+        Ok(Self {
+            venue,
+            symbol,
+            entries,
+        })
+    }
+}
+
+//MARK: - Replay ---------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    X1,
+    X2,
+    X10,
+    Max,
+}
+
+impl Speed {
+    pub const ALL: [Speed; 4] = [Speed::X1, Speed::X2, Speed::X10, Speed::Max];
+
+    /// Recorded milliseconds per wall-clock millisecond, `None` for as fast
+    /// as possible.
+    pub fn factor(&self) -> Option<u64> {
+        match self {
+            Speed::X1 => Some(1),
+            Speed::X2 => Some(2),
+            Speed::X10 => Some(10),
+            Speed::Max => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Speed::X1 => "1x",
+            Speed::X2 => "2x",
+            Speed::X10 => "10x",
+            Speed::Max => "max",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    position: Time,
+    cursor: usize,
+    speed: Speed,
+    paused: bool,
+    seek: Option<Time>,
+    stopped: bool,
+}
+
+/// Playback cursor over a `Recording`, shared between the replay task in
+/// `Data` and the transport controls.
+#[derive(Clone)]
+pub struct Replay {
+    pub recording: Rc<Recording>,
+    state: Rc<RefCell<ReplayState>>,
+}
+
+impl Replay {
+    pub fn new(recording: Rc<Recording>) -> Self {
+        let position = recording.span().map(|(start, _)| start).unwrap_or_default();
+        Self {
+            recording,
+            state: Rc::new(RefCell::new(ReplayState {
+                position,
+                cursor: 0,
+                speed: Speed::X1,
+                paused: false,
+                seek: None,
+                stopped: false,
+            })),
+        }
+    }
+
+    pub fn position(&self) -> Time {
+        self.state.borrow().position
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.state.borrow().speed
+    }
+
+    pub fn set_speed(&self, speed: Speed) {
+        self.state.borrow_mut().speed = speed;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.borrow().paused
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.state.borrow_mut().paused = paused;
+    }
+
+    pub fn seek(&self, to: Time) {
+        self.state.borrow_mut().seek = Some(to);
+    }
+
+    pub fn stop(&self) {
+        self.state.borrow_mut().stopped = true;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.borrow().stopped
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().cursor >= self.recording.entries.len()
+    }
+
+    pub(crate) fn take_seek(&self) -> Option<Time> {
+        self.state.borrow_mut().seek.take()
+    }
+
+    /// Back to the first entry, the caller clears what was already fed.
+    pub(crate) fn rewind(&self) {
+        let mut state = self.state.borrow_mut();
+        state.cursor = 0;
+        state.position = self
+            .recording
+            .span()
+            .map(|(start, _)| start)
+            .unwrap_or_default();
+    }
+
+    /// Entries received up to `until`, advancing the cursor past them.
+    pub(crate) fn advance(&self, until: Time) -> &[(Time, Record)] {
+        let mut state = self.state.borrow_mut();
+        let start = state.cursor;
+        let entries = &self.recording.entries;
+        let end = start + entries[start..].partition_point(|(rx, _)| *rx <= until);
+        state.cursor = end;
+        state.position = state.position.max(until);
+        &entries[start..end]
+    }
+
+    /// Receive time `n` entries ahead of the cursor, for unthrottled playback.
+    pub(crate) fn lookahead(&self, n: usize) -> Option<Time> {
+        let cursor = self.state.borrow().cursor;
+        let entries = &self.recording.entries;
+        entries
+            .get(cursor + n)
+            .or(entries.last())
+            .map(|(rx, _)| *rx)
+    }
+}
+
+//MARK: - Browser Files ---------------------------------------------
+
+fn download(name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let array = js_sys::Uint8Array::from(bytes);
+    let parts = js_sys::Array::of1(&array.buffer());
+    let mut options = BlobPropertyBag::new();
+    options.type_("application/octet-stream");
+    let blob = Blob::new_with_buffer_source_sequence_and_options(&parts, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let anchor = document
+        .create_element("a")?
+        .dyn_into::<HtmlAnchorElement>()?;
+    anchor.set_href(&url);
+    anchor.set_download(name);
+    anchor.click();
+    Url::revoke_object_url(&url)
+}
+
+async fn read_file(input: &HtmlInputElement) -> anyhow::Result<Recording> {
+    let file = input
+        .files()
+        .and_then(|files| files.get(0))
+        .ok_or_else(|| anyhow!("no file selected"))?;
+    let buffer = JsFuture::from(file.array_buffer())
+        .await
+        .map_err(|e| anyhow!("read {}: {:?}", file.name(), e))?;
+    Recording::decode(&js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+//MARK: - Controls ---------------------------------------------
+
+#[function_component(Controls)]
+pub fn controls() -> Html {
+    let data_ctx = use_context::<DataContext>().unwrap();
+
+    // recorder and replay state live outside yew, poll them
+    let tick = use_state(|| 0u32);
+    {
+        let tick = tick.clone();
+        use_interval(move || tick.set(tick.wrapping_add(1)), 500);
+    }
+
+    let button = "px-2 py-1 rounded border border-gray-300 dark:border-gray-700 hover:bg-gray-200 dark:hover:bg-gray-800 disabled:opacity-40";
+    let replay = data_ctx.replay.borrow().clone();
+
+    let transport = if let Some(replay) = replay {
+        let (start, end) = replay.recording.span().unwrap_or_default();
+        let on_play = {
+            let replay = replay.clone();
+            Callback::from(move |_| replay.set_paused(!replay.is_paused()))
+        };
+        let on_speed = {
+            let replay = replay.clone();
+            Callback::from(move |e: Event| {
+                let value = e
+                    .target_unchecked_into::<web_sys::HtmlSelectElement>()
+                    .value();
+                if let Some(speed) = Speed::ALL.into_iter().find(|s| s.label() == value) {
+                    replay.set_speed(speed);
+                }
+            })
+        };
+        let on_seek = {
+            let replay = replay.clone();
+            Callback::from(move |e: InputEvent| {
+                let value = e.target_unchecked_into::<HtmlInputElement>().value();
+                if let Ok(to) = value.parse::<Time>() {
+                    replay.seek(to);
+                }
+            })
+        };
+        let on_live = {
+            let data_ref = data_ctx.dispatcher();
+            Callback::from(move |_| data_ref.dispatch(DataAction::GoLive))
+        };
+        let position = chrono::DateTime::from_timestamp_millis(replay.position() as i64)
+            .map(|t| t.format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        html! {
+            <>
+                <span class="font-bold">{"replay"}</span>
+                <button class={button} onclick={on_play}>
+                    {if replay.is_paused() || replay.is_finished() { "▶ play" } else { "⏸ pause" }}
+                </button>
+                <select class={button} onchange={on_speed}>
+                    {for Speed::ALL.iter().map(|s| html! {
+                        <option value={s.label()} selected={*s == replay.speed()}>{s.label()}</option>
+                    })}
+                </select>
+                <input
+                    type="range"
+                    class="w-64"
+                    min={start.to_string()}
+                    max={end.to_string()}
+                    value={replay.position().to_string()}
+                    oninput={on_seek}
+                />
+                <span>{position}</span>
+                <button class={button} onclick={on_live}>{"go live"}</button>
+            </>
+        }
+    } else {
+        let recording = data_ctx.is_recording();
+        let on_record = {
+            let data = (*data_ctx).clone();
+            Callback::from(move |_| {
+                if data.is_recording() {
+                    data.stop_recording();
+                } else {
+                    data.start_recording();
+                }
+            })
+        };
+        let recorded = data_ctx.recorded();
+        html! {
+            <button class={button} onclick={on_record}>
+                {if recording {
+                    format!("■ stop ({} recorded)", recorded)
+                } else {
+                    "● record".to_string()
+                }}
+            </button>
+        }
+    };
+
+    let on_download = {
+        let data = (*data_ctx).clone();
+        Callback::from(move |_| {
+            if let Some(recording) = data.recording.lock().unwrap().as_ref() {
+                let _ = download(&recording.file_name(), &recording.encode());
+            }
+        })
+    };
+    let on_load = {
+        let data_ref = data_ctx.dispatcher();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let data_ref = data_ref.clone();
+            yew::platform::spawn_local(async move {
+                match read_file(&input).await {
+                    Ok(recording) => data_ref.dispatch(DataAction::Replay(Rc::new(recording))),
+                    Err(e) => web_sys::console::log_1(&format!("🚫 [replay] {:?}", e).into()),
+                }
+                input.set_value("");
+            });
+        })
+    };
+
+    let on_history_server = {
+        let data_ref = data_ctx.dispatcher();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            data::set_history_server(&input.value());
+            data_ref.dispatch(DataAction::GoLive);
+        })
+    };
+
+    html! {
+        <div class="flex flex-row gap-2 items-center px-2 py-1 text-xs font-mono">
+            {transport}
+            <button class={button} onclick={on_download} disabled={data_ctx.recorded() == 0}>
+                {"⤓ download"}
+            </button>
+            <label class={classes!(button, "cursor-pointer")}>
+                {"⤒ load"}
+                <input type="file" accept=".lbp" class="hidden" onchange={on_load}/>
+            </label>
+            <div class="flex-1"></div>
+            <label class="flex gap-1 items-center" title="lbplot-server to preload history from">
+                {"history"}
+                <input
+                    type="url"
+                    class="w-48 px-2 py-1 rounded border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-900"
+                    placeholder="http://127.0.0.1:8787"
+                    value={data::history_server().unwrap_or_default()}
+                    onchange={on_history_server}
+                />
+            </label>
+        </div>
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::anyhow;
+use serde_json::Value;
+use web_sys::console;
+
+use super::{decimal, integer, now, number, Instrument, MarketDataSource, MarketEvent, Venue};
+use crate::book::Side;
+use crate::data::{DepthSnapshot, DepthUpdate, Kline, OrderBookUpdate, Time, Trade};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Market {
+    Spot,
+    UsdM,
+}
+
+/// Binance combined streams: `@depth@100ms` diffs on top of a REST snapshot,
+/// plus klines and `@aggTrade`. Spot and USD-M futures share the wire format, futures adds
+/// `pu` (previous final update id) to each diff.
+pub struct Binance {
+    market: Market,
+}
+
+impl Binance {
+    pub fn spot() -> Self {
+        Self {
+            market: Market::Spot,
+        }
+    }
+
+    pub fn usd_m() -> Self {
+        Self {
+            market: Market::UsdM,
+        }
+    }
+}
+
+impl MarketDataSource for Binance {
+    fn venue(&self) -> Venue {
+        match self.market {
+            Market::Spot => Venue::BinanceSpot,
+            Market::UsdM => Venue::BinanceUsdM,
+        }
+    }
+
+    fn stream_url(&self, symbol: &str) -> String {
+        let symbol = symbol.to_lowercase();
+        match self.market {
+            Market::Spot => format!(
+                "wss://data-stream.binance.vision/stream?streams={}@depth@100ms/{}@kline_1s/{}@aggTrade",
+                symbol, symbol, symbol
+            ),
+            // futures has no 1s klines
+            Market::UsdM => format!(
+                "wss://fstream.binance.com/stream?streams={}@depth@100ms/{}@kline_1m/{}@aggTrade",
+                symbol, symbol, symbol
+            ),
+        }
+    }
+
+    fn snapshot_url(&self, symbol: &str) -> Option<String> {
+        let symbol = symbol.to_uppercase();
+        Some(match self.market {
+            Market::Spot => format!(
+                "https://data-api.binance.vision/api/v3/depth?symbol={}&limit=1000",
+                symbol
+            ),
+            Market::UsdM => format!(
+                "https://fapi.binance.com/fapi/v1/depth?symbol={}&limit=1000",
+                symbol
+            ),
+        })
+    }
+
+    fn parse_snapshot(&self, body: &str) -> anyhow::Result<DepthSnapshot> {
+        let msg: Value = serde_json::from_str(body)?;
+        // spot snapshots carry no event time, stamp it on arrival
+        let ts = msg["E"].as_u64().unwrap_or_else(now);
+        let last_update_id = msg["lastUpdateId"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("depth snapshot without lastUpdateId"))?;
+        let mut levels = parse_levels(&msg["bids"], ts, last_update_id, Side::Bid)?;
+        levels.extend(parse_levels(&msg["asks"], ts, last_update_id, Side::Ask)?);
+        Ok(DepthSnapshot {
+            ts,
+            last_update_id,
+            levels,
+        })
+    }
+
+    fn instrument_url(&self, symbol: &str) -> Option<String> {
+        let symbol = symbol.to_uppercase();
+        Some(match self.market {
+            Market::Spot => format!(
+                "https://data-api.binance.vision/api/v3/exchangeInfo?symbol={}",
+                symbol
+            ),
+            Market::UsdM => "https://fapi.binance.com/fapi/v1/exchangeInfo".to_string(),
+        })
+    }
+
+    fn parse_instrument(&self, symbol: &str, body: &str) -> anyhow::Result<Instrument> {
+        let msg: Value = serde_json::from_str(body)?;
+        let symbol = symbol.to_uppercase();
+        let info = msg["symbols"]
+            .as_array()
+            .and_then(|symbols| symbols.iter().find(|s| s["symbol"] == symbol.as_str()))
+            .ok_or_else(|| anyhow!("{} not in exchangeInfo", symbol))?;
+        let tick_size = info["filters"]
+            .as_array()
+            .and_then(|filters| filters.iter().find(|f| f["filterType"] == "PRICE_FILTER"))
+            .and_then(|f| f["tickSize"].as_str())
+            .ok_or_else(|| anyhow!("{} has no PRICE_FILTER", symbol))?;
+        Instrument::parse_tick_size(tick_size)
+    }
+
+    fn parse(&self, msg: &str) -> anyhow::Result<Vec<MarketEvent>> {
+        let msg: Value = serde_json::from_str(msg)?;
+        Ok(match msg["stream"].as_str() {
+            Some(stream) if stream.contains("kline") => {
+                // console::log_1(&format!("📈 [ws] kline {:?}", msg).into());
+                let kline = &msg["data"]["k"];
+                let op = number(&kline["o"])?;
+                let vb = number(&kline["V"])?;
+                let vo = number(&kline["v"])?;
+                vec![MarketEvent::Kline(Kline {
+                    ts: integer(&kline["t"])?,
+                    op,
+                    hi: number(&kline["h"])?,
+                    lo: number(&kline["l"])?,
+                    cl: number(&kline["c"])?,
+                    vo,
+                    vb,
+                    vs: vo - vb,
+                    tc: integer(&kline["T"])?,
+                })]
+            }
+            Some(stream) if stream.contains("aggTrade") => {
+                let data = &msg["data"];
+                vec![MarketEvent::Trade(Trade {
+                    ts: integer(&data["T"])?,
+                    px: decimal(&data["p"])?,
+                    sz: decimal(&data["q"])?,
+                    // `m`: the buyer was the maker, so a seller took
+                    side: if data["m"].as_bool().unwrap_or(false) {
+                        Side::Ask
+                    } else {
+                        Side::Bid
+                    },
+                })]
+            }
+            Some(stream) if stream.contains("depth") => {
+                // console::log_1(&format!("📊 [ws] depth {:?}", msg).into());
+                vec![MarketEvent::Depth(parse_depth_update(&msg["data"])?)]
+            }
+            _ => {
+                console::log_1(&format!("🚫 [ws] stream {:?}", msg).into());
+                Vec::new()
+            }
+        })
+    }
+}
+
+fn parse_levels(v: &Value, ts: Time, sq: u64, side: Side) -> anyhow::Result<Vec<OrderBookUpdate>> {
+    let Some(levels) = v.as_array() else {
+        return Ok(Vec::new());
+    };
+    levels
+        .iter()
+        .map(|l| {
+            Ok(OrderBookUpdate {
+                ts,
+                sq,
+                side,
+                px: decimal(&l[0])?,
+                sz: decimal(&l[1])?,
+            })
+        })
+        .collect()
+}
+
+fn parse_depth_update(data: &Value) -> anyhow::Result<DepthUpdate> {
+    let ts = integer(&data["E"])?;
+    let first_id = integer(&data["U"])?;
+    let final_id = integer(&data["u"])?;
+    let prev_final_id = data["pu"].as_u64();
+    let mut levels = parse_levels(&data["b"], ts, final_id, Side::Bid)?;
+    levels.extend(parse_levels(&data["a"], ts, final_id, Side::Ask)?);
+    Ok(DepthUpdate {
+        ts,
+        first_id,
+        final_id,
+        prev_final_id,
+        levels,
+    })
+}
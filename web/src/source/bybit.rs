@@ -0,0 +1,167 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde_json::{json, Value};
+
+use super::{decimal, integer, number, Instrument, MarketDataSource, MarketEvent, Venue};
+use crate::book::Side;
+use crate::data::{DepthSnapshot, DepthUpdate, Kline, OrderBookUpdate, Time, Trade};
+
+/// Bybit v5 public spot: `orderbook.200` (snapshot then deltas, sequenced by
+/// `u`), 1 minute klines and `publicTrade`. Bybit expects a ping every 20 seconds.
+///
+/// `u` only ever grows but may skip, so the book diffs are numbered locally
+/// and only `u` going backwards counts as a gap. A snapshot, sent again
+/// whenever Bybit resets the book on its side, replaces the book.
+#[derive(Default)]
+pub struct Bybit {
+    last_update_id: Cell<Option<u64>>,
+    book_seq: Cell<u64>,
+}
+
+impl Bybit {
+    fn levels(v: &Value, ts: Time, sq: u64, side: Side) -> anyhow::Result<Vec<OrderBookUpdate>> {
+        let Some(levels) = v.as_array() else {
+            return Ok(Vec::new());
+        };
+        levels
+            .iter()
+            .map(|l| {
+                Ok(OrderBookUpdate {
+                    ts,
+                    sq,
+                    side,
+                    px: decimal(&l[0])?,
+                    sz: decimal(&l[1])?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl MarketDataSource for Bybit {
+    fn venue(&self) -> Venue {
+        Venue::Bybit
+    }
+
+    fn stream_url(&self, _symbol: &str) -> String {
+        "wss://stream.bybit.com/v5/public/spot".to_string()
+    }
+
+    fn subscriptions(&self, symbol: &str) -> Vec<String> {
+        let symbol = symbol.to_uppercase();
+        vec![json!({
+            "op": "subscribe",
+            "args": [
+                format!("orderbook.200.{}", symbol),
+                format!("kline.1.{}", symbol),
+                format!("publicTrade.{}", symbol),
+            ],
+        })
+        .to_string()]
+    }
+
+    fn heartbeat(&self) -> Option<(Duration, String)> {
+        Some((Duration::from_secs(20), json!({ "op": "ping" }).to_string()))
+    }
+
+    fn instrument_url(&self, symbol: &str) -> Option<String> {
+        Some(format!(
+            "https://api.bybit.com/v5/market/instruments-info?category=spot&symbol={}",
+            symbol.to_uppercase()
+        ))
+    }
+
+    fn parse_instrument(&self, symbol: &str, body: &str) -> anyhow::Result<Instrument> {
+        let msg: Value = serde_json::from_str(body)?;
+        let tick_size = msg["result"]["list"][0]["priceFilter"]["tickSize"]
+            .as_str()
+            .ok_or_else(|| anyhow!("{} not in instruments-info", symbol))?;
+        Instrument::parse_tick_size(tick_size)
+    }
+
+    fn reset(&self) {
+        self.last_update_id.set(None);
+        self.book_seq.set(0);
+    }
+
+    fn parse(&self, msg: &str) -> anyhow::Result<Vec<MarketEvent>> {
+        let msg: Value = serde_json::from_str(msg)?;
+        let Some(topic) = msg["topic"].as_str() else {
+            // subscription acks and pongs
+            return Ok(Vec::new());
+        };
+        let empty = Vec::new();
+
+        if topic.starts_with("orderbook") {
+            let data = &msg["data"];
+            let ts = integer(&msg["ts"])?;
+            let update_id = integer(&data["u"])?;
+            let snapshot = msg["type"] == "snapshot";
+            let last_update_id = self.last_update_id.replace(Some(update_id));
+            if !snapshot && last_update_id.is_some_and(|last| update_id <= last) {
+                return Ok(vec![MarketEvent::Gap]);
+            }
+            let book_seq = self.book_seq.get() + 1;
+            self.book_seq.set(book_seq);
+            let mut levels = Self::levels(&data["b"], ts, book_seq, Side::Bid)?;
+            levels.extend(Self::levels(&data["a"], ts, book_seq, Side::Ask)?);
+            Ok(vec![if snapshot {
+                MarketEvent::Snapshot(DepthSnapshot {
+                    ts,
+                    last_update_id: book_seq,
+                    levels,
+                })
+            } else {
+                MarketEvent::Depth(DepthUpdate {
+                    ts,
+                    first_id: book_seq,
+                    final_id: book_seq,
+                    prev_final_id: None,
+                    levels,
+                })
+            }])
+        } else if topic.starts_with("kline") {
+            msg["data"]
+                .as_array()
+                .unwrap_or(&empty)
+                .iter()
+                .map(|candle| {
+                    Ok(MarketEvent::Kline(Kline {
+                        ts: integer(&candle["start"])?,
+                        op: number(&candle["open"])?,
+                        hi: number(&candle["high"])?,
+                        lo: number(&candle["low"])?,
+                        cl: number(&candle["close"])?,
+                        vo: number(&candle["volume"])?,
+                        vb: f64::NAN,
+                        vs: f64::NAN,
+                        tc: integer(&candle["end"])?,
+                    }))
+                })
+                .collect()
+        } else if topic.starts_with("publicTrade") {
+            msg["data"]
+                .as_array()
+                .unwrap_or(&empty)
+                .iter()
+                .map(|trade| {
+                    Ok(MarketEvent::Trade(Trade {
+                        ts: integer(&trade["T"])?,
+                        px: decimal(&trade["p"])?,
+                        sz: decimal(&trade["v"])?,
+                        // the taker's side
+                        side: if trade["S"] == "Buy" {
+                            Side::Bid
+                        } else {
+                            Side::Ask
+                        },
+                    }))
+                })
+                .collect()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use web_sys::console;
+
+use super::{
+    decimal, now, number, parse_rfc3339, split_symbol, Instrument, MarketDataSource, MarketEvent,
+    Venue,
+};
+use crate::book::Side;
+use crate::data::{DepthSnapshot, DepthUpdate, Kline, OrderBookUpdate, Time, Trade};
+
+/// Levels per side subscribed to, and kept in the local book the checksum is
+/// taken over.
+const BOOK_DEPTH: usize = 1000;
+/// Levels per side covered by the checksum.
+const CHECKSUM_DEPTH: usize = 10;
+
+/// Kraken websockets v2: `book` (snapshot then diffs), 1 minute `ohlc` and
+/// `trade`, plus `instrument` for the precision the book checksum needs.
+///
+/// The book channel carries a CRC32 checksum of the top of the book instead
+/// of sequence numbers, so a copy of the book is kept here, truncated to the
+/// subscribed depth as Kraken does, and checked after every message. Diffs
+/// are numbered locally in arrival order.
+#[derive(Default)]
+pub struct Kraken {
+    book_seq: Cell<u64>,
+    bids: RefCell<BTreeMap<Decimal, Decimal>>,
+    asks: RefCell<BTreeMap<Decimal, Decimal>>,
+    /// Price and qty decimals by pair, from the `instrument` channel.
+    precision: RefCell<HashMap<String, (usize, usize)>>,
+}
+
+impl Kraken {
+    fn pair(symbol: &str) -> String {
+        let (base, quote) = split_symbol(symbol);
+        format!("{}/{}", base, quote)
+    }
+
+    fn levels(v: &Value, ts: Time, sq: u64, side: Side) -> anyhow::Result<Vec<OrderBookUpdate>> {
+        let Some(levels) = v.as_array() else {
+            return Ok(Vec::new());
+        };
+        levels
+            .iter()
+            .map(|l| {
+                Ok(OrderBookUpdate {
+                    ts,
+                    sq,
+                    side,
+                    px: decimal(&l["price"])?,
+                    sz: decimal(&l["qty"])?,
+                })
+            })
+            .collect()
+    }
+
+    /// Apply `levels` to the local book and cut it back to `BOOK_DEPTH`;
+    /// the levels cut off are added to `levels` as removals.
+    fn apply(&self, levels: &mut Vec<OrderBookUpdate>, ts: Time, sq: u64) {
+        let (mut bids, mut asks) = (self.bids.borrow_mut(), self.asks.borrow_mut());
+        for level in levels.iter() {
+            let book = match level.side {
+                Side::Bid => &mut *bids,
+                Side::Ask => &mut *asks,
+            };
+            if level.sz.is_zero() {
+                book.remove(&level.px);
+            } else {
+                book.insert(level.px, level.sz);
+            }
+        }
+        let cut = |book: &mut BTreeMap<Decimal, Decimal>, side: Side| {
+            let excess = book.len().saturating_sub(BOOK_DEPTH);
+            let worst: Vec<_> = match side {
+                Side::Bid => book.keys().take(excess).copied().collect(),
+                Side::Ask => book.keys().rev().take(excess).copied().collect(),
+            };
+            worst
+                .into_iter()
+                .map(move |px| {
+                    book.remove(&px);
+                    OrderBookUpdate {
+                        ts,
+                        sq,
+                        side,
+                        px,
+                        sz: Decimal::ZERO,
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        levels.extend(cut(&mut bids, Side::Bid));
+        levels.extend(cut(&mut asks, Side::Ask));
+    }
+
+    /// CRC32 of the top asks then the top bids, each price and qty written
+    /// at the pair's precision without the point and leading zeros.
+    fn checksum(&self, (price_precision, qty_precision): (usize, usize)) -> u32 {
+        let digits = |v: &Decimal, precision: usize| {
+            format!("{:.*}", precision, v)
+                .replace('.', "")
+                .trim_start_matches('0')
+                .to_string()
+        };
+        let (bids, asks) = (self.bids.borrow(), self.asks.borrow());
+        let top = asks
+            .iter()
+            .take(CHECKSUM_DEPTH)
+            .chain(bids.iter().rev().take(CHECKSUM_DEPTH));
+        let mut text = String::new();
+        for (px, sz) in top {
+            text.push_str(&digits(px, price_precision));
+            text.push_str(&digits(sz, qty_precision));
+        }
+        crc32(text.as_bytes())
+    }
+}
+
+/// CRC-32 (IEEE), as used by Kraken's book checksum.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+impl MarketDataSource for Kraken {
+    fn venue(&self) -> Venue {
+        Venue::Kraken
+    }
+
+    fn stream_url(&self, _symbol: &str) -> String {
+        "wss://ws.kraken.com/v2".to_string()
+    }
+
+    fn subscriptions(&self, symbol: &str) -> Vec<String> {
+        let pair = Self::pair(symbol);
+        vec![
+            json!({
+                "method": "subscribe",
+                "params": { "channel": "instrument" },
+            })
+            .to_string(),
+            json!({
+                "method": "subscribe",
+                "params": { "channel": "book", "symbol": [pair], "depth": BOOK_DEPTH },
+            })
+            .to_string(),
+            json!({
+                "method": "subscribe",
+                "params": { "channel": "ohlc", "symbol": [pair], "interval": 1 },
+            })
+            .to_string(),
+            json!({
+                "method": "subscribe",
+                "params": { "channel": "trade", "symbol": [pair], "snapshot": false },
+            })
+            .to_string(),
+        ]
+    }
+
+    fn instrument_url(&self, symbol: &str) -> Option<String> {
+        let (base, quote) = split_symbol(symbol);
+        Some(format!(
+            "https://api.kraken.com/0/public/AssetPairs?pair={}{}",
+            base, quote
+        ))
+    }
+
+    fn parse_instrument(&self, symbol: &str, body: &str) -> anyhow::Result<Instrument> {
+        let msg: Value = serde_json::from_str(body)?;
+        // keyed by Kraken's own pair name, e.g. XXBTZUSD
+        let tick_size = msg["result"]
+            .as_object()
+            .and_then(|pairs| pairs.values().next())
+            .and_then(|pair| pair["tick_size"].as_str())
+            .ok_or_else(|| anyhow!("{} not in AssetPairs: {}", symbol, msg["error"]))?;
+        Instrument::parse_tick_size(tick_size)
+    }
+
+    fn reset(&self) {
+        self.book_seq.set(0);
+        self.bids.borrow_mut().clear();
+        self.asks.borrow_mut().clear();
+    }
+
+    fn parse(&self, msg: &str) -> anyhow::Result<Vec<MarketEvent>> {
+        let msg: Value = serde_json::from_str(msg)?;
+        let empty = Vec::new();
+        let data = msg["data"].as_array().unwrap_or(&empty);
+
+        match msg["channel"].as_str() {
+            Some("instrument") => {
+                let pairs = msg["data"]["pairs"].as_array().unwrap_or(&empty);
+                let mut precision = self.precision.borrow_mut();
+                for pair in pairs {
+                    let (Some(symbol), Some(price), Some(qty)) = (
+                        pair["symbol"].as_str(),
+                        pair["price_precision"].as_u64(),
+                        pair["qty_precision"].as_u64(),
+                    ) else {
+                        continue;
+                    };
+                    precision.insert(symbol.to_string(), (price as usize, qty as usize));
+                }
+                Ok(Vec::new())
+            }
+            Some("book") => {
+                let snapshot = msg["type"] == "snapshot";
+                let mut events = Vec::new();
+                for book in data {
+                    let book_seq = self.book_seq.get() + 1;
+                    self.book_seq.set(book_seq);
+                    // snapshots carry no timestamp
+                    let ts = book["timestamp"]
+                        .as_str()
+                        .and_then(parse_rfc3339)
+                        .unwrap_or_else(now);
+                    let mut levels = Self::levels(&book["bids"], ts, book_seq, Side::Bid)?;
+                    levels.extend(Self::levels(&book["asks"], ts, book_seq, Side::Ask)?);
+                    if snapshot {
+                        self.bids.borrow_mut().clear();
+                        self.asks.borrow_mut().clear();
+                    }
+                    self.apply(&mut levels, ts, book_seq);
+
+                    // until the instrument snapshot arrives there is nothing
+                    // to check against
+                    let precision = book["symbol"]
+                        .as_str()
+                        .and_then(|symbol| self.precision.borrow().get(symbol).copied());
+                    if let (Some(precision), Some(expected)) =
+                        (precision, book["checksum"].as_u64())
+                    {
+                        let checksum = self.checksum(precision);
+                        if checksum as u64 != expected {
+                            console::log_1(
+                                &format!("🚫 [ws] kraken checksum {} != {}", checksum, expected)
+                                    .into(),
+                            );
+                            return Ok(vec![MarketEvent::Gap]);
+                        }
+                    }
+
+                    events.push(if snapshot {
+                        MarketEvent::Snapshot(DepthSnapshot {
+                            ts,
+                            last_update_id: book_seq,
+                            levels,
+                        })
+                    } else {
+                        MarketEvent::Depth(DepthUpdate {
+                            ts,
+                            first_id: book_seq,
+                            final_id: book_seq,
+                            prev_final_id: None,
+                            levels,
+                        })
+                    });
+                }
+                Ok(events)
+            }
+            Some("ohlc") => data
+                .iter()
+                .map(|candle| {
+                    let ts = candle["interval_begin"]
+                        .as_str()
+                        .and_then(parse_rfc3339)
+                        .ok_or_else(|| anyhow!("ohlc without interval_begin"))?;
+                    let interval = candle["interval"].as_u64().unwrap_or(1);
+                    Ok(MarketEvent::Kline(Kline {
+                        ts,
+                        op: number(&candle["open"])?,
+                        hi: number(&candle["high"])?,
+                        lo: number(&candle["low"])?,
+                        cl: number(&candle["close"])?,
+                        vo: number(&candle["volume"])?,
+                        vb: f64::NAN,
+                        vs: f64::NAN,
+                        tc: ts + interval * 60 * 1000 - 1,
+                    }))
+                })
+                .collect(),
+            Some("trade") => data
+                .iter()
+                .map(|trade| {
+                    Ok(MarketEvent::Trade(Trade {
+                        ts: trade["timestamp"]
+                            .as_str()
+                            .and_then(parse_rfc3339)
+                            .unwrap_or_else(now),
+                        px: decimal(&trade["price"])?,
+                        sz: decimal(&trade["qty"])?,
+                        // the taker's side
+                        side: if trade["side"] == "buy" {
+                            Side::Bid
+                        } else {
+                            Side::Ask
+                        },
+                    }))
+                })
+                .collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
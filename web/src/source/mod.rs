@@ -0,0 +1,290 @@
+use std::{fmt, rc::Rc, str::FromStr, time::Duration};
+
+use anyhow::anyhow;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Response, Window, WorkerGlobalScope};
+
+use crate::data::{DepthSnapshot, DepthUpdate, Kline, Time, Trade};
+
+mod binance;
+mod bybit;
+mod coinbase;
+mod kraken;
+
+//MARK: - Venues ---------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Venue {
+    #[default]
+    BinanceSpot,
+    BinanceUsdM,
+    Coinbase,
+    Kraken,
+    Bybit,
+}
+
+impl Venue {
+    pub const ALL: [Venue; 5] = [
+        Venue::BinanceSpot,
+        Venue::BinanceUsdM,
+        Venue::Coinbase,
+        Venue::Kraken,
+        Venue::Bybit,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Venue::BinanceSpot => "binance",
+            Venue::BinanceUsdM => "binance-usdm",
+            Venue::Coinbase => "coinbase",
+            Venue::Kraken => "kraken",
+            Venue::Bybit => "bybit",
+        }
+    }
+
+    /// A fresh adapter; adapters keep per-connection state so each session
+    /// gets its own.
+    pub fn source(&self) -> Rc<dyn MarketDataSource> {
+        match self {
+            Venue::BinanceSpot => Rc::new(binance::Binance::spot()),
+            Venue::BinanceUsdM => Rc::new(binance::Binance::usd_m()),
+            Venue::Coinbase => Rc::new(coinbase::Coinbase::default()),
+            Venue::Kraken => Rc::new(kraken::Kraken::default()),
+            Venue::Bybit => Rc::new(bybit::Bybit::default()),
+        }
+    }
+}
+
+impl fmt::Display for Venue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Venue::BinanceSpot => "Binance Spot",
+            Venue::BinanceUsdM => "Binance USD-M",
+            Venue::Coinbase => "Coinbase",
+            Venue::Kraken => "Kraken",
+            Venue::Bybit => "Bybit",
+        };
+        f.write_str(label)
+    }
+}
+
+impl FromStr for Venue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Venue::ALL
+            .into_iter()
+            .find(|v| v.id() == s)
+            .ok_or_else(|| anyhow!("unknown venue {}", s))
+    }
+}
+
+//MARK: - Source Interface ---------------------------------------------
+
+/// Symbol metadata from the venue's reference data endpoint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instrument {
+    pub tick_size: Decimal,
+}
+
+impl Instrument {
+    fn parse_tick_size(tick_size: &str) -> anyhow::Result<Self> {
+        let tick_size = tick_size.parse::<Decimal>()?.normalize();
+        if tick_size <= Decimal::ZERO {
+            return Err(anyhow!("tick size {}", tick_size));
+        }
+        Ok(Self { tick_size })
+    }
+
+    /// Decimal places prices are quoted to.
+    pub fn precision(&self) -> usize {
+        self.tick_size.scale() as usize
+    }
+}
+
+/// Normalised market data, as produced by every venue adapter.
+#[derive(Debug, Clone)]
+pub enum MarketEvent {
+    /// Full book image, replaces whatever the local book holds.
+    Snapshot(DepthSnapshot),
+    /// Incremental book diff.
+    Depth(DepthUpdate),
+    Kline(Kline),
+    Trade(Trade),
+    /// The venue's own sequencing shows messages were lost.
+    Gap,
+}
+
+/// A venue's market data stream.
+///
+/// Symbols are passed in the canonical concatenated form used by the ticker
+/// list (`BTCUSDT`); adapters map them onto the venue's own naming.
+pub trait MarketDataSource {
+    fn venue(&self) -> Venue;
+
+    fn stream_url(&self, symbol: &str) -> String;
+
+    /// Messages sent once the socket opens, e.g. channel subscriptions.
+    fn subscriptions(&self, _symbol: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Application level ping for venues that drop quiet clients.
+    fn heartbeat(&self) -> Option<(Duration, String)> {
+        None
+    }
+
+    /// REST depth snapshot endpoint, for venues whose stream only carries
+    /// diffs. Venues that open the stream with a book image return `None`
+    /// and recover from gaps by reconnecting.
+    fn snapshot_url(&self, _symbol: &str) -> Option<String> {
+        None
+    }
+
+    fn parse_snapshot(&self, _body: &str) -> anyhow::Result<DepthSnapshot> {
+        Err(anyhow!("{} has no REST depth snapshot", self.venue()))
+    }
+
+    /// Reference data endpoint carrying the symbol's tick size.
+    fn instrument_url(&self, _symbol: &str) -> Option<String> {
+        None
+    }
+
+    fn parse_instrument(&self, _symbol: &str, _body: &str) -> anyhow::Result<Instrument> {
+        Err(anyhow!("{} has no instrument metadata", self.venue()))
+    }
+
+    /// Called when a new connection opens, before its first message.
+    fn reset(&self) {}
+
+    /// Parse one text frame; control frames yield no events. An error only
+    /// loses this frame, the stream carries on.
+    fn parse(&self, msg: &str) -> anyhow::Result<Vec<MarketEvent>>;
+}
+
+//MARK: - Helpers ---------------------------------------------
+
+const QUOTES: [&str; 10] = [
+    "FDUSD", "USDT", "USDC", "TUSD", "BUSD", "USD", "EUR", "BTC", "ETH", "BNB",
+];
+
+/// Split a concatenated symbol into base and quote, `BTCUSDT` -> (`BTC`, `USDT`).
+pub fn split_symbol(symbol: &str) -> (String, String) {
+    let symbol = symbol.to_uppercase();
+    QUOTES
+        .iter()
+        .find(|q| symbol.len() > q.len() && symbol.ends_with(*q))
+        .map(|q| {
+            let base = &symbol[..symbol.len() - q.len()];
+            (base.to_string(), q.to_string())
+        })
+        .unwrap_or((symbol, String::new()))
+}
+
+/// Decimal from a JSON string or number. Numbers arrive as the shortest
+/// `f64` that round-trips, which serde_json writes in scientific notation
+/// when very small or large, like Kraken's `1e-8` lots.
+fn decimal(v: &Value) -> anyhow::Result<Decimal> {
+    let decimal = match v {
+        Value::String(s) => s.parse(),
+        Value::Number(n) => {
+            let n = n.to_string();
+            n.parse().or_else(|_| Decimal::from_scientific(&n))
+        }
+        _ => return Err(anyhow!("not a decimal: {}", v)),
+    };
+    decimal.map_err(|e| anyhow!("not a decimal: {} {}", v, e))
+}
+
+/// `f64` from a JSON number or numeric string, for kline fields.
+fn number(v: &Value) -> anyhow::Result<f64> {
+    match v {
+        Value::String(s) => s.parse().map_err(|e| anyhow!("not a number: {} {}", v, e)),
+        _ => v.as_f64().ok_or_else(|| anyhow!("not a number: {}", v)),
+    }
+}
+
+/// Integer from a JSON number or numeric string, for ids and timestamps.
+fn integer(v: &Value) -> anyhow::Result<u64> {
+    match v {
+        Value::String(s) => s
+            .parse()
+            .map_err(|e| anyhow!("not an integer: {} {}", v, e)),
+        _ => v.as_u64().ok_or_else(|| anyhow!("not an integer: {}", v)),
+    }
+}
+
+/// Millisecond epoch of an RFC 3339 timestamp, as sent by Coinbase and Kraken.
+fn parse_rfc3339(ts: &str) -> Option<Time> {
+    chrono::DateTime::parse_from_rfc3339(ts)
+        .ok()
+        .map(|t| t.timestamp_millis() as Time)
+}
+
+fn now() -> Time {
+    web_sys::js_sys::Date::now() as Time
+}
+
+/// Works from the page and from the ingest worker alike.
+async fn fetch(url: &str) -> anyhow::Result<Response> {
+    let global = web_sys::js_sys::global();
+    let request = match global.dyn_ref::<Window>() {
+        Some(window) => window.fetch_with_str(url),
+        None => global
+            .unchecked_into::<WorkerGlobalScope>()
+            .fetch_with_str(url),
+    };
+    let resp = JsFuture::from(request)
+        .await
+        .map_err(|e| anyhow!("fetch {}: {:?}", url, e))?
+        .dyn_into::<Response>()
+        .map_err(|e| anyhow!("fetch {}: {:?}", url, e))?;
+    if !resp.ok() {
+        return Err(anyhow!("fetch {}: HTTP {}", url, resp.status()));
+    }
+    Ok(resp)
+}
+
+pub async fn fetch_text(url: &str) -> anyhow::Result<String> {
+    let resp = fetch(url).await?;
+    let text = JsFuture::from(resp.text().map_err(|e| anyhow!("{:?}", e))?)
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?
+        .as_string()
+        .unwrap_or_default();
+    Ok(text)
+}
+
+pub async fn fetch_bytes(url: &str) -> anyhow::Result<Vec<u8>> {
+    let resp = fetch(url).await?;
+    let buf = JsFuture::from(resp.array_buffer().map_err(|e| anyhow!("{:?}", e))?)
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    Ok(web_sys::js_sys::Uint8Array::new(&buf).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_reads_strings_and_numbers() {
+        let v: Value =
+            serde_json::from_str(r#"["0.00000001", 0.00000001, 65000.1, 1.2345678901234568e16]"#)
+                .unwrap();
+        let expected = ["0.00000001", "0.00000001", "65000.1", "12345678901234568"];
+        for (v, expected) in v.as_array().unwrap().iter().zip(expected) {
+            assert_eq!(decimal(v).unwrap(), expected.parse::<Decimal>().unwrap());
+        }
+    }
+
+    #[test]
+    fn decimal_rejects_other_values() {
+        assert!(decimal(&Value::Bool(true)).is_err());
+        assert!(decimal(&Value::String("1.2.3".into())).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use rust_decimal::prelude::*;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+use crate::book::{BookSample, Depth, Removal, Side};
+use crate::data::{load_stored, save_stored, walk, Bins, Time};
+
+/// Detections kept, newest first.
+const MAX_SPOOFS: usize = 200;
+/// Fills may explain at most this share of the drop for a wall to count as
+/// pulled.
+const MAX_FILLED_SHARE: f64 = 0.1;
+
+//MARK: - Settings ---------------------------------------------
+
+/// Detector thresholds, kept in local storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpoofSettings {
+    /// A wall is at least this many times the typical level size.
+    pub multiple: f64,
+    /// Walls gone within this long are flagged.
+    pub max_life: Time,
+    /// Walls further than this from their side's touch when they appeared
+    /// are ignored, in basis points.
+    pub max_distance_bps: f64,
+}
+
+impl Default for SpoofSettings {
+    fn default() -> Self {
+        Self {
+            multiple: 5.0,
+            max_life: 10_000,
+            max_distance_bps: 50.0,
+        }
+    }
+}
+
+impl SpoofSettings {
+    const STORAGE_KEY: &'static str = "spoof-detector";
+
+    /// Stored as `multiple,max life ms,max distance bps`.
+    pub fn load() -> Self {
+        let stored = load_stored(Self::STORAGE_KEY);
+        let mut settings = Self::default();
+        if let Some(stored) = stored {
+            let mut parts = stored.split(',');
+            if let Some(multiple) = parts.next().and_then(|s| s.parse().ok()) {
+                settings.multiple = multiple;
+            }
+            if let Some(max_life) = parts.next().and_then(|s| s.parse().ok()) {
+                settings.max_life = max_life;
+            }
+            if let Some(max_distance_bps) = parts.next().and_then(|s| s.parse().ok()) {
+                settings.max_distance_bps = max_distance_bps;
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let stored = format!(
+            "{},{},{}",
+            self.multiple, self.max_life, self.max_distance_bps
+        );
+        save_stored(Self::STORAGE_KEY, &stored);
+    }
+}
+
+//MARK: - Detector ---------------------------------------------
+
+/// A wall that appeared, lived briefly and was pulled rather than traded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spoof {
+    pub side: Side,
+    /// Low price of the heatmap row.
+    pub price: Decimal,
+    /// First time bin at wall size, and the one it was gone in.
+    pub start: Time,
+    pub end: Time,
+    pub peak: Decimal,
+    /// Peak as a multiple of the typical level size.
+    pub multiple: f64,
+    /// Distance from its side's touch when it appeared, in basis points.
+    pub distance_bps: Option<f64>,
+    pub filled: Decimal,
+    pub cancelled: Decimal,
+}
+
+impl Spoof {
+    pub fn life(&self) -> Time {
+        self.end - self.start
+    }
+
+    /// Where the chart marks it: the middle of its row of `step` when it
+    /// vanished.
+    pub fn marker(&self, step: Decimal) -> (Time, f64) {
+        let price = (self.price + step / Decimal::TWO).to_f64().unwrap();
+        (self.end, price)
+    }
+}
+
+/// Finds walls, sizes at least `settings.multiple` times the median resting
+/// size on their side and within `settings.max_distance_bps` of the touch,
+/// that were gone within `settings.max_life` with fills explaining next to
+/// none of the drop.
+///
+/// Walks each time bin once, as it closes, keeping the walls still standing
+/// and the size distribution between scans. Decreases only have a
+/// fill/cancel split once trades are flowing, so walls without one are
+/// never flagged. Walls already standing when the history starts have no
+/// known start and are skipped too.
+#[derive(Default)]
+pub struct SpoofDetector {
+    settings: Option<(SpoofSettings, Bins)>,
+    /// Bins before this have been walked.
+    scanned_to: Option<Time>,
+    /// The first bin walked.
+    origin: Time,
+    /// Walls standing, by side and row: first bin at wall size and peak.
+    walls: HashMap<(Side, Decimal), (Time, Decimal)>,
+    sizes: [SizeHistogram; 2],
+    spoofs: Vec<Spoof>,
+}
+
+impl SpoofDetector {
+    /// Walk the bins closed since the last scan, up to `until`. Anything
+    /// changed at or before a bin already walked, like history arriving or
+    /// retention downsampling, starts over from the beginning.
+    pub fn scan(
+        &mut self,
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+        samples: &BTreeMap<Time, BookSample>,
+        until: Time,
+        changed: Option<Time>,
+        (settings, bins): (SpoofSettings, Bins),
+    ) {
+        if self.settings != Some((settings, bins))
+            || changed.is_some_and(|c| self.scanned_to.is_some_and(|s| c < s))
+        {
+            *self = Self {
+                settings: Some((settings, bins)),
+                ..Self::default()
+            };
+        }
+        let from = self.scanned_to;
+        if from.is_some_and(|from| from >= until) {
+            return;
+        }
+        if from.is_none() {
+            let Some(origin) = heatmap.values().filter_map(|c| c.keys().next()).min() else {
+                return;
+            };
+            self.origin = *origin;
+        }
+
+        // the typical size takes in the new bins before they are judged
+        for cells in heatmap.values() {
+            for (_, depth) in walk(cells, from, until) {
+                for (side, sizes) in [Side::Bid, Side::Ask].iter().zip(&mut self.sizes) {
+                    sizes.add(depth.side(*side));
+                }
+            }
+        }
+
+        for (side, sizes) in [Side::Bid, Side::Ask].into_iter().zip(&self.sizes) {
+            let Some(typical) = sizes.median() else {
+                continue;
+            };
+            let threshold = typical * settings.multiple;
+            for (price, cells) in heatmap {
+                for (&ts, depth) in walk(cells, from, until) {
+                    let size = depth.side(side);
+                    if size.to_f64().unwrap() >= threshold {
+                        let (_, peak) = self.walls.entry((side, *price)).or_insert((ts, size));
+                        *peak = (*peak).max(size);
+                        continue;
+                    }
+                    let Some((start, peak)) = self.walls.remove(&(side, *price)) else {
+                        continue;
+                    };
+                    if start == self.origin || ts - start > settings.max_life {
+                        continue;
+                    }
+                    let row_mid = (price + bins.price_step / Decimal::TWO).to_f64().unwrap();
+                    let distance_bps = samples.range(..=start).next_back().map(|(_, sample)| {
+                        let (touch, _) = match side {
+                            Side::Bid => sample.bid,
+                            Side::Ask => sample.ask,
+                        };
+                        (row_mid - touch).abs() / touch * 10_000.0
+                    });
+                    if distance_bps.is_some_and(|d| d > settings.max_distance_bps) {
+                        continue;
+                    }
+                    let removal = removals
+                        .get(price)
+                        .into_iter()
+                        .flat_map(|r| r.range(start..=ts))
+                        .fold(Removal::default(), |mut sum, (_, r)| {
+                            sum.add(*r);
+                            sum
+                        });
+                    let gone = (peak - size).to_f64().unwrap();
+                    if removal.cancelled.is_zero()
+                        || removal.filled.to_f64().unwrap() > gone * MAX_FILLED_SHARE
+                    {
+                        continue;
+                    }
+                    self.spoofs.push(Spoof {
+                        side,
+                        price: *price,
+                        start,
+                        end: ts,
+                        peak,
+                        multiple: peak.to_f64().unwrap() / typical,
+                        distance_bps,
+                        filled: removal.filled,
+                        cancelled: removal.cancelled,
+                    });
+                }
+            }
+        }
+        self.spoofs.sort_by_key(|s| std::cmp::Reverse(s.end));
+        self.spoofs.truncate(MAX_SPOOFS);
+        self.scanned_to = Some(until);
+    }
+
+    /// Detections so far, newest first.
+    pub fn spoofs(&self) -> &[Spoof] {
+        &self.spoofs
+    }
+}
+
+/// Counts of resting sizes in log-spaced buckets, an eighth of a doubling
+/// wide, for a running median that never holds every cell.
+#[derive(Default)]
+struct SizeHistogram {
+    buckets: BTreeMap<i32, u64>,
+    count: u64,
+}
+
+impl SizeHistogram {
+    const PER_DOUBLING: f64 = 8.0;
+
+    fn add(&mut self, size: Decimal) {
+        if size.is_zero() {
+            return;
+        }
+        let bucket = (size.to_f64().unwrap().log2() * Self::PER_DOUBLING).floor() as i32;
+        *self.buckets.entry(bucket).or_default() += 1;
+        self.count += 1;
+    }
+
+    fn median(&self) -> Option<f64> {
+        let mut seen = 0;
+        for (&bucket, &n) in &self.buckets {
+            seen += n;
+            if seen * 2 > self.count {
+                return Some(((bucket as f64 + 0.5) / Self::PER_DOUBLING).exp2());
+            }
+        }
+        None
+    }
+}
+
+//MARK: - Panel ---------------------------------------------
+
+#[derive(Properties, PartialEq)]
+pub struct SpoofPanelProps {
+    pub spoofs: Rc<Vec<Spoof>>,
+    pub settings: SpoofSettings,
+    pub on_settings: Callback<SpoofSettings>,
+    /// A detection was clicked.
+    pub on_select: Callback<Spoof>,
+}
+
+/// Detections, newest first, with the detector thresholds.
+#[function_component(SpoofPanel)]
+pub fn spoof_panel(props: &SpoofPanelProps) -> Html {
+    let settings = props.settings;
+    let on_multiple = {
+        let on_settings = props.on_settings.clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            match input.value().parse::<f64>() {
+                Ok(multiple) if multiple > 1.0 => on_settings.emit(SpoofSettings {
+                    multiple,
+                    ..settings
+                }),
+                _ => input.set_value(&settings.multiple.to_string()),
+            }
+        })
+    };
+    let on_max_life = {
+        let on_settings = props.on_settings.clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            match input.value().parse::<f64>() {
+                Ok(secs) if secs > 0.0 => on_settings.emit(SpoofSettings {
+                    max_life: (secs * 1000.0) as Time,
+                    ..settings
+                }),
+                _ => input.set_value(&(settings.max_life as f64 / 1000.0).to_string()),
+            }
+        })
+    };
+    let on_max_distance = {
+        let on_settings = props.on_settings.clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            match input.value().parse::<f64>() {
+                Ok(max_distance_bps) if max_distance_bps > 0.0 => on_settings.emit(SpoofSettings {
+                    max_distance_bps,
+                    ..settings
+                }),
+                _ => input.set_value(&settings.max_distance_bps.to_string()),
+            }
+        })
+    };
+
+    let field =
+        "w-14 px-1 rounded border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-900";
+    html! {
+        <div class="flex flex-col h-full text-xs font-mono">
+            <div class="flex flex-col gap-1 p-2 border-b border-gray-300 dark:border-gray-700">
+                <span class="font-bold">{format!("pulled walls ({})", props.spoofs.len())}</span>
+                <label class="flex gap-1 items-center">
+                    {"size ≥"}
+                    <input type="number" min="1" step="0.5" class={field}
+                        value={settings.multiple.to_string()} onchange={on_multiple}/>
+                    {"× typical"}
+                </label>
+                <label class="flex gap-1 items-center">
+                    {"gone within"}
+                    <input type="number" min="0" step="1" class={field}
+                        value={(settings.max_life as f64 / 1000.0).to_string()} onchange={on_max_life}/>
+                    {"s"}
+                </label>
+                <label class="flex gap-1 items-center">
+                    {"within"}
+                    <input type="number" min="1" step="5" class={field}
+                        value={settings.max_distance_bps.to_string()} onchange={on_max_distance}/>
+                    {"bp of touch"}
+                </label>
+            </div>
+            <ul class="flex-1 overflow-auto">
+                {for props.spoofs.iter().map(|spoof| {
+                    let onclick = {
+                        let (on_select, spoof) = (props.on_select.clone(), spoof.clone());
+                        Callback::from(move |_: MouseEvent| on_select.emit(spoof.clone()))
+                    };
+                    let time = chrono::DateTime::from_timestamp_millis(spoof.start as i64)
+                        .map(|t| t.format("%H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    let (label, color) = match spoof.side {
+                        Side::Bid => ("bid", "text-emerald-500"),
+                        Side::Ask => ("ask", "text-red-500"),
+                    };
+                    html! {
+                        <li class="px-2 py-1 cursor-pointer hover:bg-gray-200 dark:hover:bg-gray-800" {onclick}>
+                            <div class="flex gap-2">
+                                <span>{time}</span>
+                                <span class={color}>{label}</span>
+                                <span>{spoof.price.normalize().to_string()}</span>
+                            </div>
+                            <div class="text-gray-500">
+                                {format!(
+                                    "{} ({:.1}×) for {:.1}s",
+                                    spoof.peak.normalize(),
+                                    spoof.multiple,
+                                    spoof.life() as f64 / 1000.0
+                                )}
+                                {spoof.distance_bps.map(|d| format!(" · {:.0}bp off", d)).unwrap_or_default()}
+                            </div>
+                        </li>
+                    }
+                })}
+            </ul>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALL: i64 = 105;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    /// Bids of 1 on rows 100..110 every second for 10s, with the wall row
+    /// showing `wall` from the given times on.
+    fn heatmap(wall: &[(Time, &str)]) -> HashMap<Decimal, BTreeMap<Time, Depth>> {
+        (100..110)
+            .map(|px| {
+                let cells = (0..10)
+                    .map(|i| {
+                        let ts = i * 1_000;
+                        let bid = match wall.iter().rev().find(|(from, _)| *from <= ts) {
+                            Some((_, size)) if px == WALL => dec(size),
+                            _ => Decimal::ONE,
+                        };
+                        let depth = Depth {
+                            bid,
+                            ask: Decimal::ZERO,
+                        };
+                        (ts, depth)
+                    })
+                    .collect();
+                (Decimal::from(px), cells)
+            })
+            .collect()
+    }
+
+    /// A wall of 20 from 2s, back to 1 at 4s.
+    fn pulled() -> HashMap<Decimal, BTreeMap<Time, Depth>> {
+        heatmap(&[(0, "1"), (2_000, "20"), (4_000, "1")])
+    }
+
+    fn removals(filled: &str, cancelled: &str) -> HashMap<Decimal, BTreeMap<Time, Removal>> {
+        let removal = Removal {
+            filled: dec(filled),
+            cancelled: dec(cancelled),
+        };
+        HashMap::from([(Decimal::from(WALL), BTreeMap::from([(4_000, removal)]))])
+    }
+
+    /// Best bid 109.5, the wall row's middle 105.5 is ~365bp under it.
+    fn samples() -> BTreeMap<Time, BookSample> {
+        let sample = BookSample {
+            bid: (109.5, 1.0),
+            ask: (110.5, 1.0),
+            depth: [(0.0, 0.0); 5],
+        };
+        BTreeMap::from([(0, sample)])
+    }
+
+    fn settings() -> (SpoofSettings, Bins) {
+        let settings = SpoofSettings {
+            max_distance_bps: 500.0,
+            ..SpoofSettings::default()
+        };
+        (settings, Bins::default())
+    }
+
+    fn scan(
+        heatmap: &HashMap<Decimal, BTreeMap<Time, Depth>>,
+        removals: &HashMap<Decimal, BTreeMap<Time, Removal>>,
+        settings: (SpoofSettings, Bins),
+    ) -> Vec<Spoof> {
+        let mut detector = SpoofDetector::default();
+        detector.scan(heatmap, removals, &samples(), 10_000, None, settings);
+        detector.spoofs().to_vec()
+    }
+
+    #[test]
+    fn flags_a_pulled_wall() {
+        let spoofs = scan(&pulled(), &removals("0", "19"), settings());
+        assert_eq!(spoofs.len(), 1);
+        let spoof = &spoofs[0];
+        assert_eq!((spoof.side, spoof.price), (Side::Bid, Decimal::from(WALL)));
+        assert_eq!(
+            (spoof.start, spoof.end, spoof.peak),
+            (2_000, 4_000, dec("20"))
+        );
+        assert!(spoof.multiple > 15.0);
+        let distance = spoof.distance_bps.unwrap();
+        assert!((distance - 365.3).abs() < 0.1, "{}", distance);
+    }
+
+    #[test]
+    fn ignores_levels_under_the_multiple() {
+        let heatmap = heatmap(&[(0, "1"), (2_000, "3"), (4_000, "1")]);
+        assert!(scan(&heatmap, &removals("0", "2"), settings()).is_empty());
+    }
+
+    #[test]
+    fn skips_walls_standing_at_the_origin() {
+        let heatmap = heatmap(&[(0, "20"), (4_000, "1")]);
+        assert!(scan(&heatmap, &removals("0", "19"), settings()).is_empty());
+    }
+
+    #[test]
+    fn skips_walls_living_past_max_life() {
+        let (mut settings, bins) = settings();
+        settings.max_life = 1_000;
+        assert!(scan(&pulled(), &removals("0", "19"), (settings, bins)).is_empty());
+    }
+
+    #[test]
+    fn skips_walls_far_from_the_touch() {
+        let (mut settings, bins) = settings();
+        settings.max_distance_bps = 100.0;
+        assert!(scan(&pulled(), &removals("0", "19"), (settings, bins)).is_empty());
+        // without a sample the distance is unknown and the wall is kept
+        let mut detector = SpoofDetector::default();
+        let (heatmap, removals) = (pulled(), removals("0", "19"));
+        detector.scan(
+            &heatmap,
+            &removals,
+            &BTreeMap::new(),
+            10_000,
+            None,
+            (settings, bins),
+        );
+        assert_eq!(detector.spoofs()[0].distance_bps, None);
+    }
+
+    #[test]
+    fn skips_walls_filled_or_unclassified() {
+        assert!(scan(&pulled(), &removals("10", "9"), settings()).is_empty());
+        assert!(scan(&pulled(), &HashMap::new(), settings()).is_empty());
+        // a fill within MAX_FILLED_SHARE of the drop still counts as pulled
+        assert_eq!(scan(&pulled(), &removals("1", "18"), settings()).len(), 1);
+    }
+
+    #[test]
+    fn scans_incrementally_and_restarts_on_older_changes() {
+        let (heatmap, removals, samples) = (pulled(), removals("0", "19"), samples());
+        let mut detector = SpoofDetector::default();
+        // the wall is still standing at 3s
+        detector.scan(&heatmap, &removals, &samples, 3_000, None, settings());
+        assert!(detector.spoofs().is_empty());
+        detector.scan(
+            &heatmap,
+            &removals,
+            &samples,
+            10_000,
+            Some(9_000),
+            settings(),
+        );
+        assert_eq!(detector.spoofs().len(), 1);
+        // nothing new to walk
+        detector.scan(&heatmap, &removals, &samples, 10_000, None, settings());
+        assert_eq!(detector.spoofs().len(), 1);
+        // a change behind the scan walks it all again, without duplicates
+        detector.scan(&heatmap, &removals, &samples, 10_000, Some(0), settings());
+        assert_eq!(detector.spoofs().len(), 1);
+        // and forgets what the new history no longer shows
+        let flat = self::heatmap(&[]);
+        detector.scan(&flat, &removals, &samples, 10_000, Some(0), settings());
+        assert!(detector.spoofs().is_empty());
+    }
+
+    #[test]
+    fn size_histogram_median_is_within_a_bucket() {
+        let mut sizes = SizeHistogram::default();
+        assert_eq!(sizes.median(), None);
+        for size in ["1", "2", "3", "0", "100"] {
+            sizes.add(dec(size));
+        }
+        // zeroes are not resting sizes, the median of 1, 2, 3, 100 is 3
+        let median = sizes.median().unwrap();
+        assert!((median / 3.0 - 1.0).abs() < 0.1, "{}", median);
+    }
+}
@@ -31,7 +31,7 @@ pub fn bn() -> Html {
             >
             <svg
                 id="theme-toggle-dark-icon"
-                class={classes!("w-5","h-5", is_dark.then(|| Some("hidden")))}
+                class={classes!("w-5","h-5", is_dark.then_some(Some("hidden")))}
                 fill="currentColor"
                 viewBox="0 0 20 20"
                 xmlns="http://www.w3.org/2000/svg"
@@ -42,7 +42,7 @@ pub fn bn() -> Html {
             </svg>
             <svg
                 id="theme-toggle-light-icon"
-                class={classes!("w-5","h-5", (!*is_dark).then(|| Some("hidden")))}
+                class={classes!("w-5","h-5", (!*is_dark).then_some(Some("hidden")))}
                 fill="currentColor"
                 viewBox="0 0 20 20"
                 xmlns="http://www.w3.org/2000/svg"
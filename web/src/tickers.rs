@@ -4,7 +4,9 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use web_sys::console;
 use yew::prelude::*;
-use yew_hooks::prelude::*;
+
+use crate::connection::{Socket, SocketHandlers, SocketStatus, StatusBadge};
+use crate::data::{DataAction, DataContext};
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq)]
 pub struct Tick {
@@ -17,6 +19,7 @@ pub struct Tick {
 #[derive(Clone, Debug, PartialEq)]
 pub struct TickerState {
     pub tickers: Vec<Tick>,
+    pub status: SocketStatus,
 }
 
 pub enum TickerActions {
@@ -27,16 +30,25 @@ impl TickerState {
     pub fn new() -> Self {
         Self {
             tickers: Vec::<Tick>::default(),
+            status: SocketStatus::default(),
         }
     }
 }
+impl Default for TickerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Reducible for TickerState {
     type Action = TickerActions;
 
     fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
         match action {
-            TickerActions::SetTickers(tickers) => { TickerState { tickers } }.into(),
+            TickerActions::SetTickers(tickers) => {
+                let status = self.status.clone();
+                TickerState { tickers, status }.into()
+            }
         }
     }
 }
@@ -53,49 +65,51 @@ pub struct TickerProviderProps {
 pub fn ticker_provider(props: &TickerProviderProps) -> Html {
     let state = use_reducer(TickerState::new);
 
-    let ws = use_websocket("wss://stream.binance.com:9443/stream?streams=!ticker@arr".to_string());
     {
         let state = state.clone();
-        let ws = ws.clone();
-        use_effect_update_with_deps(
-            move |message| {
-                if let Some(msg) = &**message {
-                    let data_str: String = msg.into();
-                    let msg: Value = serde_json::from_str(&data_str).unwrap();
-                    // console::log_1(&format!("🦜 [ws] tick {:?}", msg).into());
-
-                    match msg["stream"].as_str() {
-                        Some(stream) if stream.contains("!ticker@arr") => {
-                            if let Some(v) = msg["data"].as_array() {
-                                let mut tickers = v
-                                    .iter()
-                                    .map(|t| Tick {
-                                        symbol: t["s"].as_str().unwrap().to_string(),
-                                        best_bid: t["b"].as_str().unwrap().parse().unwrap(),
-                                        best_ask: t["a"].as_str().unwrap().parse().unwrap(),
-                                        volume: t["q"].as_str().unwrap().parse().unwrap(),
-                                    })
-                                    .collect::<Vec<Tick>>();
-                                tickers.sort_by(|a, b| b.volume.partial_cmp(&a.volume).unwrap());
-                                // Taking the top 25 tickers
-                                let top_tickers =
-                                    tickers.iter().take(25).cloned().collect::<Vec<Tick>>();
-                                console::log_1(
-                                    &format!("🦜 [ws] tickers {:?}", top_tickers).into(),
-                                );
-
-                                state.dispatch(TickerActions::SetTickers(top_tickers));
-                            }
-                        }
-                        _ => {
-                            console::log_1(&format!("🚫 [ws] stream {:?}", msg).into());
+        use_effect_with((), move |_| {
+            let status = state.status.clone();
+            let on_message = move |data_str: String| {
+                let msg: Value = serde_json::from_str(&data_str).unwrap();
+                // console::log_1(&format!("🦜 [ws] tick {:?}", msg).into());
+
+                match msg["stream"].as_str() {
+                    Some(stream) if stream.contains("!ticker@arr") => {
+                        if let Some(v) = msg["data"].as_array() {
+                            let mut tickers = v
+                                .iter()
+                                .map(|t| Tick {
+                                    symbol: t["s"].as_str().unwrap().to_string(),
+                                    best_bid: t["b"].as_str().unwrap().parse().unwrap(),
+                                    best_ask: t["a"].as_str().unwrap().parse().unwrap(),
+                                    volume: t["q"].as_str().unwrap().parse().unwrap(),
+                                })
+                                .collect::<Vec<Tick>>();
+                            tickers.sort_by(|a, b| b.volume.partial_cmp(&a.volume).unwrap());
+                            // Taking the top 25 tickers
+                            let top_tickers =
+                                tickers.iter().take(25).cloned().collect::<Vec<Tick>>();
+                            console::log_1(&format!("🦜 [ws] tickers {:?}", top_tickers).into());
+
+                            state.dispatch(TickerActions::SetTickers(top_tickers));
                         }
                     }
+                    _ => {
+                        console::log_1(&format!("🚫 [ws] stream {:?}", msg).into());
+                    }
                 }
-                || ()
-            },
-            ws.message,
-        );
+            };
+            let socket = Socket::open(
+                "wss://stream.binance.com:9443/stream?streams=!ticker@arr".to_string(),
+                status,
+                SocketHandlers {
+                    on_message: Box::new(on_message),
+                    on_open: Box::new(|_| ()),
+                    on_drop: Box::new(|| ()),
+                },
+            );
+            move || socket.close()
+        });
     }
 
     html! {
@@ -105,13 +119,31 @@ pub fn ticker_provider(props: &TickerProviderProps) -> Html {
     }
 }
 
+#[function_component(TickerStatus)]
+pub fn ticker_status() -> Html {
+    let context = use_context::<TickerContext>().unwrap();
+    html! {
+        <StatusBadge label="tickers" status={context.status.clone()}/>
+    }
+}
+
 #[function_component(TickerList)]
 pub fn ticker_list() -> Html {
     let context = use_context::<TickerContext>().unwrap();
+    let data_ctx = use_context::<DataContext>().unwrap();
     let tickers = &*context.tickers;
     let tickers = tickers.iter().map(|t| {
+        let selected = data_ctx.symbol.as_deref() == Some(t.symbol.as_str());
+        let onclick = {
+            let data_ref = data_ctx.dispatcher();
+            let symbol = t.symbol.clone();
+            Callback::from(move |_| data_ref.dispatch(DataAction::SetSymbol(symbol.clone())))
+        };
         html! {
-            <div class="flex flex-row justify-between items-center p-2 border-b border-gray-200 dark:border-gray-800">
+            <div
+                class={classes!("flex", "flex-row", "justify-between", "items-center", "p-2", "border-b", "border-gray-200", "dark:border-gray-800", "cursor-pointer", "hover:bg-gray-200", "dark:hover:bg-gray-800", selected.then_some("bg-gray-200 dark:bg-gray-800"))}
+                {onclick}
+            >
                 <div class="flex flex-col">
                     <span class="text-sm font-bold">{&t.symbol}</span>
                     <span class="text-xs text-gray-500 dark:text-gray-400">{format!("{} / {}", t.best_bid, t.best_ask)}</span>